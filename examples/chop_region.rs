@@ -0,0 +1,30 @@
+//! Chop the records overlapping a region of an indexed BAM, as an embedding program would.
+//!
+//! Usage: cargo run --example chop_region -- <in.bam> <region> <chunk size>
+//!
+//! Prints the name, contig, 1-based position and CIGAR of each chunk.
+
+use std::rc::Rc;
+use rust_htslib::bam::{self, Read};
+use chop_reads::alignment_chopper::{AlignmentChopper, ClipPolicy};
+
+fn main() {
+    let args = std::env::args().collect::<Vec<_>>();
+    let [_, path, region, chunk_size] = &args[..] else {
+        eprintln!("Usage: chop_region <in.bam> <region> <chunk size>");
+        std::process::exit(1);
+    };
+
+    let mut reader = bam::IndexedReader::from_path(path).expect("Unable to open indexed input");
+    let header = Rc::new(reader.header().clone());
+    reader.fetch(region.as_str()).expect("Unable to fetch region");
+
+    let mut chopper = AlignmentChopper::new(chunk_size.parse().expect("Chunk size must be a number"), 0, ClipPolicy::Attach, None);
+    chopper.set_header(Some(Rc::clone(&header)));
+    for r in reader.records() {
+        let rec = r.expect("Failed to parse record");
+        for chunk in chopper.chop_read(&rec) {
+            println!("{}\t{}\t{}\t{}", String::from_utf8_lossy(chunk.qname()), String::from_utf8_lossy(header.tid2name(chunk.tid() as u32)), chunk.pos() + 1, chunk.cigar());
+        }
+    }
+}
@@ -1,34 +1,365 @@
 use std::cmp::min;
-use rust_htslib::bam::{Record};
+use std::collections::HashMap;
+use std::ops::Range;
+use std::rc::Rc;
+use std::str::FromStr;
+use clap::ValueEnum;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rust_htslib::bam::{HeaderView, Record};
 use rust_htslib::bam::record::{CigarString, Cigar, Aux};
+use crate::region::TargetRegion;
+use crate::strategy::ChopStrategy;
+use crate::cigar_utils::{aligned_len, consume_cigar, consume_cigar_ref, consumes_query, consumes_ref, extend_cigar, long_cigar, query_len, ref_len, reg2bin, clip_cigar_to_query_len, split_cigar_at, split_cigar_at_aligned, split_cigar_at_query, split_cigar_at_ref, trim_cigar_query, SplitCigarBuf};
+
+/// How to handle records whose CIGAR implies a different query length than their SEQ
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum CigarSeqMismatchPolicy {
+    /// Abort on the first mismatched record
+    Error,
+    /// Trim (or softclip-extend) the CIGAR so it matches SEQ
+    ClipCigar,
+    /// Drop mismatched records
+    Skip,
+}
+
+// Tags holding one value per base (or describing the full alignment), which would be wrong on a chunk
+const PER_BASE_TAGS: [&[u8]; 13] = [b"MM", b"ML", b"Mm", b"Ml", b"mv", b"OQ", b"E2", b"U2", b"CQ", b"CS", b"BQ", b"MD", b"NM"];
+
+/// Integer value of an aux field, if it holds one
+fn aux_int(value: &Aux) -> Option<i64> {
+    match *value {
+        Aux::I8(v) => Some(v as i64),
+        Aux::U8(v) => Some(v as i64),
+        Aux::I16(v) => Some(v as i64),
+        Aux::U16(v) => Some(v as i64),
+        Aux::I32(v) => Some(v as i64),
+        Aux::U32(v) => Some(v as i64),
+        _ => None,
+    }
+}
+
+/// Why a record was dropped whole instead of being chopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RejectReason {
+    /// CIGAR and SEQ lengths disagree under the skip policy
+    CigarSeqMismatch,
+    /// QUAL is shorter than SEQ
+    QualSeqMismatch,
+    /// Unaligned record without any bases
+    EmptyRecord,
+}
+
+impl RejectReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RejectReason::CigarSeqMismatch => "cigar-seq-mismatch",
+            RejectReason::QualSeqMismatch => "qual-seq-mismatch",
+            RejectReason::EmptyRecord => "empty-record",
+        }
+    }
+}
+
+/// What to do with the final piece of a read when it's shorter than the min length
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum RemainderPolicy {
+    /// Drop it
+    Drop,
+    /// Append it to the chunk before, unless that was dropped itself
+    Merge,
+}
+
+/// How chunk CIGARs represent the bases of the parent outside the chunk
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ClipMode {
+    /// Leave them out
+    None,
+    /// As leading and trailing hard clips, so the parent's length can be worked out from any chunk
+    Hard,
+}
+
+/// What becomes of the soft clipped bases at the edges of a record
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ClipPolicy {
+    /// Leave them, and any hard clips, out of the chunks
+    Drop,
+    /// Add them to the first and last chunks without counting them towards their size
+    Attach,
+    /// Emit each as an unmapped record of its own, numbered by where it lies among the chunks, leaving hard clips out as Drop does
+    Separate,
+}
+
+/// What becomes of deletions split where chunks end in reference coordinates, e.g. at tile edges
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum DeletionPolicy {
+    /// Keep the pieces as trailing and leading D of the chunks either side, or chunks of nothing else
+    Split,
+    /// Leave the pieces at either end of a chunk out, and chunks of nothing else with them
+    Drop,
+}
+
+/// Which bases count towards the chunk size
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ChunkBy {
+    /// Query bases (M/I/S/=/X), so each chunk holds the same number of read bases
+    Query,
+    /// Reference bases (M/D/N/=/X), so each chunk spans the same number of reference bp
+    Ref,
+    /// Reference bases, with boundaries at multiples of the chunk size from the start of the contig
+    /// so chunks of different reads covering a locus share them
+    Tile,
+    /// Query bases aligned to the reference (M/=/X), so soft clips and insertions don't count and
+    /// each chunk aligns the same number of read bases
+    Aligned,
+}
+
+impl ChunkBy {
+    // Consume as much of `c` as fits in a chunk with CIGAR `chunk`, already holding `query_used` query and `ref_used` reference bases
+    fn consume(&self, c: &Cigar, chunk_size: u32, query_used: u32, ref_used: i64, chunk: &CigarString) -> SplitCigarBuf {
+        match self {
+            ChunkBy::Query => consume_cigar(c, chunk_size - query_used),
+            ChunkBy::Ref | ChunkBy::Tile => consume_cigar_ref(c, chunk_size as i64 - ref_used),
+            // Ops that align nothing are taken whole
+            ChunkBy::Aligned if self.counts(c) => consume_cigar(c, chunk_size - aligned_len(chunk)),
+            ChunkBy::Aligned => consume_cigar(c, u32::MAX),
+        }
+    }
+
+    // Size of a chunk with CIGAR `chunk`, holding `query_used` query and `ref_used` reference bases
+    fn size(&self, query_used: u32, ref_used: i64, chunk: &CigarString) -> u32 {
+        match self {
+            ChunkBy::Query => query_used,
+            ChunkBy::Ref | ChunkBy::Tile => ref_used as u32,
+            ChunkBy::Aligned => aligned_len(chunk),
+        }
+    }
+
+    // Whether `c` counts towards the chunk size
+    fn counts(&self, c: &Cigar) -> bool {
+        match self {
+            ChunkBy::Query => consumes_query(c),
+            ChunkBy::Ref | ChunkBy::Tile => consumes_ref(c),
+            ChunkBy::Aligned => consumes_query(c) && consumes_ref(c),
+        }
+    }
+}
+
+/// Where the tiles of ChunkBy::Tile start counting from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinAnchor {
+    /// The start of the contig
+    Zero,
+    /// The read's alignment start, so its first chunk is a whole tile
+    Pos,
+    /// This 0-based reference position
+    Offset(i64),
+}
+
+impl FromStr for BinAnchor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "zero" => Ok(BinAnchor::Zero),
+            "pos" => Ok(BinAnchor::Pos),
+            _ => match s.strip_prefix("offset:") {
+                Some(offset) => offset.parse().map(BinAnchor::Offset).map_err(|_| format!("Invalid bin offset: {}", offset)),
+                None => Err(format!("Unknown bin anchor: {} (expected zero, pos or offset:N)", s)),
+            }
+        }
+    }
+}
+
+/// How to mark parent records written alongside their chunks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ParentMark {
+    /// Leave the parent untouched
+    None,
+    /// Tag the parent with pa:i:1
+    Tag,
+    /// Flag the parent as a secondary alignment, so tools counting primary records skip it
+    Secondary,
+}
+
+impl ParentMark {
+    pub fn apply(&self, parent: &mut Record) {
+        match self {
+            ParentMark::None => {}
+            ParentMark::Tag => parent.push_aux(b"pa", Aux::I32(1)).unwrap_or_else(|_| panic!("Unable to push pa tag at: {} - {}", parent.tid(), parent.pos())),
+            ParentMark::Secondary => parent.set_flags(parent.flags() | 0x100),
+        }
+    }
+}
+
+/// How the MAPQ of each chunk is derived from its parent record
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapqPolicy {
+    /// Keep the parent's MAPQ
+    Inherit,
+    /// Keep the parent's MAPQ but never exceed this value
+    Cap(u8),
+    /// Set MAPQ to zero
+    Zero,
+    /// Scale the parent's MAPQ down for chunks shorter than the chunk size
+    LengthScaled,
+}
+
+impl FromStr for MapqPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "inherit" => Ok(MapqPolicy::Inherit),
+            "zero" => Ok(MapqPolicy::Zero),
+            "length-scaled" => Ok(MapqPolicy::LengthScaled),
+            _ => match s.strip_prefix("cap:") {
+                Some(cap) => cap.parse().map(MapqPolicy::Cap).map_err(|_| format!("Invalid MAPQ cap: {}", cap)),
+                None => Err(format!("Unknown MAPQ policy: {} (expected inherit, cap:N, zero or length-scaled)", s)),
+            }
+        }
+    }
+}
+
+impl MapqPolicy {
+    fn apply(&self, mapq: u8, chunk_len: u32, chunk_size: u32) -> u8 {
+        // 255 means MAPQ is unavailable, so there's nothing to adjust
+        if mapq == 255 && *self != MapqPolicy::Zero {
+            return mapq;
+        }
+        match self {
+            MapqPolicy::Inherit => mapq,
+            MapqPolicy::Cap(cap) => min(mapq, *cap),
+            MapqPolicy::Zero => 0,
+            MapqPolicy::LengthScaled => (mapq as u64 * min(chunk_len, chunk_size) as u64 / chunk_size.max(1) as u64) as u8,
+        }
+    }
+}
+
+// Bases to trim from the start of `qual` by BWA's running sum, maximizing the total of `threshold` minus each base's quality
+fn qual_trim_len<'a>(qual: impl Iterator<Item = &'a u8>, threshold: u8) -> usize {
+    let (mut sum, mut best, mut trim) = (0, 0, 0);
+    for (i, &q) in qual.enumerate() {
+        sum += threshold as i64 - q as i64;
+        if sum < 0 {
+            break;
+        }
+        if sum > best {
+            (best, trim) = (sum, i + 1);
+        }
+    }
+    trim
+}
 
 #[derive(Debug, Clone)]
 pub struct AlignmentChopper {
     chunk_size: u32,
+    overlap: u32,
+    num_chunks: Option<u32>,
+    balance: bool,
+    chunk_trim: u32,
+    max_ref_span: Option<i64>,
+    deletion_policy: DeletionPolicy,
+    chunk_by: ChunkBy,
+    bin_anchor: BinAnchor,
     min_length: u32,
-    skip_clipped_bases: bool,
+    remainder: RemainderPolicy,
+    clip_mode: ClipMode,
+    clip_policy: ClipPolicy,
     read_group: Option<String>,
+    position_tag: bool,
+    signal_samples_per_base: Option<f64>,
+    cigar_seq_mismatch: CigarSeqMismatchPolicy,
+    skipped_records: u64,
+    malformed_records: u64,
+    empty_records: u64,
+    last_rejection: Option<RejectReason>,
+    passthrough_below: Option<u32>,
+    passed_through: bool,
+    max_chunks: Option<usize>,
+    min_mean_qual: Option<f64>,
+    qual_trim: Option<u8>,
+    low_quality_chunks: u64,
+    dropout: Option<(f64, StdRng)>,
+    dropped_chunks: u64,
+    mapq_policy: MapqPolicy,
+    keep_tags: bool,
+    header: Option<Rc<HeaderView>>,
+    strategies: Vec<Box<dyn ChopStrategy>>,
+    avoid_intervals: HashMap<i32, Vec<(i64, i64)>>,
+    snap_positions: HashMap<i32, Vec<i64>>,
+    snap_tolerance: i64,
+    stripped_tag_chunks: u64,
+    chunk_specs: Vec<ChunkSpec>,
     rec_pieces_buffer: Vec<Record>,
     record_slice_meta_buffer: RecordSliceMetaBuffer,
 }
 
-#[derive(Debug)]
-struct SplitCigarBuf {
-    left_c: Cigar,
-    right_c: Option<Cigar>,
-    query_offset: u32,
+// Where one chunk lies within its parent record
+#[derive(Debug, Clone)]
+struct ChunkSpec {
+    index: usize,
+    // Parent query and reference offsets the chunk was cut at, before trimming or attaching clips, which number it
+    cut_at: (usize, i64),
+    // Reference position where the chunk meets the one before it, unless cut from it at a skipped op or first
+    joined_at: Option<i64>,
+    query_start: usize,
+    query_end: usize,
+    query_len: u32,
     ref_offset: i64,
+    cigar: CigarString,
+    // Clipped bases emitted unmapped by ClipPolicy::Separate
+    unaligned: bool,
 }
 
-impl SplitCigarBuf {
-    fn new(left_c: Cigar, right_c: Option<Cigar>, query_offset: u32, ref_offset: i64) -> Self {
-        Self {
-            left_c,
-            right_c,
-            query_offset,
-            ref_offset
+/// A chunk of a parent record that borrows from it instead of copying SEQ and QUAL
+#[derive(Debug, Clone)]
+pub struct ChunkView<'a> {
+    parent: &'a Record,
+    spec: ChunkSpec,
+}
+
+impl<'a> ChunkView<'a> {
+    /// Position of this chunk within the parent, as used in its name
+    pub fn index(&self) -> usize {
+        self.spec.index
+    }
+
+    pub fn qname(&self) -> Vec<u8> {
+        [self.parent.qname(), b"-", self.spec.index.to_string().as_bytes()].concat()
+    }
+
+    /// CIGAR of the chunk, or None if it or the parent is unmapped
+    pub fn cigar(&self) -> Option<&CigarString> {
+        (!self.parent.is_unmapped() && !self.spec.unaligned).then_some(&self.spec.cigar)
+    }
+
+    pub fn tid(&self) -> i32 {
+        self.parent.tid()
+    }
+
+    /// Leftmost reference position, which for unmapped chunks and parents is the parent's
+    pub fn pos(&self) -> i64 {
+        if self.parent.is_unmapped() || self.spec.unaligned {
+            self.parent.pos()
+        } else {
+            self.parent.pos() + self.spec.ref_offset
         }
     }
+
+    /// Range of parent query positions covered by this chunk
+    pub fn query_range(&self) -> Range<usize> {
+        self.spec.query_start..self.spec.query_end
+    }
+
+    /// Decode the chunk's bases from the parent's packed SEQ
+    pub fn seq(&self) -> Vec<u8> {
+        let seq = self.parent.seq();
+        self.query_range().map(|i| seq[i]).collect()
+    }
+
+    pub fn qual(&self) -> &'a [u8] {
+        &self.parent.qual()[self.query_range()]
+    }
 }
 
 // A struct to hold metadata about current record slicing process
@@ -36,7 +367,22 @@ impl SplitCigarBuf {
 struct RecordSliceMetaBuffer {
     global_ref_offset: i64,
     global_query_offset: usize,
+    chunk_count: usize,
+    chunk_starts: Vec<(usize, i64)>,
+    pieces: Range<usize>,
+    ref_end: i64,
+    full_cigar: CigarString,
+    reached_end: bool,
+    even_sizes: Option<(u32, usize)>,
+    drawn_size: Option<u32>,
+    breakpoints: Vec<i64>,
+    boundaries: Option<Vec<i64>>,
+    query_cuts: Vec<usize>,
     cigar_string: CigarString,
+    junctions: Vec<i64>,
+    open_junction: Option<i64>,
+    leading_clip: Option<Range<usize>>,
+    trailing_clip: Option<Range<usize>>,
 }
 
 impl RecordSliceMetaBuffer {
@@ -44,291 +390,1987 @@ impl RecordSliceMetaBuffer {
         Self {
             global_ref_offset: 0,
             global_query_offset: 0,
+            chunk_count: 0,
+            chunk_starts: Vec::new(),
+            pieces: 0..0,
+            ref_end: 0,
+            full_cigar: CigarString(Vec::new()),
+            reached_end: false,
+            even_sizes: None,
+            drawn_size: None,
+            breakpoints: Vec::new(),
+            boundaries: None,
+            query_cuts: Vec::new(),
             cigar_string: CigarString(Vec::new()),
+            junctions: Vec::new(),
+            open_junction: None,
+            leading_clip: None,
+            trailing_clip: None,
         }
     }
 
     fn reset(&mut self) {
         self.global_ref_offset = 0;
         self.global_query_offset = 0;
+        self.chunk_count = 0;
+        self.chunk_starts.clear();
+        self.pieces = 0..0;
+        self.ref_end = 0;
+        self.full_cigar.clear();
+        self.reached_end = false;
+        self.even_sizes = None;
+        self.drawn_size = None;
+        self.breakpoints.clear();
+        self.boundaries = None;
+        self.query_cuts.clear();
         self.cigar_string.clear();
+        self.junctions.clear();
+        self.open_junction = None;
+        self.leading_clip = None;
+        self.trailing_clip = None;
     }
 }
 
 impl AlignmentChopper {
-    pub fn new(chunk_size: u32, min_length: u32, skip_clipped_bases: bool, read_group: Option<String>) -> Self {
+    pub fn new(chunk_size: u32, min_length: u32, clip_policy: ClipPolicy, read_group: Option<String>) -> Self {
         Self {
             chunk_size,
+            overlap: 0,
+            num_chunks: None,
+            balance: false,
+            chunk_trim: 0,
+            max_ref_span: None,
+            deletion_policy: DeletionPolicy::Split,
+            chunk_by: ChunkBy::Query,
+            bin_anchor: BinAnchor::Zero,
             min_length,
-            skip_clipped_bases,
+            remainder: RemainderPolicy::Drop,
+            clip_mode: ClipMode::None,
+            clip_policy,
             read_group,
+            position_tag: false,
+            signal_samples_per_base: None,
+            cigar_seq_mismatch: CigarSeqMismatchPolicy::Error,
+            skipped_records: 0,
+            malformed_records: 0,
+            empty_records: 0,
+            last_rejection: None,
+            passthrough_below: None,
+            passed_through: false,
+            max_chunks: None,
+            min_mean_qual: None,
+            qual_trim: None,
+            low_quality_chunks: 0,
+            dropout: None,
+            dropped_chunks: 0,
+            mapq_policy: MapqPolicy::Inherit,
+            keep_tags: false,
+            header: None,
+            strategies: Vec::new(),
+            avoid_intervals: HashMap::new(),
+            snap_positions: HashMap::new(),
+            snap_tolerance: 0,
+            stripped_tag_chunks: 0,
+            chunk_specs: Vec::new(),
             rec_pieces_buffer: Vec::new(),
             record_slice_meta_buffer: RecordSliceMetaBuffer::new()
         }
     }
 
-    fn reset(&mut self) {
-        // Reset internal buffers for new Record
-        self.rec_pieces_buffer.clear();
-        self.record_slice_meta_buffer.reset();
+    /// Set which bases count towards the chunk size and min length
+    pub fn set_chunk_by(&mut self, chunk_by: ChunkBy) {
+        self.chunk_by = chunk_by;
     }
 
-    fn add_chunk_record(&mut self, original_rec: &Record, local_query_consumed: usize) {
-        let mut new_rec = Record::default();
+    /// Set where the tiles of ChunkBy::Tile are counted from
+    pub fn set_bin_anchor(&mut self, bin_anchor: BinAnchor) {
+        self.bin_anchor = bin_anchor;
+    }
 
-        // Get seq and qual slices
-        let query_offset = self.record_slice_meta_buffer.global_query_offset;
-        let chunk_num = self.rec_pieces_buffer.len();
-        let slice_end = min(original_rec.seq_len(), query_offset + local_query_consumed);
+    /// Have consecutive chunks share `overlap` bases, in the units of chunk_by, which must be less than the chunk size.
+    ///
+    /// Chunks then start every chunk size minus `overlap` bases, each running on for the chunk size
+    /// across any breakpoints. Chunks at the end of a read that would lie within the one before are left out.
+    pub fn set_overlap(&mut self, overlap: u32) {
+        self.overlap = overlap;
+    }
 
-        let new_seq = &original_rec.seq().as_bytes()[query_offset..slice_end];
-        let new_qual = &original_rec.qual()[query_offset..slice_end];
+    /// Start a chunk every `step` bases, from 1 up to the chunk size, as a window sliding along each read
+    pub fn set_step(&mut self, step: u32) {
+        self.overlap = self.chunk_size.saturating_sub(step);
+    }
 
-        // Update name for chunk
-        let new_qname = &[original_rec.qname(), b"-", chunk_num.to_string().as_bytes()].concat();
+    /// Split each read into this many chunks of near-equal size instead of by the chunk size, or fewer for reads too short.
+    ///
+    /// Sizes are in the units of chunk_by and differ by at most one, with the longer chunks first.
+    pub fn set_num_chunks(&mut self, num_chunks: Option<u32>) {
+        self.num_chunks = num_chunks;
+    }
 
-        // These are changed based on the particular slice
-        new_rec.set(new_qname, Some(&self.record_slice_meta_buffer.cigar_string), new_seq, new_qual);
-        new_rec.set_pos(original_rec.pos() + self.record_slice_meta_buffer.global_ref_offset);
+    /// Toggle splitting each read into chunks of near-equal size as close to the chunk size as possible,
+    /// rather than chunks of the chunk size and a shorter remainder, e.g. 251/251/251/250 for 1003 bases by 250
+    pub fn set_balance(&mut self, balance: bool) {
+        self.balance = balance;
+    }
 
-        // Following are unchanged
-        new_rec.set_flags(original_rec.flags());
-        new_rec.set_tid(original_rec.tid());
-        new_rec.set_mapq(original_rec.mapq());
-        new_rec.set_mtid(original_rec.mtid());
-        new_rec.set_mpos(original_rec.mpos());
-        new_rec.set_insert_size(original_rec.insert_size());
+    /// Trim this many query bases from both ends of every chunk, dropping chunks no longer than twice it
+    pub fn set_chunk_trim(&mut self, chunk_trim: u32) {
+        self.chunk_trim = chunk_trim;
+    }
 
-        // All aux data other than RG is lost
-        if let Some(rg) = &self.read_group {
-            if let Ok(_a) = new_rec.aux(b"RG") {
-                new_rec.remove_aux(b"RG").expect(&format!("Could not remove RG from: {} - {}", &new_rec.tid(), &new_rec.pos()));
-            }
-            new_rec.push_aux(b"RG", Aux::String(rg)).expect(&format!("Unable to push RG string at: {} - {}", &new_rec.tid(), &new_rec.pos()));
-        }
+    /// Also end chunks once they span this many reference bases, or don't if None.
+    ///
+    /// Deletions are split where a chunk ends, and the pieces left at either end of a chunk are
+    /// dropped so no chunk starts or ends with D, nor consists of nothing else.
+    pub fn set_max_ref_span(&mut self, max_ref_span: Option<u32>) {
+        self.max_ref_span = max_ref_span.map(i64::from);
+    }
 
-        self.rec_pieces_buffer.push(new_rec);
+    /// Set what becomes of the deletions at the ends of chunks, which --max-ref-span always drops
+    pub fn set_deletion_policy(&mut self, deletion_policy: DeletionPolicy) {
+        self.deletion_policy = deletion_policy;
     }
 
-    fn consume_cigar(c: &Cigar, amount: u32) -> SplitCigarBuf {
-        match c {
-            Cigar::Match(x) => {
-                let (left_c, right_c, consumed) = if amount < *x {
-                    (Cigar::Match(amount), Some(Cigar::Match(*x - amount)), amount)
-                } else {
-                    (Cigar::Match(*x), None, *x)
-                };
-                SplitCigarBuf::new(left_c, right_c, consumed, consumed as i64)
-            },
-            Cigar::Ins(x) => {
-                let (left_c, right_c, consumed) = if amount < *x {
-                    (Cigar::Ins(amount), Some(Cigar::Ins(*x - amount)), amount)
-                } else {
-                    (Cigar::Ins(*x), None, *x)
-                };
-                SplitCigarBuf::new(left_c, right_c, consumed, 0i64)
-            },
-            Cigar::Del(x) => {
-                SplitCigarBuf::new(Cigar::Del(*x), None, 0, *x as i64)
-            },
-            Cigar::RefSkip(x) => {
-                SplitCigarBuf::new(Cigar::RefSkip(*x), None, 0, *x as i64)
-            },
-            Cigar::SoftClip(x) => {
-                let (left_c, right_c, consumed) = if amount < *x {
-                    (Cigar::SoftClip(amount), Some(Cigar::SoftClip(*x - amount)), amount)
-                } else {
-                    (Cigar::SoftClip(*x), None, *x)
-                };
-                SplitCigarBuf::new(left_c, right_c, consumed, 0i64)
-            },
-            Cigar::HardClip(x) => {
-                SplitCigarBuf::new(Cigar::HardClip(*x), None, 0, 0i64)
-            },
-            Cigar::Pad(x) => {
-                SplitCigarBuf::new(Cigar::Pad(*x), None, 0, 0i64)
-            },
-            Cigar::Equal(x) => {
-                let (left_c, right_c, consumed) = if amount < *x {
-                    (Cigar::Equal(amount), Some(Cigar::Equal(*x - amount)), amount)
-                } else {
-                    (Cigar::Equal(*x), None, *x)
-                };
-                SplitCigarBuf::new(left_c, right_c, consumed, consumed as i64)
-            }
-            Cigar::Diff(x) => {
-                let (left_c, right_c, consumed) = if amount < *x {
-                    (Cigar::Diff(amount), Some(Cigar::Diff(*x - amount)), amount)
-                } else {
-                    (Cigar::Diff(*x), None, *x)
-                };
-                SplitCigarBuf::new(left_c, right_c, consumed, consumed as i64)
-            }
+    /// Set what happens to final pieces shorter than the min length
+    pub fn set_remainder(&mut self, remainder: RemainderPolicy) {
+        self.remainder = remainder;
+    }
+
+    /// Set how chunk CIGARs represent the bases of their parent outside them
+    pub fn set_clip_mode(&mut self, clip_mode: ClipMode) {
+        self.clip_mode = clip_mode;
+    }
+
+    /// Also split records wherever `strategy` asks, e.g. a BreakpointList or RefSkipSplit.
+    ///
+    /// Strategies that size chunks each adjust the size left by those added before them, so e.g. a
+    /// Jitter goes after a RandomSize whose sizes it shifts.
+    pub fn add_strategy(&mut self, strategy: Box<dyn ChopStrategy>) {
+        self.strategies.push(strategy);
+    }
+
+    /// Keep chunk boundaries out of these intervals, moving any that fall inside one to its nearer end.
+    ///
+    /// The intervals must be sorted and not overlap, as resolve_regions leaves them. Breakpoints are
+    /// never moved, and boundaries that can't move without leaving the read are dropped.
+    pub fn set_avoid_intervals(&mut self, intervals: &[TargetRegion]) {
+        self.avoid_intervals.clear();
+        for interval in intervals {
+            self.avoid_intervals.entry(interval.tid).or_default().push((interval.start, interval.end));
         }
     }
 
-    pub fn chop_read(&mut self, rec: &Record) -> &Vec<Record> {
-        self.reset();  // Clear internal buffers
+    /// Move chunk boundaries to the nearest of these reference positions, by tid, no more than `tolerance` away.
+    ///
+    /// The chunk after a moved boundary starts on the position. Boundaries are snapped before being
+    /// moved out of avoided intervals, breakpoints are never moved, and positions outside the read are ignored.
+    pub fn set_snap_positions(&mut self, positions: HashMap<i32, Vec<i64>>, tolerance: u32) {
+        self.snap_positions = positions;
+        self.snap_positions.values_mut().for_each(|positions| positions.sort_unstable());
+        self.snap_tolerance = tolerance as i64;
+    }
 
-        let mut local_ref_consumed = 0;
-        let mut local_query_consumed = 0;
+    /// Toggle tagging each chunk with its fractional start position in the parent read as sequenced (fp:f)
+    pub fn set_position_tag(&mut self, position_tag: bool) {
+        self.position_tag = position_tag;
+    }
 
-        let mut cigar_consumption;
+    /// Tag each chunk with its estimated number of raw signal samples (sn:i), or don't if None.
+    ///
+    /// The parent's basecalled samples (ns minus the ts trimmed from the start) are shared out in
+    /// proportion to each chunk's bases, falling back to `samples_per_base` when it has no ns tag.
+    pub fn set_signal_tag(&mut self, samples_per_base: Option<f64>) {
+        self.signal_samples_per_base = samples_per_base;
+    }
 
-        let mut current_cigar = rec.cigar().take();
+    /// Set how records with mismatched CIGAR and SEQ lengths are handled
+    pub fn set_cigar_seq_mismatch(&mut self, policy: CigarSeqMismatchPolicy) {
+        self.cigar_seq_mismatch = policy;
+    }
 
-        // Handle trailing clipped bases
-        if self.skip_clipped_bases {
-            if rec.cigar().trailing_hardclips() > 0 {
-                current_cigar.pop();
-            }
-            let trailing_softclips = rec.cigar().trailing_softclips() as usize;
-            if trailing_softclips > 0 {
-                current_cigar.pop();
-            }
-        }
+    /// Number of records dropped so far by the CIGAR/SEQ mismatch policy
+    pub fn skipped_records(&self) -> u64 {
+        self.skipped_records
+    }
 
-        let mut cigar_iter = current_cigar.into_iter();
+    /// Number of records dropped so far for having fewer base qualities than bases
+    pub fn malformed_records(&self) -> u64 {
+        self.malformed_records
+    }
 
-        // Handle starting clipped bases
-        if self.skip_clipped_bases {
-            if rec.cigar().leading_hardclips() > 0 {
-                cigar_iter.next();
-            }
-            let leading_softclips = rec.cigar().leading_softclips() as usize;
-            if leading_softclips > 0 {
-                cigar_iter.next();
-                self.record_slice_meta_buffer.global_query_offset += leading_softclips;
-            }
-        }
+    /// Number of unaligned records dropped so far for having no bases
+    pub fn empty_records(&self) -> u64 {
+        self.empty_records
+    }
 
-        for c in cigar_iter {
-            cigar_consumption = Self::consume_cigar(c, self.chunk_size - local_query_consumed);
-            self.record_slice_meta_buffer.cigar_string.push(cigar_consumption.left_c);
-            local_ref_consumed += cigar_consumption.ref_offset;
-            local_query_consumed += cigar_consumption.query_offset;
+    /// Why the last record passed to `chop_read` was dropped, if it was
+    pub fn last_rejection(&self) -> Option<RejectReason> {
+        self.last_rejection
+    }
 
-            if cigar_consumption.right_c.is_none() {
-                // Fully consumed cigar token
-                if local_query_consumed == self.chunk_size {
-                    // Add record if filled chunk_size
-                    self.add_chunk_record(rec, local_query_consumed as usize);
+    /// Leave records with fewer bases than this unchopped, for the caller to write as they are, or don't if None
+    pub fn set_passthrough_below(&mut self, passthrough_below: Option<u32>) {
+        self.passthrough_below = passthrough_below;
+    }
 
-                    // Update global offsets after adding records
-                    self.record_slice_meta_buffer.global_ref_offset += local_ref_consumed;
-                    self.record_slice_meta_buffer.global_query_offset += local_query_consumed as usize;
+    /// Whether the last record passed to `chop_read` was too short to chop, so should be written as it is
+    pub fn passed_through(&self) -> bool {
+        self.passed_through
+    }
 
-                    // Restart new consumption cycle
-                    self.record_slice_meta_buffer.cigar_string.clear();
-                    local_ref_consumed = 0;
-                    local_query_consumed = 0;
-                }
-            } else {
-                // Finish consuming any Cigar in the buffer from previous iteration
-                while let Some(c_buf) = cigar_consumption.right_c {
-                    // Partially consumed cigar, so must be time to write new record chunk
-                    self.add_chunk_record(rec, local_query_consumed as usize);
+    /// Drop chunks whose mean base quality is below this threshold
+    pub fn set_min_mean_qual(&mut self, min_mean_qual: Option<f64>) {
+        self.min_mean_qual = min_mean_qual;
+    }
 
-                    // Update global offsets after adding records
-                    self.record_slice_meta_buffer.global_ref_offset += local_ref_consumed;
-                    self.record_slice_meta_buffer.global_query_offset += local_query_consumed as usize;
+    /// Leave the low quality ends of records out of their chunks, trimmed as BWA does with this threshold, or don't if None
+    pub fn set_qual_trim(&mut self, qual_trim: Option<u8>) {
+        self.qual_trim = qual_trim;
+    }
 
-                    // Restart new consumption cycle
-                    self.record_slice_meta_buffer.cigar_string.clear();
-                    local_ref_consumed = 0;
-                    local_query_consumed = 0;
+    /// Number of chunks dropped so far for low mean base quality
+    pub fn low_quality_chunks(&self) -> u64 {
+        self.low_quality_chunks
+    }
 
-                    cigar_consumption = Self::consume_cigar(&c_buf, self.chunk_size - local_query_consumed);
-                    self.record_slice_meta_buffer.cigar_string.push(cigar_consumption.left_c);
-                    local_ref_consumed += cigar_consumption.ref_offset;
-                    local_query_consumed += cigar_consumption.query_offset;
-                }
-            }
-        }
+    /// Only keep the first `max_chunks` chunks of each record in the order it was sequenced, so from the end of reverse strand ones, or all if None
+    pub fn set_max_chunks(&mut self, max_chunks: Option<usize>) {
+        self.max_chunks = max_chunks;
+    }
 
-        // Handle min length requirement for last chunk
-        if local_query_consumed >= self.min_length {
-            self.add_chunk_record(rec, local_query_consumed as usize);
-        }
+    /// Randomly drop each chunk with probability `rate` (0 to 1), never dropping every chunk of a read.
+    ///
+    /// Kept chunks keep their indices and offset tags. Given a `seed`, the same input drops the same chunks.
+    pub fn set_dropout(&mut self, rate: f64, seed: Option<u64>) {
+        self.dropout = (rate > 0.0).then(|| (rate, seed.map_or_else(StdRng::from_entropy, StdRng::seed_from_u64)));
+    }
 
-        &self.rec_pieces_buffer
+    pub fn dropped_chunks(&self) -> u64 {
+        self.dropped_chunks
     }
 
-}
+    /// Set how each chunk's MAPQ is derived from its parent
+    pub fn set_mapq_policy(&mut self, mapq_policy: MapqPolicy) {
+        self.mapq_policy = mapq_policy;
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Toggle copying the parent's aux tags onto each chunk, minus per-base tags that can't be sliced
+    pub fn set_keep_tags(&mut self, keep_tags: bool) {
+        self.keep_tags = keep_tags;
+    }
 
-    fn make_record(qname: &str, seq: &str, base_quals: &str, cigar: &CigarString, pos: i64) -> Record {
-        let mut rec = Record::default();
-        rec.set(qname.as_bytes(), Some(cigar), seq.as_bytes(), base_quals.as_bytes());
-        rec.set_pos(pos);
-        rec.set_tid(1);
-        rec.set_mapq(60);
-        rec.set_flags(0);
-        rec
+    /// Attach `header` to each chunk, for callers using header-dependent methods like `Record::contig`.
+    ///
+    /// Chunks otherwise have no header, whichever reader their parent came from, and copy its tid as is.
+    pub fn set_header(&mut self, header: Option<Rc<HeaderView>>) {
+        self.header = header;
     }
 
-    #[test]
-    fn simple_test() {
-        let mut chopper_no_edges = AlignmentChopper::new(5, 5, false, None);
-        let mut chopper_with_edges = AlignmentChopper::new(5, 0, false, None);
+    /// Number of chunks so far that had per-base tags stripped
+    pub fn stripped_tag_chunks(&self) -> u64 {
+        self.stripped_tag_chunks
+    }
 
-        let cigar = CigarString(vec![Cigar::Match(4), Cigar::Del(5), Cigar::Match(2), Cigar::Ins(4), Cigar::SoftClip(3)]);
-        let rec = make_record("test", "AGTCGATGCATGC", "?!/??50(?/321", &cigar, 100);
+    /// Chunks of the last chopped record
+    pub fn chunks(&self) -> &Vec<Record> {
+        &self.rec_pieces_buffer
+    }
 
-        let cigar1 = CigarString(vec![Cigar::Match(4), Cigar::Del(5), Cigar::Match(1)]);
-        let rec1 = make_record("test-0", "AGTCG", "?!/??", &cigar1, 100);
+    /// Parent query ranges of the chunks of the last chopped record, in the same order
+    pub fn query_ranges(&self) -> Vec<Range<usize>> {
+        self.chunk_specs.iter().map(|spec| spec.query_start..spec.query_end).collect()
+    }
 
-        let cigar2 = CigarString(vec![Cigar::Match(1), Cigar::Ins(4)]);
-        let rec2 = make_record("test-1", "ATGCA", "50(?/", &cigar2, 110);
+    /// Whether the start and end in SEQ order of each chunk of the last chopped record were cut from
+    /// another chunk of it, rather than being the ends of the record, in the same order as the chunks
+    pub fn cut_ends(&self) -> Vec<(bool, bool)> {
+        let pieces = &self.record_slice_meta_buffer.pieces;
+        self.chunk_specs.iter().map(|spec| (spec.index > pieces.start, spec.index + 1 < pieces.end)).collect()
+    }
 
-        let cigar3 = CigarString(vec![Cigar::SoftClip(3)]);
-        let rec3 = make_record("test-2", "TGC", "321", &cigar3, 111);
+    /// Reference positions where adjacent chunks of the last chopped record meet
+    pub fn junctions(&self) -> &Vec<i64> {
+        &self.record_slice_meta_buffer.junctions
+    }
 
-        assert_eq!(chopper_no_edges.chop_read(&rec), &vec![rec1.clone(), rec2.clone()]);
-        assert_eq!(chopper_with_edges.chop_read(&rec), &vec![rec1, rec2, rec3]);
+    /// Reference positions where adjacent chunks of the last chopped record that were both kept meet, each with the
+    /// index in `chunks` of the chunk after it
+    pub fn chunk_junctions(&self) -> Vec<(i64, usize)> {
+        self.chunk_specs.windows(2).enumerate()
+            .filter(|(_, pair)| pair[1].index == pair[0].index + 1)
+            .filter_map(|(i, pair)| pair[1].joined_at.map(|pos| (pos, i + 1)))
+            .collect()
     }
 
-    #[test]
-    fn test_pos_with_starting_softclip() {
-        let mut chopper_with_edges = AlignmentChopper::new(5, 0, false, None);
+    fn reset(&mut self) {
+        // Reset internal buffers for new Record
+        self.chunk_specs.clear();
+        self.rec_pieces_buffer.clear();
+        self.record_slice_meta_buffer.reset();
+        self.last_rejection = None;
+        self.passed_through = false;
+    }
 
-        let cigar = CigarString(vec![Cigar::SoftClip(4), Cigar::Equal(1), Cigar::Del(4), Cigar::Match(2), Cigar::Ins(4), Cigar::SoftClip(3)]);
-        let rec = make_record("test", "AGTCGATGCATGCA", "?!/??50(?/3210", &cigar, 100);
+    // Drop chunk specs past the first max_chunks from the 5' end of `rec`, counting those already filtered out
+    fn apply_max_chunks(&mut self, rec: &Record) {
+        let Some(max_chunks) = self.max_chunks else {
+            return;
+        };
+        let pieces = self.record_slice_meta_buffer.pieces.clone();
+        if rec.is_reverse() {
+            self.chunk_specs.retain(|spec| spec.index + max_chunks >= pieces.end);
+        } else {
+            self.chunk_specs.retain(|spec| spec.index < pieces.start + max_chunks);
+        }
+    }
 
-        let cigar1 = CigarString(vec![Cigar::SoftClip(4), Cigar::Equal(1)]);
-        let rec1 = make_record("test-0", "AGTCG", "?!/??", &cigar1, 100);
+    // Hard clip the bases of `rec` before and after each chunk, on top of any it was already clipped by
+    fn apply_clip_mode(&mut self, rec: &Record) {
+        // Unmapped records have no CIGAR, and SEQ '*' gives no offsets to clip by
+        if self.clip_mode != ClipMode::Hard || rec.is_unmapped() || rec.seq_len() == 0 {
+            return;
+        }
+        let cigar = long_cigar(rec).unwrap_or_else(|| rec.cigar().take());
+        let hard_clip = |c: Option<&Cigar>| match c {
+            Some(Cigar::HardClip(len)) => *len as usize,
+            _ => 0,
+        };
+        let (leading, trailing) = (hard_clip(cigar.first()), if cigar.len() > 1 { hard_clip(cigar.last()) } else { 0 });
+        for spec in self.chunk_specs.iter_mut().filter(|spec| !spec.unaligned) {
+            let mut clipped = vec![Cigar::HardClip((leading + spec.query_start) as u32)];
+            clipped.extend(spec.cigar.iter().copied().filter(|c| !matches!(c, Cigar::HardClip(_))));
+            clipped.push(Cigar::HardClip((trailing + rec.seq_len() - spec.query_end) as u32));
+            clipped.retain(|c| !c.is_empty());
+            spec.cigar = CigarString(clipped);
+        }
+    }
+
+    // Randomly drop chunk specs, keeping one at random if every chunk of a read came up
+    fn apply_dropout(&mut self) {
+        let Some((rate, rng)) = self.dropout.as_mut() else {
+            return;
+        };
+        if self.chunk_specs.len() < 2 {
+            return;
+        }
+        let mut keep = self.chunk_specs.iter().map(|_| !rng.gen_bool(*rate)).collect::<Vec<_>>();
+        if !keep.contains(&true) {
+            let survivor = rng.gen_range(0..keep.len());
+            keep[survivor] = true;
+        }
+        let mut keep = keep.into_iter();
+        let before = self.chunk_specs.len();
+        self.chunk_specs.retain(|_| keep.next().unwrap());
+        self.dropped_chunks += (before - self.chunk_specs.len()) as u64;
+    }
+
+    fn add_chunk_spec(&mut self, original_rec: &Record, local_query_consumed: usize) {
+        // Everything after a chunk reaching the end of the read lies within it
+        if self.record_slice_meta_buffer.reached_end {
+            return;
+        }
+        let joined_at = self.record_slice_meta_buffer.open_junction.take();
+        let query_offset = self.record_slice_meta_buffer.global_query_offset;
+        let mut cigar = self.record_slice_meta_buffer.cigar_string.clone();
+        let mut local_query_consumed = local_query_consumed;
+        if self.overlap > 0 {
+            // Run on past the chunk into the bases of those after it
+            let (_, rest) = split_cigar_at(&self.record_slice_meta_buffer.full_cigar, (query_offset + local_query_consumed) as u32, self.record_slice_meta_buffer.global_ref_offset + ref_len(&cigar));
+            let (extension, after) = match self.chunk_by {
+                ChunkBy::Query => split_cigar_at_query(&rest, self.overlap),
+                ChunkBy::Ref | ChunkBy::Tile => split_cigar_at_ref(&rest, self.overlap as i64),
+                ChunkBy::Aligned => split_cigar_at_aligned(&rest, self.overlap),
+            };
+            self.record_slice_meta_buffer.reached_end = after.iter().all(|c| !consumes_query(c) && !consumes_ref(c));
+            local_query_consumed += query_len(&extension) as usize;
+            extend_cigar(&mut cigar, &extension);
+        }
+        let mut ref_offset = self.record_slice_meta_buffer.global_ref_offset;
+        if self.max_ref_span.is_some() || self.deletion_policy == DeletionPolicy::Drop {
+            // Leave out the deletions split at the ends of the chunk, moving its start past them
+            let leading = cigar.iter().take_while(|c| matches!(c, Cigar::Del(_))).count();
+            let trailing = cigar.iter().rev().take_while(|c| matches!(c, Cigar::Del(_))).count().min(cigar.len() - leading);
+            ref_offset += cigar.iter().take(leading).map(|c| c.len() as i64).sum::<i64>();
+            cigar = CigarString(cigar.0[leading..cigar.len() - trailing].to_vec());
+            if query_len(&cigar) == 0 {
+                return;
+            }
+        }
+        // Note where chunks start before filtering so names don't depend on which chunks were emitted
+        let cut_at = (query_offset, ref_offset);
+        self.record_slice_meta_buffer.chunk_starts.push(cut_at);
+        self.record_slice_meta_buffer.chunk_count += 1;
+        let mut query_offset = query_offset;
+        if self.chunk_trim > 0 {
+            // Trim both ends, dropping chunks with nothing left between them
+            let len = query_len(&cigar);
+            if len <= 2 * self.chunk_trim {
+                return;
+            }
+            let (ref_trimmed, kept) = trim_cigar_query(&cigar, self.chunk_trim, len - self.chunk_trim);
+            query_offset += self.chunk_trim as usize;
+            local_query_consumed -= 2 * self.chunk_trim as usize;
+            ref_offset += ref_trimmed;
+            cigar = kept;
+        }
+        // Clamp to SEQ so a CIGAR running past it can't slice out of bounds; SEQ '*' gives empty chunks
+        let slice_end = min(original_rec.seq_len(), query_offset + local_query_consumed);
+        let slice_start = min(query_offset, slice_end);
 
-        let cigar2 = CigarString(vec![Cigar::Del(4), Cigar::Match(2), Cigar::Ins(3)]);
-        let rec2 = make_record("test-1", "ATGCA", "50(?/", &cigar2, 101);
+        // Filter out low quality chunks, ignoring records without base qualities
+        if let Some(min_mean_qual) = self.min_mean_qual {
+            let qual = &original_rec.qual()[slice_start..slice_end];
+            if !qual.is_empty() && qual[0] != 255 {
+                let mean_qual = qual.iter().map(|&q| q as f64).sum::<f64>() / qual.len() as f64;
+                if mean_qual < min_mean_qual {
+                    self.low_quality_chunks += 1;
+                    return;
+                }
+            }
+        }
 
-        let cigar3 = CigarString(vec![Cigar::Ins(1), Cigar::SoftClip(3)]);
-        let rec3 = make_record("test-2", "TGCA", "3210", &cigar3, 107);
+        self.chunk_specs.push(ChunkSpec {
+            index: 0,
+            cut_at,
+            joined_at,
+            query_start: slice_start,
+            query_end: slice_end,
+            query_len: local_query_consumed as u32,
+            ref_offset,
+            cigar,
+            unaligned: false,
+        });
+    }
 
-        assert_eq!(chopper_with_edges.chop_read(&rec), &vec![rec1, rec2, rec3]);
+    // Append the final piece of `rec`, too short to be a chunk of its own, onto the chunk before it if that was kept
+    fn merge_remainder(&mut self, rec: &Record, local_query_consumed: usize) {
+        let meta = &mut self.record_slice_meta_buffer;
+        let Some(last) = self.chunk_specs.last_mut().filter(|spec| meta.chunk_starts.last() == Some(&spec.cut_at)) else {
+            return;
+        };
+        extend_cigar(&mut last.cigar, &meta.cigar_string);
+        last.query_end = min(rec.seq_len(), meta.global_query_offset + local_query_consumed);
+        last.query_len += local_query_consumed as u32;
+        meta.junctions.pop();
     }
 
-    #[test]
-    fn skip_softclips_test() {
-        let mut chopper_skip_softclips_no_edges = AlignmentChopper::new(5, 5, true, None);
-        let mut chopper_skip_softclips_with_edges = AlignmentChopper::new(5, 0, true, None);
+    fn build_chunk_record(&mut self, original_rec: &Record, spec: &ChunkSpec) -> Record {
+        let mut new_rec = Record::default();
 
-        let cigar = CigarString(vec![Cigar::SoftClip(1), Cigar::Match(4), Cigar::Del(5), Cigar::Match(2), Cigar::Ins(4), Cigar::Equal(1), Cigar::SoftClip(3)]);
-        let rec = make_record("test", "CAGTCGATGCATGCG", "??!/??50(?/3210", &cigar, 100);
+        // Get seq and qual slices
+        let new_seq = original_rec.seq().as_bytes()[spec.query_start..spec.query_end].to_vec();
+        let new_qual = &original_rec.qual()[spec.query_start..spec.query_end];
 
-        let cigar1 = CigarString(vec![Cigar::Match(4), Cigar::Del(5), Cigar::Match(1)]);
-        let rec1 = make_record("test-0", "AGTCG", "?!/??", &cigar1, 100);
+        // Update name for chunk
+        let new_qname = &[original_rec.qname(), b"-", spec.index.to_string().as_bytes()].concat();
 
-        let cigar2 = CigarString(vec![Cigar::Match(1), Cigar::Ins(4)]);
-        let rec2 = make_record("test-1", "ATGCA", "50(?/", &cigar2, 110);
+        // These are changed based on the particular slice, except unmapped records stay unplaced
+        if original_rec.is_unmapped() || spec.unaligned {
+            new_rec.set(new_qname, None, &new_seq, new_qual);
+            new_rec.set_pos(original_rec.pos());
+        } else {
+            new_rec.set(new_qname, Some(&spec.cigar), &new_seq, new_qual);
+            new_rec.set_pos(original_rec.pos() + spec.ref_offset);
+        }
 
-        let cigar3 = CigarString(vec![Cigar::Equal(1)]);
-        let rec3 = make_record("test-2", "T", "3", &cigar3, 111);
+        // Following are unchanged, other than MAPQ per policy
+        new_rec.set_flags(original_rec.flags());
+        new_rec.set_tid(original_rec.tid());
+        let chunk_len = self.chunk_by.size(spec.query_len, ref_len(&spec.cigar), &spec.cigar);
+        new_rec.set_mapq(self.mapq_policy.apply(original_rec.mapq(), chunk_len, self.chunk_size));
+        if spec.unaligned {
+            new_rec.set_unmapped();
+            new_rec.set_mapq(0);
+        }
+        new_rec.set_mtid(original_rec.mtid());
+        new_rec.set_mpos(original_rec.mpos());
+        new_rec.set_insert_size(original_rec.insert_size());
+        // Fresh records have bin 0, which BAM would store as is; records without a reference span take up one base
+        let end = new_rec.pos() + ref_len(&new_rec.cigar().take()).max(1);
+        new_rec.set_bin(reg2bin(new_rec.pos(), end));
+        if let Some(header) = &self.header {
+            new_rec.set_header(header.clone());
+        }
 
-        assert_eq!(chopper_skip_softclips_no_edges.chop_read(&rec), &vec![rec1.clone(), rec2.clone()]);
-        assert_eq!(chopper_skip_softclips_with_edges.chop_read(&rec), &vec![rec1, rec2, rec3]);
+        // Aux data other than RG is lost unless keeping tags
+        if self.keep_tags {
+            let mut stripped = false;
+            for (tag, value) in original_rec.aux_iter().flatten() {
+                if PER_BASE_TAGS.contains(&tag) {
+                    stripped = true;
+                    continue;
+                }
+                // The mate is chopped too, so its original CIGAR no longer describes any record, and
+                // CG held the parent's own CIGAR, which htslib moves back to CG for chunks that need it
+                if tag == b"MC" || tag == b"CG" {
+                    continue;
+                }
+                // The mate's chunks get their MAPQ through the same policy, but a length-scaled one can't be known here
+                if tag == b"MQ" {
+                    if let Some(mate_mapq) = aux_int(&value).filter(|_| self.mapq_policy != MapqPolicy::LengthScaled) {
+                        let mate_mapq = self.mapq_policy.apply(mate_mapq.clamp(0, 255) as u8, self.chunk_size, self.chunk_size);
+                        new_rec.push_aux(b"MQ", Aux::I32(mate_mapq as i32)).expect("Unable to add MQ tag");
+                    }
+                    continue;
+                }
+                new_rec.push_aux(tag, value).unwrap_or_else(|_| panic!("Unable to copy {} tag at: {} - {}", String::from_utf8_lossy(tag), &new_rec.tid(), &new_rec.pos()));
+            }
+            if stripped {
+                self.stripped_tag_chunks += 1;
+            }
+        }
+        if let Some(rg) = &self.read_group {
+            if let Ok(_a) = new_rec.aux(b"RG") {
+                new_rec.remove_aux(b"RG").unwrap_or_else(|_| panic!("Could not remove RG from: {} - {}", &new_rec.tid(), &new_rec.pos()));
+            }
+            new_rec.push_aux(b"RG", Aux::String(rg)).unwrap_or_else(|_| panic!("Unable to push RG string at: {} - {}", &new_rec.tid(), &new_rec.pos()));
+        }
+
+        // Fraction of the parent read preceding this chunk as sequenced, which for reverse records follows it in SEQ
+        if self.position_tag {
+            let start = if original_rec.is_reverse() { original_rec.seq_len() - spec.query_end } else { spec.query_start };
+            let fraction = start as f32 / original_rec.seq_len().max(1) as f32;
+            new_rec.push_aux(b"fp", Aux::Float(fraction)).unwrap_or_else(|_| panic!("Unable to push fp tag at: {} - {}", &new_rec.tid(), &new_rec.pos()));
+        }
+
+        // Read from the parent even when its tags aren't copied
+        if let Some(samples_per_base) = self.signal_samples_per_base {
+            let tag_int = |tag: &[u8]| original_rec.aux(tag).ok().and_then(|value| aux_int(&value));
+            let chunk_bases = (spec.query_end - spec.query_start) as f64;
+            let samples = match tag_int(b"ns") {
+                Some(ns) => (ns - tag_int(b"ts").unwrap_or(0)).max(0) as f64 * chunk_bases / original_rec.seq_len().max(1) as f64,
+                None => samples_per_base * chunk_bases,
+            };
+            new_rec.push_aux(b"sn", Aux::I32(samples.round() as i32)).unwrap_or_else(|_| panic!("Unable to push sn tag at: {} - {}", &new_rec.tid(), &new_rec.pos()));
+        }
+
+        new_rec
+    }
+
+    pub fn chop_read(&mut self, rec: &Record) -> &Vec<Record> {
+        self.find_chunks(rec);
+        self.apply_max_chunks(rec);
+        self.apply_dropout();
+        self.apply_clip_mode(rec);
+
+        let specs = std::mem::take(&mut self.chunk_specs);
+        for spec in &specs {
+            let new_rec = self.build_chunk_record(rec, spec);
+            self.rec_pieces_buffer.push(new_rec);
+        }
+        self.chunk_specs = specs;
+
+        &self.rec_pieces_buffer
+    }
+
+    /// Like `chop_read`, but returns views borrowing from `rec` so SEQ and QUAL are only copied on demand
+    pub fn chunk_views<'a>(&mut self, rec: &'a Record) -> Vec<ChunkView<'a>> {
+        self.find_chunks(rec);
+        self.apply_max_chunks(rec);
+        self.apply_dropout();
+        self.apply_clip_mode(rec);
+        self.chunk_specs.iter().map(|spec| ChunkView { parent: rec, spec: spec.clone() }).collect()
+    }
+
+    // Size of the current chunk in the units of chunk_by, where a chunk size of 0 leaves only the breakpoints to split at
+    fn capacity(&self) -> u32 {
+        if self.record_slice_meta_buffer.boundaries.is_some() {
+            return u32::MAX;
+        }
+        if let Some((size, longer)) = self.record_slice_meta_buffer.even_sizes {
+            return size + (self.record_slice_meta_buffer.chunk_count < longer) as u32;
+        }
+        if let Some(size) = self.record_slice_meta_buffer.drawn_size {
+            return size;
+        }
+        if self.chunk_size == 0 { u32::MAX } else { self.chunk_size.saturating_sub(self.overlap).max(1) }
+    }
+
+    // Reference bases between the start of the current chunk's tile and the chunk, which count towards it
+    fn ref_phase(&self, rec: &Record) -> i64 {
+        if self.chunk_by != ChunkBy::Tile || rec.is_unmapped() || self.record_slice_meta_buffer.boundaries.is_some() || self.record_slice_meta_buffer.even_sizes.is_some() || self.record_slice_meta_buffer.drawn_size.is_some() {
+            return 0;
+        }
+        let anchor = match self.bin_anchor {
+            BinAnchor::Zero => 0,
+            BinAnchor::Pos => rec.pos(),
+            BinAnchor::Offset(offset) => offset,
+        };
+        (rec.pos() + self.record_slice_meta_buffer.global_ref_offset - anchor).rem_euclid(self.capacity() as i64)
+    }
+
+    // Breakpoints strictly within the reference span of `rec`, where it must be split
+    fn breakpoints_within(&self, rec: &Record, cigar: &CigarString) -> Vec<i64> {
+        if rec.is_unmapped() {
+            return Vec::new();
+        }
+        let start = rec.pos() + self.record_slice_meta_buffer.global_ref_offset;
+        let end = start + ref_len(cigar);
+        let mut breakpoints = self.strategies.iter().flat_map(|strategy| strategy.breakpoints(rec.tid(), start, end)).collect::<Vec<_>>();
+        breakpoints.sort_unstable();
+        breakpoints.dedup();
+        breakpoints
+    }
+
+    // Consume as much of `c` as fits in the current chunk, holding `query_used` query and `ref_used`
+    // reference bases so far, without running past the next breakpoint
+    fn consume(&self, c: &Cigar, rec: &Record, query_used: u32, ref_used: i64, breakpoints: &[i64]) -> SplitCigarBuf {
+        let consumption = self.chunk_by.consume(c, self.capacity(), query_used, ref_used + self.ref_phase(rec), &self.record_slice_meta_buffer.cigar_string);
+        let ref_pos = rec.pos() + self.record_slice_meta_buffer.global_ref_offset + ref_used;
+        let consumption = match breakpoints.iter().find(|&&bp| bp > ref_pos) {
+            Some(&bp) if ref_pos + consumption.ref_offset > bp => consume_cigar_ref(c, bp - ref_pos),
+            _ => consumption,
+        };
+        let consumption = match self.max_ref_span {
+            Some(span) if ref_used + consumption.ref_offset > span => consume_cigar_ref(c, span - ref_used),
+            _ => consumption,
+        };
+        let query_pos = self.record_slice_meta_buffer.global_query_offset + query_used as usize;
+        match self.record_slice_meta_buffer.query_cuts.iter().find(|&&cut| cut > query_pos) {
+            Some(&cut) if query_pos + consumption.query_offset as usize > cut => consume_cigar(c, (cut - query_pos) as u32),
+            _ => consumption,
+        }
+    }
+
+    // Whether the current chunk has reached the chunk size
+    fn chunk_full(&self, rec: &Record, query_used: u32, ref_used: i64) -> bool {
+        self.chunk_by.size(query_used, ref_used + self.ref_phase(rec), &self.record_slice_meta_buffer.cigar_string) == self.capacity()
+            || self.max_ref_span.is_some_and(|span| ref_used >= span)
+    }
+
+    // Whether the current chunk, holding `query_used` query and `ref_used` reference bases, ends on a breakpoint or query cut
+    fn at_breakpoint(&self, rec: &Record, query_used: u32, ref_used: i64, breakpoints: &[i64]) -> bool {
+        ref_used > 0 && breakpoints.binary_search(&(rec.pos() + self.record_slice_meta_buffer.global_ref_offset + ref_used)).is_ok()
+            || query_used > 0 && self.record_slice_meta_buffer.query_cuts.binary_search(&(self.record_slice_meta_buffer.global_query_offset + query_used as usize)).is_ok()
+    }
+
+    // Add the current chunk, holding `query_used` query and `ref_used` reference bases, and start the next after it
+    fn close_chunk(&mut self, rec: &Record, query_used: u32, ref_used: i64) {
+        self.add_chunk_spec(rec, query_used as usize);
+
+        // Update global offsets after adding records
+        self.record_slice_meta_buffer.global_ref_offset += ref_used;
+        self.record_slice_meta_buffer.global_query_offset += query_used as usize;
+        self.record_slice_meta_buffer.junctions.push(rec.pos() + self.record_slice_meta_buffer.global_ref_offset);
+        self.record_slice_meta_buffer.open_junction = self.record_slice_meta_buffer.junctions.last().copied();
+
+        // Restart new consumption cycle
+        self.record_slice_meta_buffer.cigar_string.clear();
+        self.draw_chunk_size();
+    }
+
+    // Whether records are cut at `c`, which then belongs to no chunk
+    fn cuts_at(&self, c: &Cigar) -> bool {
+        self.strategies.iter().any(|strategy| strategy.cuts_at(c))
+    }
+
+    // End the current chunk, holding `query_used` query and `ref_used` reference bases, at a cut through
+    // the read if it meets the min length, and start the next after the `skipped` op
+    fn cut(&mut self, rec: &Record, query_used: u32, ref_used: i64, skipped: &Cigar) {
+        let size = self.chunk_by.size(query_used, ref_used, &self.record_slice_meta_buffer.cigar_string);
+        if size > 0 && size >= self.min_length {
+            self.add_chunk_spec(rec, query_used as usize);
+        } else if size == 0 && self.record_slice_meta_buffer.junctions.last() == Some(&(rec.pos() + self.record_slice_meta_buffer.global_ref_offset)) {
+            // The chunk before ends at the cut, so its boundary can't be moved
+            self.record_slice_meta_buffer.junctions.pop();
+        }
+        let skipped_ref = if consumes_ref(skipped) { skipped.len() as i64 } else { 0 };
+        let skipped_query = if consumes_query(skipped) { skipped.len() } else { 0 };
+        self.record_slice_meta_buffer.global_ref_offset += ref_used + skipped_ref;
+        self.record_slice_meta_buffer.global_query_offset += (query_used + skipped_query) as usize;
+        self.record_slice_meta_buffer.open_junction = None;
+        self.record_slice_meta_buffer.cigar_string.clear();
+        self.draw_chunk_size();
+    }
+
+    // Draw the size of the next chunk, if sizes are random
+    fn draw_chunk_size(&mut self) {
+        // Each strategy adjusts the size left by those before it
+        let size = self.chunk_size.saturating_sub(self.overlap).max(1);
+        let mut drawn_size = None;
+        for strategy in self.strategies.iter_mut() {
+            drawn_size = strategy.next_size(drawn_size.unwrap_or(size)).or(drawn_size);
+        }
+        self.record_slice_meta_buffer.drawn_size = drawn_size;
+    }
+
+    // Boundaries of the last split of `rec`, snapped to listed positions and moved out of avoided intervals, if any had to be
+    fn shifted_boundaries(&self, rec: &Record) -> Option<Vec<i64>> {
+        if rec.is_unmapped() {
+            return None;
+        }
+        let intervals = self.avoid_intervals.get(&rec.tid()).map_or(&[][..], Vec::as_slice);
+        let positions = self.snap_positions.get(&rec.tid()).map_or(&[][..], Vec::as_slice);
+        if intervals.is_empty() && positions.is_empty() {
+            return None;
+        }
+        let breakpoints = &self.record_slice_meta_buffer.breakpoints;
+        let (start, end) = (rec.pos(), self.record_slice_meta_buffer.ref_end);
+        // Interval with `boundary` strictly inside it, splitting two of its bases
+        let avoided = |boundary: i64| intervals[..intervals.partition_point(|&(s, _)| s < boundary)].last().filter(|&&(_, e)| boundary < e).copied();
+        // Listed position nearest to `boundary` within the tolerance and the read, preferring the earlier of two
+        let snapped = |boundary: i64| {
+            let next = positions.partition_point(|&position| position < boundary);
+            positions[next.saturating_sub(1)..min(next + 1, positions.len())].iter().copied()
+                .filter(|&position| (position - boundary).abs() <= self.snap_tolerance && start < position && position < end)
+                .min_by_key(|&position| (position - boundary).abs())
+                .unwrap_or(boundary)
+        };
+
+        let junctions = &self.record_slice_meta_buffer.junctions;
+        let boundaries = junctions.iter().filter_map(|&junction| {
+            if breakpoints.binary_search(&junction).is_ok() {
+                return Some(junction);
+            }
+            let boundary = snapped(junction);
+            match avoided(boundary) {
+                Some((s, e)) => [s, e].into_iter().filter(|&moved| start < moved && moved < end).min_by_key(|&moved| (moved - boundary).abs()),
+                None => Some(boundary),
+            }
+        }).collect::<Vec<_>>();
+        if boundaries == *junctions {
+            return None;
+        }
+        let mut boundaries = boundaries;
+        boundaries.sort_unstable();
+        boundaries.dedup();
+        Some(boundaries)
+    }
+
+    // Work out where each chunk of `rec` lies, leaving them in chunk_specs
+    fn find_chunks(&mut self, rec: &Record) {
+        let low_quality_chunks = self.low_quality_chunks;
+        self.split_record(rec, None);
+        // Split afresh at the boundaries moved out of avoided intervals, not counting chunks filtered the first time
+        if let Some(boundaries) = self.shifted_boundaries(rec) {
+            self.low_quality_chunks = low_quality_chunks;
+            self.split_record(rec, Some(boundaries));
+        }
+    }
+
+    // Split `rec` into chunks by size and breakpoints, or only at `boundaries` if given
+    fn split_record(&mut self, rec: &Record, boundaries: Option<Vec<i64>>) {
+        self.reset();  // Clear internal buffers
+        self.record_slice_meta_buffer.boundaries = boundaries;
+        for strategy in self.strategies.iter_mut() {
+            strategy.start(rec);
+        }
+        self.draw_chunk_size();
+
+        if self.passthrough_below.is_some_and(|len| (rec.seq_len() as u32) < len) {
+            self.passed_through = true;
+            return;
+        }
+
+        let mut local_ref_consumed = 0;
+        let mut local_query_consumed = 0;
+
+        let mut cigar_consumption;
+
+        // Chop the real CIGAR of records that keep it in CG, as chunks are split from that
+        let mut current_cigar = long_cigar(rec).unwrap_or_else(|| rec.cigar().take());
+        let seq_len = rec.seq_len() as u32;
+
+        // Unaligned records (e.g. basecaller uBAMs) have no CIGAR, so chop them by sequence alone
+        if current_cigar.is_empty() && rec.is_unmapped() {
+            if seq_len == 0 {
+                self.empty_records += 1;
+                self.last_rejection = Some(RejectReason::EmptyRecord);
+                return;
+            }
+            current_cigar.push(Cigar::Match(seq_len));
+        }
+
+        // Handle CIGAR/SEQ disagreements, except SEQ '*' which is valid for any CIGAR
+        if seq_len > 0 && query_len(&current_cigar) != seq_len {
+            match self.cigar_seq_mismatch {
+                CigarSeqMismatchPolicy::Error => panic!("CIGAR and SEQ lengths disagree for record: {}", String::from_utf8_lossy(rec.qname())),
+                CigarSeqMismatchPolicy::ClipCigar => current_cigar = clip_cigar_to_query_len(&current_cigar, seq_len),
+                CigarSeqMismatchPolicy::Skip => {
+                    self.skipped_records += 1;
+                    self.last_rejection = Some(RejectReason::CigarSeqMismatch);
+                    return;
+                }
+            }
+        }
+
+        // Chunk qualities are sliced alongside SEQ, so they must cover every base
+        if rec.qual().len() < seq_len as usize {
+            self.malformed_records += 1;
+            self.last_rejection = Some(RejectReason::QualSeqMismatch);
+            return;
+        }
+
+        // Leave low quality ends out, along with deletions the trimmed start leaves before the first base
+        if let Some(threshold) = self.qual_trim {
+            let qual = &rec.qual()[..seq_len as usize];
+            if qual.first() != Some(&255) {
+                let start = qual_trim_len(qual.iter(), threshold);
+                let end = qual.len() - qual_trim_len(qual[start..].iter().rev(), threshold);
+                let (ref_trimmed, kept) = trim_cigar_query(&current_cigar, start as u32, end as u32);
+                self.record_slice_meta_buffer.global_ref_offset += ref_trimmed;
+                self.record_slice_meta_buffer.global_query_offset += start;
+                current_cigar = kept;
+            }
+        }
+
+        // Handle clipped bases, leaving hard clips out of the chunks unless the soft clips are attached to them
+        if self.clip_policy != ClipPolicy::Attach {
+            if let Some(Cigar::HardClip(_)) = current_cigar.last() {
+                current_cigar.pop();
+            }
+            if let Some(Cigar::HardClip(_)) = current_cigar.first() {
+                current_cigar.0.remove(0);
+            }
+        }
+        match self.clip_policy {
+            ClipPolicy::Drop => {
+                // Dropped clips still take up the numbers of the chunks they'd otherwise be
+                if let Some(&Cigar::SoftClip(len)) = current_cigar.last() {
+                    current_cigar.pop();
+                    let start = self.record_slice_meta_buffer.global_query_offset + query_len(&current_cigar) as usize;
+                    self.record_slice_meta_buffer.trailing_clip = Some(start..start + len as usize);
+                }
+            }
+            ClipPolicy::Attach | ClipPolicy::Separate => self.set_clips_aside(&mut current_cigar),
+        }
+
+        if self.overlap > 0 {
+            self.record_slice_meta_buffer.full_cigar = current_cigar.clone();
+        }
+        if self.num_chunks.is_some() || (self.balance && self.chunk_size > 0) {
+            let total = match self.chunk_by {
+                ChunkBy::Query => query_len(&current_cigar),
+                ChunkBy::Ref | ChunkBy::Tile => ref_len(&current_cigar) as u32,
+                ChunkBy::Aligned => aligned_len(&current_cigar),
+            };
+            // Balanced chunks are as many as the chunk size fits into the read, rounded to the nearest
+            let pieces = self.num_chunks.unwrap_or_else(|| (total + self.chunk_size / 2) / self.chunk_size).min(total).max(1);
+            self.record_slice_meta_buffer.even_sizes = Some((total / pieces, (total % pieces) as usize));
+        }
+        let mut cigar_iter = current_cigar.iter().peekable();
+
+        // Handle starting clipped bases
+        if self.clip_policy == ClipPolicy::Drop {
+            if let Some(Cigar::SoftClip(leading_softclips)) = cigar_iter.peek() {
+                let start = self.record_slice_meta_buffer.global_query_offset;
+                self.record_slice_meta_buffer.leading_clip = Some(start..start + *leading_softclips as usize);
+                self.record_slice_meta_buffer.global_query_offset += *leading_softclips as usize;
+                cigar_iter.next();
+            }
+        }
+        let chunk_by = self.chunk_by;
+        self.record_slice_meta_buffer.ref_end = rec.pos() + self.record_slice_meta_buffer.global_ref_offset + ref_len(&current_cigar);
+        let breakpoints = match &self.record_slice_meta_buffer.boundaries {
+            Some(boundaries) => boundaries.clone(),
+            None => {
+                self.record_slice_meta_buffer.breakpoints = self.breakpoints_within(rec, &current_cigar);
+                self.record_slice_meta_buffer.breakpoints.clone()
+            }
+        };
+        let mut query_cuts = self.strategies.iter().flat_map(|strategy| strategy.query_cuts(rec)).collect::<Vec<_>>();
+        query_cuts.sort_unstable();
+        query_cuts.dedup();
+        self.record_slice_meta_buffer.query_cuts = query_cuts;
+        while let Some(c) = cigar_iter.next() {
+            if self.cuts_at(c) {
+                self.cut(rec, local_query_consumed, local_ref_consumed, c);
+                local_ref_consumed = 0;
+                local_query_consumed = 0;
+                continue;
+            }
+            cigar_consumption = self.consume(c, rec, local_query_consumed, local_ref_consumed, &breakpoints);
+            // A chunk filled by the previous op leaves nothing of this one on the left
+            if !cigar_consumption.left_c.is_empty() {
+                self.record_slice_meta_buffer.cigar_string.push(cigar_consumption.left_c);
+            }
+            local_ref_consumed += cigar_consumption.ref_offset;
+            local_query_consumed += cigar_consumption.query_offset;
+
+            if cigar_consumption.right_c.is_none() {
+                // Fully consumed cigar token
+                if self.chunk_full(rec, local_query_consumed, local_ref_consumed) || self.at_breakpoint(rec, local_query_consumed, local_ref_consumed, &breakpoints) {
+                    // A trailing hard clip belongs to the final chunk, not an empty one after it
+                    if let Some(Cigar::HardClip(_)) = cigar_iter.peek() {
+                        self.record_slice_meta_buffer.cigar_string.push(*cigar_iter.next().unwrap());
+                    }
+                    // As do the trailing bases left after the last one counted when chunking by reference or aligned bases
+                    if chunk_by != ChunkBy::Query && cigar_iter.clone().all(|c| !chunk_by.counts(c)) {
+                        for c in cigar_iter.by_ref() {
+                            local_query_consumed += consume_cigar(c, u32::MAX).query_offset;
+                            self.record_slice_meta_buffer.cigar_string.push(*c);
+                        }
+                    }
+
+                    // Add record if filled chunk_size
+                    self.close_chunk(rec, local_query_consumed, local_ref_consumed);
+                    local_ref_consumed = 0;
+                    local_query_consumed = 0;
+                }
+            } else {
+                // Finish consuming any Cigar in the buffer from previous iteration
+                while let Some(c_buf) = cigar_consumption.right_c {
+                    // Partially consumed cigar, so must be time to write new record chunk
+                    self.close_chunk(rec, local_query_consumed, local_ref_consumed);
+                    local_ref_consumed = 0;
+                    local_query_consumed = 0;
+
+                    cigar_consumption = self.consume(&c_buf, rec, local_query_consumed, local_ref_consumed, &breakpoints);
+                    self.record_slice_meta_buffer.cigar_string.push(cigar_consumption.left_c);
+                    local_ref_consumed += cigar_consumption.ref_offset;
+                    local_query_consumed += cigar_consumption.query_offset;
+                }
+                // The rest of the op may end right at a breakpoint, which later ops mustn't run past
+                if self.at_breakpoint(rec, local_query_consumed, local_ref_consumed, &breakpoints) {
+                    self.close_chunk(rec, local_query_consumed, local_ref_consumed);
+                    local_ref_consumed = 0;
+                    local_query_consumed = 0;
+                }
+            }
+        }
+
+        // No junction after the final full chunk if nothing is left over
+        let remainder = chunk_by.size(local_query_consumed, local_ref_consumed, &self.record_slice_meta_buffer.cigar_string);
+        if remainder == 0 {
+            self.record_slice_meta_buffer.junctions.pop();
+        }
+
+        // Handle min length requirement for last chunk, which may be empty if the last one was filled exactly
+        if remainder > 0 && remainder >= self.min_length {
+            self.add_chunk_spec(rec, local_query_consumed as usize);
+        } else if remainder > 0 && self.remainder == RemainderPolicy::Merge {
+            self.merge_remainder(rec, local_query_consumed as usize);
+        }
+        self.place_clips(rec);
+        self.number_chunks();
+    }
+
+    // Take the soft clips at either end of `cigar` out of it, leaving any hard clips, and note the query ranges they cover
+    fn set_clips_aside(&mut self, cigar: &mut CigarString) {
+        let meta = &mut self.record_slice_meta_buffer;
+        let trailing = cigar.len().saturating_sub(1 + matches!(cigar.last(), Some(Cigar::HardClip(_))) as usize);
+        if let Some(&Cigar::SoftClip(len)) = cigar.get(trailing) {
+            cigar.0.remove(trailing);
+            let start = meta.global_query_offset + query_len(cigar) as usize;
+            meta.trailing_clip = Some(start..start + len as usize);
+        }
+        let leading = matches!(cigar.first(), Some(Cigar::HardClip(_))) as usize;
+        if let Some(&Cigar::SoftClip(len)) = cigar.get(leading) {
+            cigar.0.remove(leading);
+            let start = meta.global_query_offset;
+            meta.leading_clip = Some(start..start + len as usize);
+            meta.global_query_offset += len as usize;
+        }
+    }
+
+    // Put the clips set aside by set_clips_aside back onto the chunks next to them, or into records of their own
+    fn place_clips(&mut self, rec: &Record) {
+        let meta = &mut self.record_slice_meta_buffer;
+        let clamp = |range: &Range<usize>| min(range.start, rec.seq_len())..min(range.end, rec.seq_len());
+        match self.clip_policy {
+            ClipPolicy::Drop => {}
+            ClipPolicy::Attach => {
+                // Only onto chunks that reach the clip, so ones trimmed or filtered out leave it out too
+                for spec in self.chunk_specs.iter_mut() {
+                    if let Some(clip) = meta.leading_clip.as_ref().filter(|clip| clip.end == spec.query_start) {
+                        let at = spec.cigar.iter().take_while(|c| matches!(c, Cigar::HardClip(_))).count();
+                        spec.cigar.0.insert(at, Cigar::SoftClip(clip.len() as u32));
+                        spec.query_start = clamp(clip).start;
+                        spec.query_len += clip.len() as u32;
+                    }
+                    if let Some(clip) = meta.trailing_clip.as_ref().filter(|clip| clip.start == spec.query_end) {
+                        let at = spec.cigar.len() - spec.cigar.iter().rev().take_while(|c| matches!(c, Cigar::HardClip(_))).count();
+                        spec.cigar.0.insert(at, Cigar::SoftClip(clip.len() as u32));
+                        spec.query_end = clamp(clip).end;
+                        spec.query_len += clip.len() as u32;
+                    }
+                }
+            }
+            ClipPolicy::Separate => {
+                let clip_spec = |cut_at: (usize, i64), clip: &Range<usize>| ChunkSpec {
+                    index: 0,
+                    cut_at,
+                    joined_at: None,
+                    query_start: clamp(clip).start,
+                    query_end: clamp(clip).end,
+                    query_len: clip.len() as u32,
+                    ref_offset: 0,
+                    cigar: CigarString(Vec::new()),
+                    unaligned: true,
+                };
+                if let Some(clip) = &meta.leading_clip {
+                    self.chunk_specs.insert(0, clip_spec((clip.start, i64::MIN), clip));
+                }
+                if let Some(clip) = &meta.trailing_clip {
+                    self.chunk_specs.push(clip_spec((clip.start, i64::MAX), clip));
+                }
+            }
+        }
+    }
+
+    // Number chunks by where they were cut among every chunk of the parent, including its clips and those filtered
+    // out, so a chunk's name doesn't change with the clip policy or with which other chunks were kept
+    fn number_chunks(&mut self) {
+        let meta = &mut self.record_slice_meta_buffer;
+        if let Some(clip) = &meta.leading_clip {
+            meta.chunk_starts.insert(0, (clip.start, i64::MIN));
+        }
+        if let Some(clip) = &meta.trailing_clip {
+            meta.chunk_starts.push((clip.start, i64::MAX));
+        }
+        // Only separate clips are pieces of their own, which the chunks next to them are cut from
+        let separate = self.clip_policy == ClipPolicy::Separate;
+        let leading = (meta.leading_clip.is_some() && !separate) as usize;
+        let trailing = (meta.trailing_clip.is_some() && !separate) as usize;
+        meta.pieces = leading..meta.chunk_starts.len() - trailing;
+        for spec in self.chunk_specs.iter_mut() {
+            spec.index = meta.chunk_starts.binary_search(&spec.cut_at).expect("chunk cut where no chunk starts");
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_htslib::bam::{self, Read};
+    use crate::strategy::{BreakpointList, ChunkSizeDist, ChunkSizeTable, IndelSplit, Jitter, LowQualityStretch, RandomSize, RefSkipSplit, SupplementarySplit};
+
+    fn make_record(qname: &str, seq: &str, base_quals: &str, cigar: &CigarString, pos: i64) -> Record {
+        let mut rec = Record::default();
+        rec.set(qname.as_bytes(), Some(cigar), seq.as_bytes(), base_quals.as_bytes());
+        rec.set_pos(pos);
+        rec.set_bin(reg2bin(pos, pos + ref_len(cigar).max(1)));
+        rec.set_tid(1);
+        rec.set_mapq(60);
+        rec.set_flags(0);
+        rec
+    }
+
+    #[test]
+    fn simple_test() {
+        let mut chopper_no_edges = AlignmentChopper::new(5, 5, ClipPolicy::Attach, None);
+        let mut chopper_with_edges = AlignmentChopper::new(5, 0, ClipPolicy::Attach, None);
+
+        let cigar = CigarString(vec![Cigar::Match(4), Cigar::Del(5), Cigar::Match(2), Cigar::Ins(4), Cigar::SoftClip(3)]);
+        let rec = make_record("test", "AGTCGATGCATGC", "?!/??50(?/321", &cigar, 100);
+
+        let cigar1 = CigarString(vec![Cigar::Match(4), Cigar::Del(5), Cigar::Match(1)]);
+        let rec1 = make_record("test-0", "AGTCG", "?!/??", &cigar1, 100);
+
+        // The trailing soft clip goes with the last chunk rather than making one of its own
+        let cigar2 = CigarString(vec![Cigar::Match(1), Cigar::Ins(4), Cigar::SoftClip(3)]);
+        let rec2 = make_record("test-1", "ATGCATGC", "50(?/321", &cigar2, 110);
+
+        assert_eq!(chopper_no_edges.chop_read(&rec), &vec![rec1.clone(), rec2.clone()]);
+        assert_eq!(chopper_no_edges.junctions(), &vec![110]);
+        assert_eq!(chopper_with_edges.chop_read(&rec), &vec![rec1, rec2]);
+    }
+
+    #[test]
+    fn test_pos_with_starting_softclip() {
+        let mut chopper_with_edges = AlignmentChopper::new(5, 0, ClipPolicy::Attach, None);
+
+        let cigar = CigarString(vec![Cigar::SoftClip(4), Cigar::Equal(1), Cigar::Del(4), Cigar::Match(2), Cigar::Ins(4), Cigar::SoftClip(3)]);
+        let rec = make_record("test", "AGTCGATGCATGCA", "?!/??50(?/3210", &cigar, 100);
+
+        // Soft clips don't count towards the chunk size, but the leading one keeps the first number
+        let cigar1 = CigarString(vec![Cigar::SoftClip(4), Cigar::Equal(1), Cigar::Del(4), Cigar::Match(2), Cigar::Ins(2)]);
+        let rec1 = make_record("test-1", "AGTCGATGC", "?!/??50(?", &cigar1, 100);
+
+        let cigar2 = CigarString(vec![Cigar::Ins(2), Cigar::SoftClip(3)]);
+        let rec2 = make_record("test-2", "ATGCA", "/3210", &cigar2, 107);
+
+        assert_eq!(chopper_with_edges.chop_read(&rec), &vec![rec1, rec2]);
+    }
+
+    #[test]
+    fn skip_softclips_test() {
+        let mut chopper_skip_softclips_no_edges = AlignmentChopper::new(5, 5, ClipPolicy::Drop, None);
+        let mut chopper_skip_softclips_with_edges = AlignmentChopper::new(5, 0, ClipPolicy::Drop, None);
+
+        let cigar = CigarString(vec![Cigar::SoftClip(1), Cigar::Match(4), Cigar::Del(5), Cigar::Match(2), Cigar::Ins(4), Cigar::Equal(1), Cigar::SoftClip(3)]);
+        let rec = make_record("test", "CAGTCGATGCATGCG", "??!/??50(?/3210", &cigar, 100);
+
+        let cigar1 = CigarString(vec![Cigar::Match(4), Cigar::Del(5), Cigar::Match(1)]);
+        let rec1 = make_record("test-1", "AGTCG", "?!/??", &cigar1, 100);
+
+        let cigar2 = CigarString(vec![Cigar::Match(1), Cigar::Ins(4)]);
+        let rec2 = make_record("test-2", "ATGCA", "50(?/", &cigar2, 110);
+
+        let cigar3 = CigarString(vec![Cigar::Equal(1)]);
+        let rec3 = make_record("test-3", "T", "3", &cigar3, 111);
+
+        assert_eq!(chopper_skip_softclips_no_edges.chop_read(&rec), &vec![rec1.clone(), rec2.clone()]);
+        assert_eq!(chopper_skip_softclips_with_edges.chop_read(&rec), &vec![rec1, rec2, rec3]);
+    }
+
+    #[test]
+    fn position_tag_test() {
+        let mut chopper = AlignmentChopper::new(4, 0, ClipPolicy::Attach, None);
+        chopper.set_position_tag(true);
+
+        let cigar = CigarString(vec![Cigar::Match(10)]);
+        let mut rec = make_record("test", "AGTCGATGCA", "?!/??50(?/", &cigar, 100);
+        let mut fractions = |rec: &Record| chopper.chop_read(rec).iter().map(|r| match r.aux(b"fp") {
+            Ok(Aux::Float(f)) => f,
+            _ => panic!("Missing fp tag"),
+        }).collect::<Vec<f32>>();
+        assert_eq!(fractions(&rec), vec![0.0, 0.4, 0.8]);
+
+        // Reverse reads were sequenced from the end of SEQ, so the last chunk in it starts the read
+        rec.set_reverse();
+        assert_eq!(fractions(&rec), vec![0.6, 0.2, 0.0]);
+    }
+
+    #[test]
+    fn chunk_by_ref_test() {
+        let mut chopper = AlignmentChopper::new(5, 0, ClipPolicy::Attach, None);
+        chopper.set_chunk_by(ChunkBy::Ref);
+
+        let cigar = CigarString(vec![Cigar::SoftClip(2), Cigar::Match(3), Cigar::Del(4), Cigar::Match(6), Cigar::Ins(2), Cigar::Match(2), Cigar::SoftClip(3)]);
+        let rec = make_record("test", "ACGTACGTACGTACGTAC", "?!/??50(?/3210?!/?", &cigar, 100);
+
+        // Deletions are split across boundaries, and the final chunk keeps the trailing clip
+        let cigar1 = CigarString(vec![Cigar::SoftClip(2), Cigar::Match(3), Cigar::Del(2)]);
+        let rec1 = make_record("test-1", "ACGTA", "?!/??", &cigar1, 100);
+
+        let cigar2 = CigarString(vec![Cigar::Del(2), Cigar::Match(3)]);
+        let rec2 = make_record("test-2", "CGT", "50(", &cigar2, 105);
+
+        let cigar3 = CigarString(vec![Cigar::Match(3), Cigar::Ins(2), Cigar::Match(2), Cigar::SoftClip(3)]);
+        let rec3 = make_record("test-3", "ACGTACGTAC", "?/3210?!/?", &cigar3, 110);
+
+        assert_eq!(chopper.chop_read(&rec), &vec![rec1, rec2, rec3]);
+        assert_eq!(chopper.junctions(), &vec![105, 110]);
+
+        // Min length counts reference bases too, so the last chunk spanning 3bp is dropped
+        let mut chopper_min_length = AlignmentChopper::new(6, 6, ClipPolicy::Attach, None);
+        chopper_min_length.set_chunk_by(ChunkBy::Ref);
+        assert_eq!(chopper_min_length.chop_read(&rec).iter().map(|r| r.cigar().to_string()).collect::<Vec<_>>(), vec!["2S3M3D", "1D5M"]);
+    }
+
+    #[test]
+    fn chunk_by_aligned_test() {
+        let mut chopper = AlignmentChopper::new(4, 0, ClipPolicy::Attach, None);
+        chopper.set_chunk_by(ChunkBy::Aligned);
+        let chunks = |chopper: &mut AlignmentChopper, rec: &Record| chopper.chop_read(rec).iter().map(|r| (r.pos(), r.cigar().to_string())).collect::<Vec<_>>();
+
+        // Clips and insertions come along without counting, so each chunk aligns 4 bases
+        let cigar = CigarString(vec![Cigar::SoftClip(2), Cigar::Match(3), Cigar::Ins(2), Cigar::Match(5), Cigar::SoftClip(2)]);
+        let rec = make_record("test", "ACGTACGTACGTAC", &"I".repeat(14), &cigar, 100);
+        assert_eq!(chunks(&mut chopper, &rec), vec![(100, String::from("2S3M2I1M")), (104, String::from("4M2S"))]);
+
+        // Overlaps count aligned bases too
+        chopper.set_overlap(2);
+        assert_eq!(chunks(&mut chopper, &rec), vec![
+            (100, String::from("2S3M2I1M")),
+            (102, String::from("1M2I3M")),
+            (104, String::from("4M2S")),
+        ]);
+    }
+
+    #[test]
+    fn chunk_by_tile_test() {
+        let mut chopper = AlignmentChopper::new(5, 0, ClipPolicy::Attach, None);
+        chopper.set_chunk_by(ChunkBy::Tile);
+
+        // Reads starting at different positions break at the same multiples of 5
+        let cigar = CigarString(vec![Cigar::Match(4), Cigar::Del(3), Cigar::Match(6)]);
+        let rec = make_record("test", "ACGTACGTAC", "?!/??50(?/", &cigar, 102);
+        let chunks = |chopper: &mut AlignmentChopper, rec: &Record| chopper.chop_read(rec).iter().map(|r| {
+            (String::from_utf8(r.qname().to_vec()).unwrap(), r.pos(), r.cigar().to_string())
+        }).collect::<Vec<_>>();
+        assert_eq!(chunks(&mut chopper, &rec), vec![
+            (String::from("test-0"), 102, String::from("3M")),
+            (String::from("test-1"), 105, String::from("1M3D1M")),
+            (String::from("test-2"), 110, String::from("5M")),
+        ]);
+        assert_eq!(chopper.junctions(), &vec![105, 110]);
+
+        let cigar = CigarString(vec![Cigar::SoftClip(2), Cigar::Match(7)]);
+        let rec = make_record("test", "ACGTACGTA", "?!/??50(?", &cigar, 105);
+        assert_eq!(chunks(&mut chopper, &rec), vec![
+            (String::from("test-1"), 105, String::from("2S5M")),
+            (String::from("test-2"), 110, String::from("2M")),
+        ]);
+
+        // Tiles can start from the read's own start instead, or from any offset
+        chopper.set_bin_anchor(BinAnchor::Pos);
+        assert_eq!(chunks(&mut chopper, &rec), vec![
+            (String::from("test-1"), 105, String::from("2S5M")),
+            (String::from("test-2"), 110, String::from("2M")),
+        ]);
+        let cigar = CigarString(vec![Cigar::Match(4), Cigar::Del(3), Cigar::Match(6)]);
+        let rec = make_record("test", "ACGTACGTAC", "?!/??50(?/", &cigar, 102);
+        assert_eq!(chunks(&mut chopper, &rec), vec![
+            (String::from("test-0"), 102, String::from("4M1D")),
+            (String::from("test-1"), 107, String::from("2D3M")),
+            (String::from("test-2"), 112, String::from("3M")),
+        ]);
+        chopper.set_bin_anchor(BinAnchor::Offset(3));
+        assert_eq!(chunks(&mut chopper, &rec), vec![
+            (String::from("test-0"), 102, String::from("1M")),
+            (String::from("test-1"), 103, String::from("3M2D")),
+            (String::from("test-2"), 108, String::from("1D4M")),
+            (String::from("test-3"), 113, String::from("2M")),
+        ]);
+
+        assert_eq!("offset:-2".parse::<BinAnchor>(), Ok(BinAnchor::Offset(-2)));
+        assert!("offset:x".parse::<BinAnchor>().is_err());
+        assert!("start".parse::<BinAnchor>().is_err());
+    }
+
+    #[test]
+    fn breakpoints_test() {
+        let mut chopper = AlignmentChopper::new(5, 0, ClipPolicy::Attach, None);
+        // Breakpoints at or outside the ends of a read don't split it
+        chopper.add_strategy(Box::new(BreakpointList::new(HashMap::from([(1, vec![120, 103, 100])]))));
+        let chunks = |chopper: &mut AlignmentChopper, rec: &Record| chopper.chop_read(rec).iter().map(|r| {
+            (String::from_utf8(r.qname().to_vec()).unwrap(), r.pos(), r.cigar().to_string())
+        }).collect::<Vec<_>>();
+
+        let cigar = CigarString(vec![Cigar::Match(10)]);
+        let rec = make_record("test", "ACGTACGTAC", "?!/??50(?/", &cigar, 100);
+        assert_eq!(chunks(&mut chopper, &rec), vec![
+            (String::from("test-0"), 100, String::from("3M")),
+            (String::from("test-1"), 103, String::from("5M")),
+            (String::from("test-2"), 108, String::from("2M")),
+        ]);
+        assert_eq!(chopper.junctions(), &vec![103, 108]);
+
+        // With a chunk size of 0 only breakpoints split reads, including within deletions
+        let mut chopper = AlignmentChopper::new(0, 0, ClipPolicy::Attach, None);
+        chopper.add_strategy(Box::new(BreakpointList::new(HashMap::from([(1, vec![106, 110])]))));
+        let cigar = CigarString(vec![Cigar::Match(4), Cigar::Del(4), Cigar::Match(6)]);
+        let rec = make_record("test", "ACGTACGTAC", "?!/??50(?/", &cigar, 100);
+        assert_eq!(chunks(&mut chopper, &rec), vec![
+            (String::from("test-0"), 100, String::from("4M2D")),
+            (String::from("test-1"), 106, String::from("2D2M")),
+            (String::from("test-2"), 110, String::from("4M")),
+        ]);
+    }
+
+    #[test]
+    fn snap_positions_test() {
+        let mut chopper = AlignmentChopper::new(5, 0, ClipPolicy::Attach, None);
+        chopper.set_snap_positions(HashMap::from([(1, vec![113, 103, 107, 119])]), 2);
+        let cigar = CigarString(vec![Cigar::Match(20)]);
+        let rec = make_record("test", "ACGTACGTACGTACGTACGT", &"I".repeat(20), &cigar, 100);
+
+        // 105 is as near 103 as 107 and goes to the earlier, while 110 has no position within 2
+        let chunks = chopper.chop_read(&rec).iter().map(|r| (r.pos(), r.cigar().to_string())).collect::<Vec<_>>();
+        assert_eq!(chunks, vec![(100, String::from("3M")), (103, String::from("7M")), (110, String::from("3M")), (113, String::from("7M"))]);
+
+        // Snapped boundaries still stay out of avoided intervals
+        chopper.set_avoid_intervals(&[TargetRegion { tid: 1, start: 112, end: 116 }]);
+        let chunks = chopper.chop_read(&rec).iter().map(|r| r.pos()).collect::<Vec<_>>();
+        assert_eq!(chunks, vec![100, 103, 110, 112]);
+    }
+
+    #[test]
+    fn avoid_intervals_test() {
+        let mut chopper = AlignmentChopper::new(5, 0, ClipPolicy::Attach, None);
+        chopper.set_avoid_intervals(&[TargetRegion { tid: 1, start: 95, end: 103 }, TargetRegion { tid: 1, start: 107, end: 112 }]);
+        let chunks = |chopper: &mut AlignmentChopper, rec: &Record| chopper.chop_read(rec).iter().map(|r| {
+            (String::from_utf8(r.qname().to_vec()).unwrap(), r.pos(), r.cigar().to_string())
+        }).collect::<Vec<_>>();
+
+        // The boundary at 110 moves to the nearer end of 107-112, and later ones stay put
+        let cigar = CigarString(vec![Cigar::Match(20)]);
+        let rec = make_record("test", "ACGTACGTACGTACGTACGT", "?!/??50(?/3210?!/??5", &cigar, 100);
+        assert_eq!(chunks(&mut chopper, &rec), vec![
+            (String::from("test-0"), 100, String::from("5M")),
+            (String::from("test-1"), 105, String::from("7M")),
+            (String::from("test-2"), 112, String::from("3M")),
+            (String::from("test-3"), 115, String::from("5M")),
+        ]);
+        assert_eq!(chopper.junctions(), &vec![105, 112, 115]);
+
+        // Boundaries only move within the read, here past the end of 95-103 rather than before its start
+        let rec = make_record("test", "ACGTACGTACGT", "?!/??50(?/32", &CigarString(vec![Cigar::Match(12)]), 98);
+        assert_eq!(chunks(&mut chopper, &rec).iter().map(|(_, pos, _)| *pos).collect::<Vec<_>>(), vec![98, 103, 107]);
+
+        // Breakpoints must be split at wherever they are
+        chopper.add_strategy(Box::new(BreakpointList::new(HashMap::from([(1, vec![109])]))));
+        assert_eq!(chunks(&mut chopper, &rec).iter().map(|(_, pos, _)| *pos).collect::<Vec<_>>(), vec![98, 103, 107, 109]);
+    }
+
+    #[test]
+    fn overlap_test() {
+        let mut chopper = AlignmentChopper::new(5, 0, ClipPolicy::Attach, None);
+        chopper.set_overlap(2);
+        let chunks = |chopper: &mut AlignmentChopper, rec: &Record| chopper.chop_read(rec).iter().map(|r| {
+            (String::from_utf8(r.qname().to_vec()).unwrap(), r.pos(), r.cigar().to_string(), r.seq().as_bytes())
+        }).collect::<Vec<_>>();
+
+        // Chunks start every 3 bases, and the one from base 9 would lie within the one from base 6
+        let cigar = CigarString(vec![Cigar::Match(4), Cigar::Del(2), Cigar::Match(6), Cigar::SoftClip(1)]);
+        let rec = make_record("test", "ACGTTGCAAGT", "?!/??50(?/3", &cigar, 100);
+        assert_eq!(chunks(&mut chopper, &rec), vec![
+            (String::from("test-0"), 100, String::from("4M2D1M"), b"ACGTT".to_vec()),
+            (String::from("test-1"), 103, String::from("1M2D4M"), b"TTGCA".to_vec()),
+            (String::from("test-2"), 108, String::from("4M1S"), b"CAAGT".to_vec()),
+        ]);
+
+        // A step of 1 gives a chunk at every base up to the last full one
+        let mut sliding = AlignmentChopper::new(9, 0, ClipPolicy::Attach, None);
+        sliding.set_step(1);
+        assert_eq!(chunks(&mut sliding, &rec).iter().map(|(_, pos, cigar, _)| (*pos, cigar.clone())).collect::<Vec<_>>(), vec![
+            (100, String::from("4M2D5M")),
+            (101, String::from("3M2D6M1S")),
+        ]);
+
+        // Overlaps count reference bases when chunking by them
+        chopper.set_chunk_by(ChunkBy::Ref);
+        assert_eq!(chunks(&mut chopper, &rec).iter().map(|(_, pos, cigar, _)| (*pos, cigar.clone())).collect::<Vec<_>>(), vec![
+            (100, String::from("4M1D")),
+            (103, String::from("1M2D2M")),
+            (106, String::from("5M")),
+            (109, String::from("3M1S")),
+        ]);
+    }
+
+    #[test]
+    fn num_chunks_test() {
+        let mut chopper = AlignmentChopper::new(0, 0, ClipPolicy::Attach, None);
+        chopper.set_num_chunks(Some(3));
+
+        // 10 aligned bases make chunks of 4, 3 and 3, the last taking the soft clip
+        let cigar = CigarString(vec![Cigar::Match(4), Cigar::Del(2), Cigar::Match(6), Cigar::SoftClip(1)]);
+        let rec = make_record("test", "ACGTTGCAAGT", "?!/??50(?/3", &cigar, 100);
+        let lengths = |chopper: &mut AlignmentChopper, rec: &Record| chopper.chop_read(rec).iter().map(|r| r.seq_len()).collect::<Vec<_>>();
+        assert_eq!(lengths(&mut chopper, &rec), vec![4, 3, 4]);
+
+        // Reads shorter than the number of chunks give a chunk per base
+        chopper.set_num_chunks(Some(20));
+        assert_eq!(lengths(&mut chopper, &rec), [vec![1; 9], vec![2]].concat());
+
+        // Balancing by a chunk size of 4 gives as many chunks as rounding 10 / 4 to the nearest
+        let mut balanced = AlignmentChopper::new(4, 0, ClipPolicy::Attach, None);
+        balanced.set_balance(true);
+        assert_eq!(lengths(&mut balanced, &rec), vec![4, 3, 4]);
+        let mut balanced = AlignmentChopper::new(5, 0, ClipPolicy::Attach, None);
+        balanced.set_balance(true);
+        assert_eq!(lengths(&mut balanced, &rec), vec![5, 6]);
+
+        // Skipped clipped bases aren't shared out
+        let mut chopper = AlignmentChopper::new(0, 0, ClipPolicy::Drop, None);
+        chopper.set_num_chunks(Some(2));
+        assert_eq!(lengths(&mut chopper, &rec), vec![5, 5]);
+    }
+
+    #[test]
+    fn remainder_merge_test() {
+        let mut chopper = AlignmentChopper::new(5, 3, ClipPolicy::Attach, None);
+        chopper.set_remainder(RemainderPolicy::Merge);
+
+        let cigar = CigarString(vec![Cigar::Match(8), Cigar::Del(1), Cigar::Match(4)]);
+        let rec = make_record("test", "ACGTACGTACGT", "IIIIII!!!!!!", &cigar, 100);
+        let merged = make_record("test-1", "CGTACGT", "I!!!!!!", &CigarString(vec![Cigar::Match(3), Cigar::Del(1), Cigar::Match(4)]), 105);
+        assert_eq!(chopper.chop_read(&rec)[1], merged);
+        assert_eq!(chopper.junctions(), &vec![105]);
+
+        // Nothing is left to merge onto when the chunk before is filtered out
+        chopper.set_min_mean_qual(Some(50.0));
+        assert_eq!(chopper.chop_read(&rec).len(), 1);
+    }
+
+    #[test]
+    fn signal_tag_test() {
+        let mut chopper = AlignmentChopper::new(4, 0, ClipPolicy::Attach, None);
+        chopper.set_signal_tag(Some(10.0));
+
+        let cigar = CigarString(vec![Cigar::Match(10)]);
+        let mut rec = make_record("test", "AGTCGATGCA", "?!/??50(?/", &cigar, 100);
+        let signal_samples = |chopper: &mut AlignmentChopper, rec: &Record| chopper.chop_read(rec).iter().map(|r| match r.aux(b"sn") {
+            Ok(Aux::I32(n)) => n,
+            _ => panic!("Missing sn tag"),
+        }).collect::<Vec<_>>();
+        assert_eq!(signal_samples(&mut chopper, &rec), vec![40, 40, 20]);
+
+        rec.push_aux(b"ns", Aux::U16(1100)).unwrap();
+        rec.push_aux(b"ts", Aux::U8(100)).unwrap();
+        assert_eq!(signal_samples(&mut chopper, &rec), vec![400, 400, 200]);
+    }
+
+    #[test]
+    fn cigar_seq_mismatch_test() {
+        let mut chopper_clip = AlignmentChopper::new(5, 0, ClipPolicy::Attach, None);
+        chopper_clip.set_cigar_seq_mismatch(CigarSeqMismatchPolicy::ClipCigar);
+        let mut chopper_skip = AlignmentChopper::new(5, 0, ClipPolicy::Attach, None);
+        chopper_skip.set_cigar_seq_mismatch(CigarSeqMismatchPolicy::Skip);
+
+        // CIGAR claims 10 query bases but SEQ only has 7
+        let cigar = CigarString(vec![Cigar::Match(4), Cigar::Del(2), Cigar::Match(6)]);
+        let rec = make_record("test", "AGTCGAT", "?!/??50", &cigar, 100);
+
+        let cigar1 = CigarString(vec![Cigar::Match(4), Cigar::Del(2), Cigar::Match(1)]);
+        let rec1 = make_record("test-0", "AGTCG", "?!/??", &cigar1, 100);
+
+        let cigar2 = CigarString(vec![Cigar::Match(2)]);
+        let rec2 = make_record("test-1", "AT", "50", &cigar2, 107);
+
+        assert_eq!(chopper_clip.chop_read(&rec), &vec![rec1, rec2]);
+        assert_eq!(chopper_clip.last_rejection(), None);
+        assert!(chopper_skip.chop_read(&rec).is_empty());
+        assert_eq!(chopper_skip.skipped_records(), 1);
+        assert_eq!(chopper_skip.last_rejection(), Some(RejectReason::CigarSeqMismatch));
+    }
+
+    #[test]
+    fn empty_seq_test() {
+        let mut chopper = AlignmentChopper::new(5, 0, ClipPolicy::Attach, None);
+
+        let cigar = CigarString(vec![Cigar::Match(8)]);
+        let rec = make_record("test", "", "", &cigar, 100);
+
+        let cigar1 = CigarString(vec![Cigar::Match(5)]);
+        let rec1 = make_record("test-0", "", "", &cigar1, 100);
+
+        let cigar2 = CigarString(vec![Cigar::Match(3)]);
+        let rec2 = make_record("test-1", "", "", &cigar2, 105);
+
+        assert_eq!(chopper.chop_read(&rec), &vec![rec1, rec2]);
+    }
+
+    #[test]
+    fn min_mean_qual_test() {
+        let mut chopper = AlignmentChopper::new(5, 0, ClipPolicy::Attach, None);
+        chopper.set_min_mean_qual(Some(20.0));
+
+        let cigar = CigarString(vec![Cigar::Match(15)]);
+        let rec = make_record("test", "AGTCGATGCATGCAT", "IIIII\x05\x05\x05\x05\x05IIII\x05", &cigar, 100);
+
+        let chunks = chopper.chop_read(&rec);
+        let seqs: Vec<Vec<u8>> = chunks.iter().map(|r| r.seq().as_bytes()).collect();
+        assert_eq!(seqs, vec![b"AGTCG".to_vec(), b"TGCAT".to_vec()]);
+
+        // Dropped chunks leave a gap in the chunk indices
+        let qnames: Vec<&[u8]> = chunks.iter().map(|r| r.qname()).collect();
+        assert_eq!(qnames, vec![b"test-0", b"test-2"]);
+        assert_eq!(chopper.low_quality_chunks(), 1);
+    }
+
+    #[test]
+    fn cut_ends_test() {
+        let cigar = CigarString(vec![Cigar::SoftClip(2), Cigar::Match(12)]);
+        let rec = make_record("test", "ACGTACGTACGTAC", &"I".repeat(14), &cigar, 100);
+        let mut chopper = AlignmentChopper::new(5, 0, ClipPolicy::Attach, None);
+        chopper.chop_read(&rec);
+        assert_eq!(chopper.cut_ends(), vec![(false, true), (true, true), (true, false)]);
+
+        // A lone chunk has nothing cut from it, while separate clips are cut from the chunks next to them
+        let mut chopper = AlignmentChopper::new(20, 0, ClipPolicy::Attach, None);
+        chopper.chop_read(&rec);
+        assert_eq!(chopper.cut_ends(), vec![(false, false)]);
+        let mut chopper = AlignmentChopper::new(20, 0, ClipPolicy::Separate, None);
+        chopper.chop_read(&rec);
+        assert_eq!(chopper.cut_ends(), vec![(false, true), (true, false)]);
+    }
+
+    #[test]
+    fn chunk_index_test() {
+        // Chunks are numbered by where they start in the read, counting the leading clip whatever becomes of it
+        let cigar = CigarString(vec![Cigar::SoftClip(2), Cigar::Match(3), Cigar::Ins(4), Cigar::Match(7)]);
+        let rec = make_record("test", "ACGTACGTACGTACGT", "IIIIIIIII\x05\x05\x05\x05\x05II", &cigar, 100);
+        let names = |chopper: &mut AlignmentChopper| chopper.chop_read(&rec).iter().map(|r| String::from_utf8(r.qname().to_vec()).unwrap()).collect::<Vec<_>>();
+
+        for clip_policy in [ClipPolicy::Drop, ClipPolicy::Attach] {
+            let mut chopper = AlignmentChopper::new(5, 0, clip_policy, None);
+            chopper.add_strategy(Box::new(IndelSplit { min_len: 4 }));
+            assert_eq!(names(&mut chopper), vec!["test-1", "test-2", "test-3"]);
+        }
+        let mut chopper = AlignmentChopper::new(5, 0, ClipPolicy::Separate, None);
+        chopper.add_strategy(Box::new(IndelSplit { min_len: 4 }));
+        assert_eq!(names(&mut chopper), vec!["test-0", "test-1", "test-2", "test-3"]);
+
+        // Dropping a chunk from the middle leaves the others their names
+        chopper.set_min_mean_qual(Some(20.0));
+        assert_eq!(names(&mut chopper), vec!["test-0", "test-1", "test-3"]);
+        chopper.set_max_chunks(Some(3));
+        assert_eq!(names(&mut chopper), vec!["test-0", "test-1"]);
+    }
+
+    #[test]
+    fn split_at_sa_test() {
+        // The first 40 bases align to the reverse strand elsewhere, flipping to the end of that alignment's
+        // query, and the last 30 to the forward strand
+        let cigar = CigarString(vec![Cigar::Match(100)]);
+        let mut rec = make_record("test", &"ACGT".repeat(25), &"I".repeat(100), &cigar, 1000);
+        rec.push_aux(b"SA", Aux::String("chr2,500,-,60S40M,60,0;chr3,100,+,70S30M,60,0;")).unwrap();
+
+        // Each segment is chopped by size on its own, leaving a short chunk before every SA breakpoint
+        let mut chopper = AlignmentChopper::new(25, 0, ClipPolicy::Attach, None);
+        chopper.add_strategy(Box::new(SupplementarySplit));
+        let chunks = chopper.chop_read(&rec).iter().map(|r| (r.pos(), r.cigar().to_string())).collect::<Vec<_>>();
+        assert_eq!(chunks, vec![
+            (1000, String::from("25M")),
+            (1025, String::from("15M")),
+            (1040, String::from("25M")),
+            (1065, String::from("5M")),
+            (1070, String::from("25M")),
+            (1095, String::from("5M")),
+        ]);
+        assert_eq!(chopper.query_ranges(), vec![0..25, 25..40, 40..65, 65..70, 70..95, 95..100]);
+
+        // Supplementary records are left to the size alone
+        rec.set_supplementary();
+        assert_eq!(chopper.chop_read(&rec).len(), 4);
+    }
+
+    #[test]
+    fn chunk_junctions_test() {
+        let cigar = CigarString(vec![Cigar::Match(10), Cigar::Ins(4), Cigar::Match(10)]);
+        let rec = make_record("test", &"ACGT".repeat(6), &format!("{}{}{}", "I".repeat(14), "\x05".repeat(5), "I".repeat(5)), &cigar, 100);
+
+        // Chunks cut apart at a skipped op don't meet, and those next to filtered chunks have nothing to meet
+        let mut chopper = AlignmentChopper::new(5, 0, ClipPolicy::Attach, None);
+        chopper.add_strategy(Box::new(IndelSplit { min_len: 4 }));
+        chopper.chop_read(&rec);
+        assert_eq!(chopper.junctions(), &vec![105, 115]);
+        assert_eq!(chopper.chunk_junctions(), vec![(105, 1), (115, 3)]);
+        chopper.set_min_mean_qual(Some(20.0));
+        chopper.chop_read(&rec);
+        assert_eq!(chopper.chunk_junctions(), vec![(105, 1)]);
+    }
+
+    #[test]
+    fn dropout_test() {
+        let cigar = CigarString(vec![Cigar::Match(100)]);
+        let rec = make_record("test", &"ACGT".repeat(25), &"I".repeat(100), &cigar, 100);
+        let chop = |rate: f64, seed: u64, chunk_size: u32| {
+            let mut chopper = AlignmentChopper::new(chunk_size, 0, ClipPolicy::Attach, None);
+            chopper.set_dropout(rate, Some(seed));
+            let names = chopper.chop_read(&rec).iter().map(|r| String::from_utf8(r.qname().to_vec()).unwrap()).collect::<Vec<_>>();
+            (names, chopper.dropped_chunks())
+        };
+
+        let (names, dropped) = chop(0.5, 7, 10);
+        assert!(!names.is_empty() && names.len() < 10);
+        assert_eq!(names.len() as u64 + dropped, 10);
+        // Survivors keep the names of their place in the read
+        assert!(names.iter().all(|name| (0..10).any(|i| *name == format!("test-{}", i))));
+        assert_eq!(chop(0.5, 7, 10), (names, dropped));
+
+        // The only chunk, or the last survivor, is never dropped
+        assert_eq!(chop(1.0, 7, 100).0.len(), 1);
+        assert_eq!(chop(1.0, 7, 10).0.len(), 1);
+    }
+
+    #[test]
+    fn parent_mark_test() {
+        let cigar = CigarString(vec![Cigar::Match(10)]);
+        let rec = make_record("test", "AGTCGATGCA", "?!/??50(?/", &cigar, 100);
+
+        let mut parent = rec.clone();
+        ParentMark::None.apply(&mut parent);
+        assert_eq!(parent, rec);
+        ParentMark::Tag.apply(&mut parent);
+        assert_eq!(parent.aux(b"pa").unwrap(), Aux::I32(1));
+        ParentMark::Secondary.apply(&mut parent);
+        assert!(parent.is_secondary());
+    }
+
+    #[test]
+    fn long_cigar_test() {
+        // More operations than BAM's CIGAR field holds, which htslib moves into CG when writing
+        let ops = (0..70000).map(|i| if i % 2 == 0 { Cigar::Match(1) } else { Cigar::Ins(1) }).collect::<Vec<_>>();
+        let cigar = CigarString(ops);
+        let rec = make_record("long", &"A".repeat(70000), &"I".repeat(70000), &cigar, 100);
+        let mut chopper = AlignmentChopper::new(70000, 0, ClipPolicy::Attach, None);
+        let chunks = chopper.chop_read(&rec).clone();
+        assert_eq!(chunks.len(), 1);
+
+        let dir = std::env::temp_dir().join(format!("chop_reads_long_cigar_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("long.bam");
+        let mut header = bam::Header::new();
+        header.push_record(bam::header::HeaderRecord::new(b"SQ").push_tag(b"SN", "chr1").push_tag(b"LN", 100000));
+        let mut writer = bam::Writer::from_path(&path, &header, bam::Format::Bam).unwrap();
+        let mut chunk = chunks[0].clone();
+        chunk.set_tid(0);
+        writer.write(&chunk).unwrap();
+        drop(writer);
+
+        let mut reader = bam::Reader::from_path(&path).unwrap();
+        let read_back = reader.records().next().unwrap().unwrap();
+        assert_eq!(read_back.cigar().take(), chunks[0].cigar().take());
+        assert!(read_back.aux(b"CG").is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn mapq_policy_test() {
+        assert_eq!("cap:30".parse::<MapqPolicy>(), Ok(MapqPolicy::Cap(30)));
+        assert_eq!("length-scaled".parse::<MapqPolicy>(), Ok(MapqPolicy::LengthScaled));
+        assert!("cap:x".parse::<MapqPolicy>().is_err());
+        assert!("scaled".parse::<MapqPolicy>().is_err());
+
+        let mut chopper = AlignmentChopper::new(10, 0, ClipPolicy::Attach, None);
+        chopper.set_mapq_policy(MapqPolicy::LengthScaled);
+
+        let cigar = CigarString(vec![Cigar::Match(15)]);
+        let rec = make_record("test", "AGTCGATGCATGCAT", "IIIIIIIIIIIIIII", &cigar, 100);
+
+        let mapqs: Vec<u8> = chopper.chop_read(&rec).iter().map(|r| r.mapq()).collect();
+        assert_eq!(mapqs, vec![60, 30]);
+
+        assert_eq!(MapqPolicy::Cap(20).apply(60, 10, 10), 20);
+        assert_eq!(MapqPolicy::Cap(20).apply(255, 10, 10), 255);
+        assert_eq!(MapqPolicy::Zero.apply(255, 10, 10), 0);
+    }
+
+    #[test]
+    fn chunk_size_dist_test() {
+        let cigar = CigarString(vec![Cigar::Match(40)]);
+        let rec = make_record("test", &"ACGT".repeat(10), &"I".repeat(40), &cigar, 100);
+        let lengths = |seed: u64| {
+            let mut chopper = AlignmentChopper::new(0, 0, ClipPolicy::Attach, None);
+            chopper.add_strategy(Box::new(RandomSize::new(ChunkSizeDist::Uniform(2, 6), Some(seed))));
+            chopper.chop_read(&rec).iter().map(|r| r.seq_len()).collect::<Vec<_>>()
+        };
+        // The same seed gives the same sizes, within the distribution's range
+        let sizes = lengths(7);
+        assert_eq!(sizes, lengths(7));
+        assert_eq!(sizes.iter().sum::<usize>(), 40);
+        assert!(sizes[..sizes.len() - 1].iter().all(|size| (2..=6).contains(size)));
+        assert!(sizes.iter().any(|&size| size != sizes[0]));
+    }
+
+    #[test]
+    fn jitter_test() {
+        let cigar = CigarString(vec![Cigar::Match(40)]);
+        let rec = make_record("test", &"ACGT".repeat(10), &"I".repeat(40), &cigar, 100);
+        let ends = |seed: u64| {
+            let mut chopper = AlignmentChopper::new(10, 0, ClipPolicy::Attach, None);
+            chopper.add_strategy(Box::new(Jitter::new(3, Some(seed))));
+            chopper.chop_read(&rec).iter().map(|r| r.cigar().end_pos()).collect::<Vec<_>>()
+        };
+        // Boundaries stay within 3 bases of multiples of 10, and the same seed shifts them the same way
+        let chunk_ends = ends(11);
+        assert_eq!(chunk_ends, ends(11));
+        assert_eq!(*chunk_ends.last().unwrap(), 140);
+        assert!(chunk_ends.iter().all(|end| (end + 3) % 10 <= 6));
+        assert!(chunk_ends.iter().any(|end| end % 10 != 0));
+    }
+
+    #[test]
+    fn split_at_n_test() {
+        let mut chopper = AlignmentChopper::new(4, 2, ClipPolicy::Attach, None);
+        chopper.add_strategy(Box::new(RefSkipSplit));
+        let cigar = CigarString(vec![Cigar::Match(5), Cigar::RefSkip(100), Cigar::Match(5), Cigar::Del(2), Cigar::Match(5)]);
+        let rec = make_record("test", &"ACGTA".repeat(3), &"I".repeat(15), &cigar, 100);
+
+        // The piece left before the N is too short to keep, and no chunk spans the N
+        let chunks = chopper.chop_read(&rec).iter().map(|r| (r.pos(), r.cigar().to_string(), r.seq_len())).collect::<Vec<_>>();
+        assert_eq!(chunks, vec![
+            (100, String::from("4M"), 4),
+            (205, String::from("4M"), 4),
+            (209, String::from("1M2D3M"), 4),
+            (215, String::from("2M"), 2),
+        ]);
+
+        // Without a chunk size, records are only cut at the N
+        let mut chopper = AlignmentChopper::new(0, 0, ClipPolicy::Attach, None);
+        chopper.add_strategy(Box::new(RefSkipSplit));
+        let chunks = chopper.chop_read(&rec).iter().map(|r| (r.pos(), r.cigar().to_string())).collect::<Vec<_>>();
+        assert_eq!(chunks, vec![(100, String::from("5M")), (205, String::from("5M2D5M"))]);
+    }
+
+    #[test]
+    fn split_at_indel_test() {
+        let mut chopper = AlignmentChopper::new(0, 0, ClipPolicy::Attach, None);
+        chopper.add_strategy(Box::new(IndelSplit { min_len: 3 }));
+        let cigar = CigarString(vec![Cigar::Match(4), Cigar::Ins(3), Cigar::Match(2), Cigar::Del(2), Cigar::Match(2), Cigar::Del(5), Cigar::Match(3)]);
+        let rec = make_record("test", "ACGTAAACCGGTTA", &"I".repeat(14), &cigar, 100);
+
+        // Only the indels of 3 or more bases split the record, and their bases are in neither chunk
+        let chunks = chopper.chop_read(&rec).iter().map(|r| (r.pos(), r.cigar().to_string(), r.seq().as_bytes())).collect::<Vec<_>>();
+        assert_eq!(chunks, vec![
+            (100, String::from("4M"), b"ACGT".to_vec()),
+            (104, String::from("2M2D2M"), b"CCGG".to_vec()),
+            (115, String::from("3M"), b"TTA".to_vec()),
+        ]);
+    }
+
+    #[test]
+    fn split_at_lowq_test() {
+        // Windows of 3 bases from 3 to 9 have mean quality below 50, so those bases form chunks of their own
+        let cigar = CigarString(vec![Cigar::Match(12)]);
+        let rec = make_record("test", "ACGTACGTACGT", "IIII!!!!IIII", &cigar, 100);
+        let mut chopper = AlignmentChopper::new(4, 0, ClipPolicy::Attach, None);
+        chopper.add_strategy(Box::new(LowQualityStretch { qual: 50.0, len: 3 }));
+        let chunks = chopper.chop_read(&rec).iter().map(|r| (r.pos(), r.seq_len())).collect::<Vec<_>>();
+        assert_eq!(chunks, vec![(100, 3), (103, 4), (107, 2), (109, 3)]);
+    }
+
+    #[test]
+    fn qual_trim_test() {
+        assert_eq!(qual_trim_len([2, 2, 40, 2].iter(), 20), 2);
+        assert_eq!(qual_trim_len([30, 2, 2].iter(), 20), 0);
+
+        // The trimmed start takes the deletion after it, so the chunk starts on the next aligned base
+        let mut chopper = AlignmentChopper::new(10, 0, ClipPolicy::Attach, None);
+        chopper.set_qual_trim(Some(40));
+        let cigar = CigarString(vec![Cigar::Match(3), Cigar::Del(2), Cigar::Match(5)]);
+        let rec = make_record("test", "ACGTACGT", "!!!IIII!", &cigar, 100);
+        let chunks = chopper.chop_read(&rec).iter().map(|r| (r.pos(), r.cigar().to_string(), r.seq().as_bytes())).collect::<Vec<_>>();
+        assert_eq!(chunks, vec![(105, String::from("4M"), b"TACG".to_vec())]);
+    }
+
+    #[test]
+    fn clip_policy_test() {
+        let cigar = CigarString(vec![Cigar::HardClip(1), Cigar::SoftClip(2), Cigar::Match(6), Cigar::SoftClip(3)]);
+        let rec = make_record("test", "ACGTACGTACG", &"I".repeat(11), &cigar, 100);
+        let chunks = |chopper: &mut AlignmentChopper| chopper.chop_read(&rec).iter().map(|r| {
+            (String::from_utf8(r.qname().to_vec()).unwrap(), r.is_unmapped(), r.pos(), r.cigar().to_string(), r.seq().as_bytes())
+        }).collect::<Vec<_>>();
+
+        assert_eq!(chunks(&mut AlignmentChopper::new(3, 0, ClipPolicy::Attach, None)), vec![
+            (String::from("test-1"), false, 100, String::from("1H2S3M"), b"ACGTA".to_vec()),
+            (String::from("test-2"), false, 103, String::from("3M3S"), b"CGTACG".to_vec()),
+        ]);
+
+        assert_eq!(chunks(&mut AlignmentChopper::new(3, 0, ClipPolicy::Drop, None)), vec![
+            (String::from("test-1"), false, 100, String::from("3M"), b"GTA".to_vec()),
+            (String::from("test-2"), false, 103, String::from("3M"), b"CGT".to_vec()),
+        ]);
+
+        // Separate clips take the numbers the others leave them, unmapped at the parent's position
+        assert_eq!(chunks(&mut AlignmentChopper::new(3, 0, ClipPolicy::Separate, None)), vec![
+            (String::from("test-0"), true, 100, String::new(), b"AC".to_vec()),
+            (String::from("test-1"), false, 100, String::from("3M"), b"GTA".to_vec()),
+            (String::from("test-2"), false, 103, String::from("3M"), b"CGT".to_vec()),
+            (String::from("test-3"), true, 100, String::new(), b"ACG".to_vec()),
+        ]);
+
+        // Hard clips stay only with the soft clips attached to the chunks
+        let cigar = CigarString(vec![Cigar::HardClip(2), Cigar::SoftClip(3), Cigar::Match(10), Cigar::SoftClip(2), Cigar::HardClip(1)]);
+        let rec = make_record("test", "ACGTACGTACGTACG", &"I".repeat(15), &cigar, 100);
+        let cigars = |clip_policy: ClipPolicy| AlignmentChopper::new(5, 0, clip_policy, None).chop_read(&rec).iter().map(|r| r.cigar().to_string()).collect::<Vec<_>>();
+        assert_eq!(cigars(ClipPolicy::Attach), vec!["2H3S5M", "5M2S1H"]);
+        assert_eq!(cigars(ClipPolicy::Drop), vec!["5M", "5M"]);
+        assert_eq!(cigars(ClipPolicy::Separate), vec!["", "5M", "5M", ""]);
+    }
+
+    #[test]
+    fn clip_mode_test() {
+        let mut chopper = AlignmentChopper::new(4, 0, ClipPolicy::Attach, None);
+        chopper.set_clip_mode(ClipMode::Hard);
+        let cigar = CigarString(vec![Cigar::HardClip(3), Cigar::SoftClip(2), Cigar::Match(8)]);
+        let rec = make_record("test", "ACGTACGTAC", &"I".repeat(10), &cigar, 100);
+
+        // Every chunk clips the 13 bases of the parent down to its own, counting the parent's hard clip
+        let chunks = chopper.chop_read(&rec).iter().map(|r| (r.pos(), r.cigar().to_string(), r.seq().as_bytes())).collect::<Vec<_>>();
+        assert_eq!(chunks, vec![
+            (100, String::from("3H2S4M4H"), b"ACGTAC".to_vec()),
+            (104, String::from("9H4M"), b"GTAC".to_vec()),
+        ]);
+
+        // Clipped bases skipped from the chunks are hard clipped too
+        let mut chopper = AlignmentChopper::new(4, 0, ClipPolicy::Drop, None);
+        chopper.set_clip_mode(ClipMode::Hard);
+        assert_eq!(chopper.chop_read(&rec).iter().map(|r| r.cigar().to_string()).collect::<Vec<_>>(), vec!["5H4M4H", "9H4M"]);
+    }
+
+    #[test]
+    fn chunk_trim_test() {
+        let mut chopper = AlignmentChopper::new(5, 0, ClipPolicy::Attach, None);
+        chopper.set_chunk_trim(1);
+        let cigar = CigarString(vec![Cigar::Match(6), Cigar::Del(2), Cigar::Match(6)]);
+        let rec = make_record("test", "ACGTACGTACGT", &"I".repeat(12), &cigar, 100);
+
+        // The last chunk of 2 bases has nothing left once trimmed, and the deletion goes with the trimmed base before it
+        let chunks = chopper.chop_read(&rec).iter().map(|r| (String::from_utf8(r.qname().to_vec()).unwrap(), r.pos(), r.cigar().to_string(), r.seq().as_bytes())).collect::<Vec<_>>();
+        assert_eq!(chunks, vec![
+            (String::from("test-0"), 101, String::from("3M"), b"CGT".to_vec()),
+            (String::from("test-1"), 108, String::from("3M"), b"GTA".to_vec()),
+        ]);
+    }
+
+    #[test]
+    fn max_ref_span_test() {
+        let mut chopper = AlignmentChopper::new(10, 0, ClipPolicy::Attach, None);
+        chopper.set_max_ref_span(Some(8));
+        let cigar = CigarString(vec![Cigar::Match(4), Cigar::Del(20), Cigar::Match(10)]);
+        let rec = make_record("test", "ACGTACGTACGTAC", &"I".repeat(14), &cigar, 100);
+
+        // The deletion is split every 8 reference bases, and the chunks of nothing but deletion are dropped
+        let chunks = chopper.chop_read(&rec).iter().map(|r| (String::from_utf8(r.qname().to_vec()).unwrap(), r.pos(), r.cigar().to_string())).collect::<Vec<_>>();
+        assert_eq!(chunks, vec![
+            (String::from("test-0"), 100, String::from("4M")),
+            (String::from("test-1"), 124, String::from("8M")),
+            (String::from("test-2"), 132, String::from("2M")),
+        ]);
+    }
+
+    #[test]
+    fn deletion_policy_test() {
+        let mut chopper = AlignmentChopper::new(5, 0, ClipPolicy::Attach, None);
+        chopper.set_chunk_by(ChunkBy::Tile);
+        let cigar = CigarString(vec![Cigar::Match(4), Cigar::Del(12), Cigar::Match(6)]);
+        let rec = make_record("test", "ACGTACGTAC", &"I".repeat(10), &cigar, 100);
+        let chunks = |chopper: &mut AlignmentChopper| chopper.chop_read(&rec).iter().map(|r| (r.pos(), r.cigar().to_string())).collect::<Vec<_>>();
+
+        // A deletion spanning tiles is split at their edges
+        assert_eq!(chunks(&mut chopper), vec![(100, String::from("4M1D")), (105, String::from("5D")), (110, String::from("5D")), (115, String::from("1D4M")), (120, String::from("2M"))]);
+
+        // Or left out of the chunks either side, along with the tiles it covers whole
+        chopper.set_deletion_policy(DeletionPolicy::Drop);
+        assert_eq!(chunks(&mut chopper), vec![(100, String::from("4M")), (116, String::from("4M")), (120, String::from("2M"))]);
+    }
+
+    #[test]
+    fn passthrough_test() {
+        let mut chopper = AlignmentChopper::new(4, 4, ClipPolicy::Attach, None);
+        chopper.set_passthrough_below(Some(10));
+        let cigar = CigarString(vec![Cigar::Match(6)]);
+        let rec = make_record("test", "ACGTAC", "IIIIII", &cigar, 100);
+        assert!(chopper.chop_read(&rec).is_empty());
+        assert!(chopper.passed_through());
+
+        let cigar = CigarString(vec![Cigar::Match(12)]);
+        let rec = make_record("test", "ACGTACGTACGT", &"I".repeat(12), &cigar, 100);
+        assert_eq!(chopper.chop_read(&rec).len(), 3);
+        assert!(!chopper.passed_through());
+    }
+
+    #[test]
+    fn max_chunks_test() {
+        let mut chopper = AlignmentChopper::new(4, 0, ClipPolicy::Attach, None);
+        chopper.set_max_chunks(Some(2));
+        let cigar = CigarString(vec![Cigar::Match(14)]);
+        let mut rec = make_record("test", "ACGTACGTACGTAC", &"I".repeat(14), &cigar, 100);
+        let names = |chopper: &mut AlignmentChopper, rec: &Record| chopper.chop_read(rec).iter().map(|r| String::from_utf8(r.qname().to_vec()).unwrap()).collect::<Vec<_>>();
+        assert_eq!(names(&mut chopper, &rec), vec!["test-0", "test-1"]);
+
+        // The 5' end of a reverse strand read is its last chunk
+        rec.set_reverse();
+        assert_eq!(names(&mut chopper, &rec), vec!["test-2", "test-3"]);
+    }
+
+    #[test]
+    fn chunk_size_table_test() {
+        let mut chopper = AlignmentChopper::new(0, 0, ClipPolicy::Attach, None);
+        chopper.add_strategy(Box::new("0:2,10:5".parse::<ChunkSizeTable>().unwrap()));
+        let sizes = |chopper: &mut AlignmentChopper, len: usize| {
+            let cigar = CigarString(vec![Cigar::Match(len as u32)]);
+            let rec = make_record("test", &"A".repeat(len), &"I".repeat(len), &cigar, 100);
+            chopper.chop_read(&rec).iter().map(|r| r.seq_len()).collect::<Vec<_>>()
+        };
+        assert_eq!(sizes(&mut chopper, 6), vec![2, 2, 2]);
+        assert_eq!(sizes(&mut chopper, 12), vec![5, 5, 2]);
+    }
+
+    #[test]
+    fn add_strategy_test() {
+        // Splits every chunk after its first base, on top of the chunk size
+        #[derive(Debug, Clone)]
+        struct FirstBase;
+        impl ChopStrategy for FirstBase {
+            fn query_cuts(&self, _rec: &Record) -> Vec<usize> {
+                vec![1]
+            }
+        }
+
+        let mut chopper = AlignmentChopper::new(4, 0, ClipPolicy::Attach, None);
+        chopper.add_strategy(Box::new(FirstBase));
+        chopper.add_strategy(Box::new(RefSkipSplit));
+        let cigar = CigarString(vec![Cigar::Match(6), Cigar::RefSkip(10), Cigar::Match(4)]);
+        let rec = make_record("test", "ACGTACGTAC", &"I".repeat(10), &cigar, 100);
+        let chunks = chopper.clone().chop_read(&rec).iter().map(|r| (r.pos(), r.cigar().to_string())).collect::<Vec<_>>();
+        assert_eq!(chunks, vec![(100, String::from("1M")), (101, String::from("4M")), (105, String::from("1M")), (116, String::from("4M"))]);
+    }
+
+    #[test]
+    fn unaligned_record_test() {
+        let mut chopper = AlignmentChopper::new(5, 0, ClipPolicy::Attach, None);
+
+        let mut rec = Record::default();
+        rec.set(b"test", None, b"AGTCGATG", b"?!/??50(");
+        rec.set_tid(-1);
+        rec.set_pos(-1);
+        rec.set_flags(4);
+
+        let chunks = chopper.chop_read(&rec);
+        assert_eq!(chunks.len(), 2);
+        assert_eq!((chunks[0].qname(), chunks[0].seq().as_bytes(), chunks[0].cigar().len()), (&b"test-0"[..], b"AGTCG".to_vec(), 0));
+        assert_eq!((chunks[1].qname(), chunks[1].seq().as_bytes(), chunks[1].pos()), (&b"test-1"[..], b"ATG".to_vec(), -1));
+
+        let mut empty = Record::default();
+        empty.set(b"empty", None, b"", b"");
+        empty.set_flags(4);
+        assert!(chopper.chop_read(&empty).is_empty());
+        assert_eq!(chopper.last_rejection(), Some(RejectReason::EmptyRecord));
+        assert_eq!(chopper.empty_records(), 1);
+    }
+
+    #[test]
+    fn keep_tags_test() {
+        let mut chopper = AlignmentChopper::new(5, 0, ClipPolicy::Attach, Some(String::from("new")));
+        chopper.set_keep_tags(true);
+
+        let cigar = CigarString(vec![Cigar::Match(8)]);
+        let mut rec = make_record("test", "AGTCGATG", "?!/??50(", &cigar, 100);
+        rec.push_aux(b"RG", Aux::String("old")).unwrap();
+        rec.push_aux(b"MM", Aux::String("C+m,0;")).unwrap();
+        rec.push_aux(b"ML", Aux::ArrayU8((&[200u8][..]).into())).unwrap();
+        rec.push_aux(b"ch", Aux::I32(7)).unwrap();
+
+        let chunks = chopper.chop_read(&rec);
+        for chunk in chunks {
+            assert_eq!(chunk.aux(b"ch").unwrap(), Aux::I32(7));
+            assert_eq!(chunk.aux(b"RG").unwrap(), Aux::String("new"));
+            assert!(chunk.aux(b"MM").is_err());
+            assert!(chunk.aux(b"ML").is_err());
+        }
+        assert_eq!(chopper.stripped_tag_chunks(), 2);
+    }
+
+    #[test]
+    fn mate_tags_test() {
+        let mut chopper = AlignmentChopper::new(5, 0, ClipPolicy::Attach, None);
+        chopper.set_keep_tags(true);
+        chopper.set_mapq_policy(MapqPolicy::Cap(20));
+
+        let cigar = CigarString(vec![Cigar::Match(8)]);
+        let mut rec = make_record("test", "AGTCGATG", "?!/??50(", &cigar, 100);
+        rec.push_aux(b"MC", Aux::String("8M")).unwrap();
+        rec.push_aux(b"MQ", Aux::U8(60)).unwrap();
+
+        for chunk in chopper.chop_read(&rec) {
+            assert!(chunk.aux(b"MC").is_err());
+            assert_eq!(chunk.aux(b"MQ").unwrap(), Aux::I32(20));
+        }
+
+        chopper.set_mapq_policy(MapqPolicy::LengthScaled);
+        for chunk in chopper.chop_read(&rec) {
+            assert!(chunk.aux(b"MQ").is_err());
+        }
+    }
+
+    #[test]
+    fn chunk_views_test() {
+        let mut chopper = AlignmentChopper::new(5, 0, ClipPolicy::Attach, None);
+
+        let cigar = CigarString(vec![Cigar::Match(4), Cigar::Del(5), Cigar::Match(2), Cigar::Ins(4), Cigar::SoftClip(3)]);
+        let rec = make_record("test", "AGTCGATGCATGC", "?!/??50(?/321", &cigar, 100);
+
+        let records = chopper.chop_read(&rec).clone();
+        let views = chopper.chunk_views(&rec);
+        assert_eq!(views.len(), records.len());
+        for (view, record) in views.iter().zip(&records) {
+            assert_eq!(view.qname(), record.qname());
+            assert_eq!(view.cigar(), Some(&record.cigar().take()));
+            assert_eq!((view.tid(), view.pos()), (record.tid(), record.pos()));
+            assert_eq!(view.seq(), record.seq().as_bytes());
+            assert_eq!(view.qual(), record.qual());
+        }
+        assert_eq!(views[1].index(), 1);
+        assert_eq!(views[1].query_range(), 5..13);
+        assert_eq!(chopper.query_ranges()[1], 5..13);
     }
 
     #[test]
@@ -0,0 +1,523 @@
+//! Low-level helpers for splitting CIGAR strings in query and reference coordinates.
+//!
+//! Split points follow the convention used by the chopper: operators that consume neither
+//! coordinate being split on (e.g. a deletion when splitting on query bases) that sit exactly on
+//! a split point are placed on the right-hand side.
+
+use rust_htslib::bam::record::{Aux, Cigar, CigarString};
+use rust_htslib::bam::Record;
+
+/// The result of consuming part of a single CIGAR operator
+#[derive(Debug, PartialEq, Eq)]
+pub struct SplitCigarBuf {
+    /// The consumed portion of the operator
+    pub left_c: Cigar,
+    /// The unconsumed remainder, if the operator was split
+    pub right_c: Option<Cigar>,
+    /// Query bases consumed by `left_c`
+    pub query_offset: u32,
+    /// Reference bases consumed by `left_c`
+    pub ref_offset: i64,
+}
+
+impl SplitCigarBuf {
+    pub fn new(left_c: Cigar, right_c: Option<Cigar>, query_offset: u32, ref_offset: i64) -> Self {
+        Self {
+            left_c,
+            right_c,
+            query_offset,
+            ref_offset
+        }
+    }
+}
+
+/// Consume up to `amount` query bases from a CIGAR operator.
+///
+/// Operators that don't consume query bases are always consumed whole.
+pub fn consume_cigar(c: &Cigar, amount: u32) -> SplitCigarBuf {
+    match c {
+        Cigar::Match(x) => {
+            let (left_c, right_c, consumed) = if amount < *x {
+                (Cigar::Match(amount), Some(Cigar::Match(*x - amount)), amount)
+            } else {
+                (Cigar::Match(*x), None, *x)
+            };
+            SplitCigarBuf::new(left_c, right_c, consumed, consumed as i64)
+        },
+        Cigar::Ins(x) => {
+            let (left_c, right_c, consumed) = if amount < *x {
+                (Cigar::Ins(amount), Some(Cigar::Ins(*x - amount)), amount)
+            } else {
+                (Cigar::Ins(*x), None, *x)
+            };
+            SplitCigarBuf::new(left_c, right_c, consumed, 0i64)
+        },
+        Cigar::Del(x) => {
+            SplitCigarBuf::new(Cigar::Del(*x), None, 0, *x as i64)
+        },
+        Cigar::RefSkip(x) => {
+            SplitCigarBuf::new(Cigar::RefSkip(*x), None, 0, *x as i64)
+        },
+        Cigar::SoftClip(x) => {
+            let (left_c, right_c, consumed) = if amount < *x {
+                (Cigar::SoftClip(amount), Some(Cigar::SoftClip(*x - amount)), amount)
+            } else {
+                (Cigar::SoftClip(*x), None, *x)
+            };
+            SplitCigarBuf::new(left_c, right_c, consumed, 0i64)
+        },
+        Cigar::HardClip(x) => {
+            SplitCigarBuf::new(Cigar::HardClip(*x), None, 0, 0i64)
+        },
+        Cigar::Pad(x) => {
+            SplitCigarBuf::new(Cigar::Pad(*x), None, 0, 0i64)
+        },
+        Cigar::Equal(x) => {
+            let (left_c, right_c, consumed) = if amount < *x {
+                (Cigar::Equal(amount), Some(Cigar::Equal(*x - amount)), amount)
+            } else {
+                (Cigar::Equal(*x), None, *x)
+            };
+            SplitCigarBuf::new(left_c, right_c, consumed, consumed as i64)
+        }
+        Cigar::Diff(x) => {
+            let (left_c, right_c, consumed) = if amount < *x {
+                (Cigar::Diff(amount), Some(Cigar::Diff(*x - amount)), amount)
+            } else {
+                (Cigar::Diff(*x), None, *x)
+            };
+            SplitCigarBuf::new(left_c, right_c, consumed, consumed as i64)
+        }
+    }
+}
+
+/// Consume up to `amount` reference bases from a CIGAR operator.
+///
+/// Operators that don't consume reference bases are always consumed whole.
+pub fn consume_cigar_ref(c: &Cigar, amount: i64) -> SplitCigarBuf {
+    let x = c.len() as i64;
+    let query = consumes_query(c);
+    match c {
+        Cigar::Match(_) | Cigar::Del(_) | Cigar::RefSkip(_) | Cigar::Equal(_) | Cigar::Diff(_) if amount < x => {
+            let consumed = amount as u32;
+            let query_consumed = if query { consumed } else { 0 };
+            SplitCigarBuf::new(with_len(c, consumed), Some(with_len(c, c.len() - consumed)), query_consumed, amount)
+        },
+        _ => {
+            let ref_consumed = if consumes_ref(c) { x } else { 0 };
+            let query_consumed = if query { c.len() } else { 0 };
+            SplitCigarBuf::new(*c, None, query_consumed, ref_consumed)
+        }
+    }
+}
+
+/// Whether the operator consumes query bases
+pub fn consumes_query(c: &Cigar) -> bool {
+    matches!(c, Cigar::Match(_) | Cigar::Ins(_) | Cigar::SoftClip(_) | Cigar::Equal(_) | Cigar::Diff(_))
+}
+
+/// Whether the operator consumes reference bases
+pub fn consumes_ref(c: &Cigar) -> bool {
+    matches!(c, Cigar::Match(_) | Cigar::Del(_) | Cigar::RefSkip(_) | Cigar::Equal(_) | Cigar::Diff(_))
+}
+
+/// Build an operator of the same kind as `c` with a new length
+pub fn with_len(c: &Cigar, len: u32) -> Cigar {
+    match c {
+        Cigar::Match(_) => Cigar::Match(len),
+        Cigar::Ins(_) => Cigar::Ins(len),
+        Cigar::Del(_) => Cigar::Del(len),
+        Cigar::RefSkip(_) => Cigar::RefSkip(len),
+        Cigar::SoftClip(_) => Cigar::SoftClip(len),
+        Cigar::HardClip(_) => Cigar::HardClip(len),
+        Cigar::Pad(_) => Cigar::Pad(len),
+        Cigar::Equal(_) => Cigar::Equal(len),
+        Cigar::Diff(_) => Cigar::Diff(len),
+    }
+}
+
+/// Total query bases consumed by a CIGAR
+pub fn query_len(cigar: &CigarString) -> u32 {
+    cigar.iter().filter(|c| consumes_query(c)).map(|c| c.len()).sum()
+}
+
+/// Total query bases aligned to the reference (M/=/X) by a CIGAR
+pub fn aligned_len(cigar: &CigarString) -> u32 {
+    cigar.iter().filter(|c| consumes_query(c) && consumes_ref(c)).map(|c| c.len()).sum()
+}
+
+/// The real CIGAR of a record whose CIGAR has too many operations for BAM, if it has one.
+///
+/// Such records carry a placeholder CIGAR of kSmN (k the query length, m the reference length) with
+/// the real operations in the CG:B:I tag. htslib expands these when reading BAM, but not SAM.
+pub fn long_cigar(rec: &Record) -> Option<CigarString> {
+    let placeholder = rec.cigar();
+    match (placeholder.first(), placeholder.get(1), placeholder.len()) {
+        (Some(Cigar::SoftClip(k)), Some(Cigar::RefSkip(_)), 2) if *k as usize == rec.seq_len() => {}
+        _ => return None,
+    }
+    let Ok(Aux::ArrayU32(ops)) = rec.aux(b"CG") else {
+        return None;
+    };
+    ops.iter().map(|op| {
+        let len = op >> 4;
+        Some(match op & 0xf {
+            0 => Cigar::Match(len),
+            1 => Cigar::Ins(len),
+            2 => Cigar::Del(len),
+            3 => Cigar::RefSkip(len),
+            4 => Cigar::SoftClip(len),
+            5 => Cigar::HardClip(len),
+            6 => Cigar::Pad(len),
+            7 => Cigar::Equal(len),
+            8 => Cigar::Diff(len),
+            _ => return None,
+        })
+    }).collect::<Option<Vec<_>>>().map(CigarString)
+}
+
+/// BAI bin of the 0-based half-open interval [beg, end), computed as in the SAM spec.
+///
+/// BAM stores this with each record, so records built from scratch need it set to match their position.
+pub fn reg2bin(beg: i64, end: i64) -> u16 {
+    let end = end - 1;
+    for (shift, offset) in [(14, 4681), (17, 585), (20, 73), (23, 9), (26, 1)] {
+        if beg >> shift == end >> shift {
+            return (offset + (beg >> shift)) as u16;
+        }
+    }
+    0
+}
+
+/// Total reference bases consumed by a CIGAR
+pub fn ref_len(cigar: &CigarString) -> i64 {
+    cigar.iter().filter(|c| consumes_ref(c)).map(|c| c.len() as i64).sum()
+}
+
+/// Split a CIGAR after the first `offset` query bases.
+///
+/// Operators not consuming query bases that sit exactly at `offset` go to the right-hand side.
+pub fn split_cigar_at_query(cigar: &CigarString, offset: u32) -> (CigarString, CigarString) {
+    let mut left = CigarString(Vec::new());
+    let mut right = CigarString(Vec::new());
+    let mut remaining = offset;
+
+    for c in cigar.iter() {
+        if remaining == 0 {
+            right.push(*c);
+            continue;
+        }
+        let cigar_consumption = consume_cigar(c, remaining);
+        left.push(cigar_consumption.left_c);
+        remaining -= cigar_consumption.query_offset;
+        if let Some(right_c) = cigar_consumption.right_c {
+            right.push(right_c);
+        }
+    }
+
+    (left, right)
+}
+
+/// Split a CIGAR after the first `offset` query bases aligned to the reference (M/=/X).
+///
+/// Operators aligning no bases that sit exactly at `offset` go to the right-hand side.
+pub fn split_cigar_at_aligned(cigar: &CigarString, offset: u32) -> (CigarString, CigarString) {
+    let mut left = CigarString(Vec::new());
+    let mut right = CigarString(Vec::new());
+    let mut remaining = offset;
+
+    for c in cigar.iter() {
+        if remaining == 0 {
+            right.push(*c);
+        } else if consumes_query(c) && consumes_ref(c) {
+            let cigar_consumption = consume_cigar(c, remaining);
+            left.push(cigar_consumption.left_c);
+            remaining -= cigar_consumption.query_offset;
+            if let Some(right_c) = cigar_consumption.right_c {
+                right.push(right_c);
+            }
+        } else {
+            left.push(*c);
+        }
+    }
+
+    (left, right)
+}
+
+/// Split a CIGAR after the first `offset` reference bases, dividing D/N operators if needed.
+///
+/// Operators not consuming reference bases that sit exactly at `offset` go to the right-hand side.
+pub fn split_cigar_at_ref(cigar: &CigarString, offset: i64) -> (CigarString, CigarString) {
+    let mut left = CigarString(Vec::new());
+    let mut right = CigarString(Vec::new());
+    let mut remaining = offset;
+
+    for c in cigar.iter() {
+        if remaining == 0 {
+            right.push(*c);
+            continue;
+        }
+        let cigar_consumption = consume_cigar_ref(c, remaining);
+        left.push(cigar_consumption.left_c);
+        remaining -= cigar_consumption.ref_offset;
+        if let Some(right_c) = cigar_consumption.right_c {
+            right.push(right_c);
+        }
+    }
+
+    (left, right)
+}
+
+/// Split a CIGAR after its first `query_offset` query and `ref_offset` reference bases together.
+///
+/// Unlike splitting on one coordinate, this finds exactly where a prefix of the CIGAR consuming
+/// that many of each ends, such as a chunk ending on a deletion. Operators consuming neither stay
+/// on the left until both are used up.
+pub fn split_cigar_at(cigar: &CigarString, query_offset: u32, ref_offset: i64) -> (CigarString, CigarString) {
+    let mut left = CigarString(Vec::new());
+    let mut right = CigarString(Vec::new());
+    let (mut query_remaining, mut ref_remaining) = (query_offset, ref_offset);
+
+    for c in cigar.iter() {
+        if !right.is_empty() || (query_remaining == 0 && ref_remaining == 0) {
+            right.push(*c);
+            continue;
+        }
+        let mut take = c.len();
+        if consumes_query(c) {
+            take = take.min(query_remaining);
+        }
+        if consumes_ref(c) {
+            take = take.min(ref_remaining.min(u32::MAX as i64) as u32);
+        }
+        if take > 0 {
+            left.push(with_len(c, take));
+        }
+        if take < c.len() {
+            right.push(with_len(c, c.len() - take));
+        }
+        if consumes_query(c) {
+            query_remaining -= take;
+        }
+        if consumes_ref(c) {
+            ref_remaining -= take as i64;
+        }
+    }
+
+    (left, right)
+}
+
+/// Append `extension` to `cigar`, joining the operators either side of the seam if they're the same kind
+pub fn extend_cigar(cigar: &mut CigarString, extension: &CigarString) {
+    for c in extension.iter() {
+        match cigar.last_mut() {
+            Some(last) if std::mem::discriminant(last) == std::mem::discriminant(c) => *last = with_len(last, last.len() + c.len()),
+            _ => cigar.push(*c),
+        }
+    }
+}
+
+/// Trim a CIGAR so it consumes exactly `seq_len` query bases.
+///
+/// Operators past the end of the sequence are dropped (hard clips are kept), and if the sequence
+/// is longer than the CIGAR the leftover bases are added as a trailing softclip.
+pub fn clip_cigar_to_query_len(cigar: &CigarString, seq_len: u32) -> CigarString {
+    let mut clipped = CigarString(Vec::new());
+    let mut remaining = seq_len;
+
+    for c in cigar.iter() {
+        match c {
+            Cigar::HardClip(_) => clipped.push(*c),
+            _ if remaining == 0 => {},  // Past the end of SEQ
+            _ => {
+                let cigar_consumption = consume_cigar(c, remaining);
+                clipped.push(cigar_consumption.left_c);
+                remaining -= cigar_consumption.query_offset;
+            }
+        }
+    }
+
+    // SEQ is longer than the CIGAR, so softclip the leftover bases
+    if remaining > 0 {
+        let clip_idx = match clipped.last() {
+            Some(Cigar::HardClip(_)) => clipped.len() - 1,
+            _ => clipped.len(),
+        };
+        match clip_idx.checked_sub(1).map(|i| clipped[i]) {
+            Some(Cigar::SoftClip(x)) => clipped[clip_idx - 1] = Cigar::SoftClip(x + remaining),
+            _ => clipped.insert(clip_idx, Cigar::SoftClip(remaining)),
+        }
+    }
+
+    clipped
+}
+
+/// Keep query bases `start` to `end` of a CIGAR, with the reference bases that lie before them.
+///
+/// Operators not consuming query bases next to a trimmed end are trimmed with it, while untrimmed ends are kept whole.
+pub fn trim_cigar_query(cigar: &CigarString, start: u32, end: u32) -> (i64, CigarString) {
+    let cigar = if end < query_len(cigar) { split_cigar_at_query(cigar, end).0 } else { cigar.clone() };
+    if start == 0 {
+        return (0, cigar);
+    }
+    let (trimmed, kept) = split_cigar_at_query(&cigar, start);
+    let leading = kept.iter().take_while(|c| !consumes_query(c)).count();
+    let ref_trimmed = ref_len(&trimmed) + kept.iter().take(leading).filter(|c| consumes_ref(c)).map(|c| c.len() as i64).sum::<i64>();
+    (ref_trimmed, CigarString(kept.0[leading..].to_vec()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_cigar_at_test() {
+        let cigar = CigarString(vec![Cigar::HardClip(1), Cigar::Match(4), Cigar::Del(2), Cigar::Match(6), Cigar::SoftClip(1)]);
+        assert_eq!(split_cigar_at(&cigar, 4, 6), (CigarString(vec![Cigar::HardClip(1), Cigar::Match(4), Cigar::Del(2)]), CigarString(vec![Cigar::Match(6), Cigar::SoftClip(1)])));
+        assert_eq!(split_cigar_at(&cigar, 4, 5), (CigarString(vec![Cigar::HardClip(1), Cigar::Match(4), Cigar::Del(1)]), CigarString(vec![Cigar::Del(1), Cigar::Match(6), Cigar::SoftClip(1)])));
+        assert_eq!(split_cigar_at(&cigar, 11, 12), (cigar.clone(), CigarString(Vec::new())));
+    }
+
+    #[test]
+    fn extend_cigar_test() {
+        let mut cigar = CigarString(vec![Cigar::SoftClip(2), Cigar::Match(3)]);
+        extend_cigar(&mut cigar, &CigarString(vec![Cigar::Match(2), Cigar::Del(1), Cigar::Match(1)]));
+        assert_eq!(cigar, CigarString(vec![Cigar::SoftClip(2), Cigar::Match(5), Cigar::Del(1), Cigar::Match(1)]));
+    }
+
+    #[test]
+    fn reg2bin_test() {
+        assert_eq!(reg2bin(-1, 0), 4680);
+        assert_eq!(reg2bin(100, 110), 4681);
+        assert_eq!(reg2bin(16380, 16390), 585);
+        assert_eq!(reg2bin(1 << 26, (1 << 26) + 1), 4681 + (1 << 12));
+        assert_eq!(reg2bin(0, 1 << 29), 0);
+    }
+
+    #[test]
+    fn consume_cigar_test() {
+        assert_eq!(consume_cigar(&Cigar::Match(10), 4), SplitCigarBuf::new(Cigar::Match(4), Some(Cigar::Match(6)), 4, 4));
+        assert_eq!(consume_cigar(&Cigar::Ins(3), 5), SplitCigarBuf::new(Cigar::Ins(3), None, 3, 0));
+        assert_eq!(consume_cigar(&Cigar::SoftClip(6), 2), SplitCigarBuf::new(Cigar::SoftClip(2), Some(Cigar::SoftClip(4)), 2, 0));
+        assert_eq!(consume_cigar(&Cigar::Del(7), 1), SplitCigarBuf::new(Cigar::Del(7), None, 0, 7));
+        assert_eq!(consume_cigar(&Cigar::HardClip(5), 1), SplitCigarBuf::new(Cigar::HardClip(5), None, 0, 0));
+    }
+
+    #[test]
+    fn consume_cigar_ref_test() {
+        assert_eq!(consume_cigar_ref(&Cigar::Equal(10), 4), SplitCigarBuf::new(Cigar::Equal(4), Some(Cigar::Equal(6)), 4, 4));
+        assert_eq!(consume_cigar_ref(&Cigar::Del(10), 4), SplitCigarBuf::new(Cigar::Del(4), Some(Cigar::Del(6)), 0, 4));
+        assert_eq!(consume_cigar_ref(&Cigar::RefSkip(3), 5), SplitCigarBuf::new(Cigar::RefSkip(3), None, 0, 3));
+        assert_eq!(consume_cigar_ref(&Cigar::Ins(7), 1), SplitCigarBuf::new(Cigar::Ins(7), None, 7, 0));
+        assert_eq!(consume_cigar_ref(&Cigar::SoftClip(2), 1), SplitCigarBuf::new(Cigar::SoftClip(2), None, 2, 0));
+    }
+
+    #[test]
+    fn lengths_test() {
+        let cigar = CigarString(vec![Cigar::HardClip(2), Cigar::SoftClip(3), Cigar::Match(4), Cigar::Del(5), Cigar::Ins(1), Cigar::RefSkip(10), Cigar::Diff(2)]);
+        assert_eq!(query_len(&cigar), 10);
+        assert_eq!(ref_len(&cigar), 21);
+        assert_eq!(aligned_len(&cigar), 6);
+    }
+
+    #[test]
+    fn split_cigar_at_query_test() {
+        let cigar = CigarString(vec![Cigar::SoftClip(2), Cigar::Match(4), Cigar::Del(5), Cigar::Match(3), Cigar::Ins(2)]);
+
+        // Split inside an operator
+        assert_eq!(split_cigar_at_query(&cigar, 3), (
+            CigarString(vec![Cigar::SoftClip(2), Cigar::Match(1)]),
+            CigarString(vec![Cigar::Match(3), Cigar::Del(5), Cigar::Match(3), Cigar::Ins(2)]),
+        ));
+
+        // Deletion on the split point goes right
+        assert_eq!(split_cigar_at_query(&cigar, 6), (
+            CigarString(vec![Cigar::SoftClip(2), Cigar::Match(4)]),
+            CigarString(vec![Cigar::Del(5), Cigar::Match(3), Cigar::Ins(2)]),
+        ));
+
+        // Edges
+        assert_eq!(split_cigar_at_query(&cigar, 0), (CigarString(vec![]), cigar.clone()));
+        assert_eq!(split_cigar_at_query(&cigar, 11), (cigar.clone(), CigarString(vec![])));
+        assert_eq!(split_cigar_at_query(&cigar, 20), (cigar.clone(), CigarString(vec![])));
+    }
+
+    #[test]
+    fn split_cigar_at_aligned_test() {
+        let cigar = CigarString(vec![Cigar::SoftClip(2), Cigar::Match(4), Cigar::Ins(3), Cigar::Del(5), Cigar::Match(3)]);
+
+        // Clips, insertions and deletions don't count
+        assert_eq!(split_cigar_at_aligned(&cigar, 5), (
+            CigarString(vec![Cigar::SoftClip(2), Cigar::Match(4), Cigar::Ins(3), Cigar::Del(5), Cigar::Match(1)]),
+            CigarString(vec![Cigar::Match(2)]),
+        ));
+        assert_eq!(split_cigar_at_aligned(&cigar, 4), (
+            CigarString(vec![Cigar::SoftClip(2), Cigar::Match(4)]),
+            CigarString(vec![Cigar::Ins(3), Cigar::Del(5), Cigar::Match(3)]),
+        ));
+        assert_eq!(split_cigar_at_aligned(&cigar, 0), (CigarString(vec![]), cigar.clone()));
+    }
+
+    #[test]
+    fn split_cigar_at_ref_test() {
+        let cigar = CigarString(vec![Cigar::SoftClip(2), Cigar::Match(4), Cigar::Del(5), Cigar::Ins(2), Cigar::Match(3)]);
+
+        // Split inside a deletion
+        assert_eq!(split_cigar_at_ref(&cigar, 6), (
+            CigarString(vec![Cigar::SoftClip(2), Cigar::Match(4), Cigar::Del(2)]),
+            CigarString(vec![Cigar::Del(3), Cigar::Ins(2), Cigar::Match(3)]),
+        ));
+
+        // Insertion on the split point goes right
+        assert_eq!(split_cigar_at_ref(&cigar, 9), (
+            CigarString(vec![Cigar::SoftClip(2), Cigar::Match(4), Cigar::Del(5)]),
+            CigarString(vec![Cigar::Ins(2), Cigar::Match(3)]),
+        ));
+
+        // Leading softclip stays with the first reference base
+        assert_eq!(split_cigar_at_ref(&cigar, 1), (
+            CigarString(vec![Cigar::SoftClip(2), Cigar::Match(1)]),
+            CigarString(vec![Cigar::Match(3), Cigar::Del(5), Cigar::Ins(2), Cigar::Match(3)]),
+        ));
+
+        assert_eq!(split_cigar_at_ref(&cigar, 0), (CigarString(vec![]), cigar.clone()));
+        assert_eq!(split_cigar_at_ref(&cigar, 12), (cigar.clone(), CigarString(vec![])));
+    }
+
+    #[test]
+    fn split_roundtrip_test() {
+        let cigar = CigarString(vec![Cigar::HardClip(1), Cigar::SoftClip(2), Cigar::Match(4), Cigar::Del(5), Cigar::Ins(2), Cigar::RefSkip(3), Cigar::Equal(3), Cigar::HardClip(4)]);
+        for offset in 0..=query_len(&cigar) {
+            let (left, right) = split_cigar_at_query(&cigar, offset);
+            assert_eq!(query_len(&left), offset);
+            assert_eq!(query_len(&left) + query_len(&right), query_len(&cigar));
+            assert_eq!(ref_len(&left) + ref_len(&right), ref_len(&cigar));
+        }
+        for offset in 0..=ref_len(&cigar) {
+            let (left, right) = split_cigar_at_ref(&cigar, offset);
+            assert_eq!(ref_len(&left), offset);
+            assert_eq!(query_len(&left) + query_len(&right), query_len(&cigar));
+        }
+    }
+
+    #[test]
+    fn clip_cigar_to_query_len_test() {
+        let long_cigar = CigarString(vec![Cigar::Match(4), Cigar::Del(2), Cigar::Match(6), Cigar::HardClip(3)]);
+        assert_eq!(clip_cigar_to_query_len(&long_cigar, 7), CigarString(vec![Cigar::Match(4), Cigar::Del(2), Cigar::Match(3), Cigar::HardClip(3)]));
+        assert_eq!(clip_cigar_to_query_len(&long_cigar, 4), CigarString(vec![Cigar::Match(4), Cigar::HardClip(3)]));
+
+        let short_cigar = CigarString(vec![Cigar::Match(5), Cigar::HardClip(3)]);
+        assert_eq!(clip_cigar_to_query_len(&short_cigar, 7), CigarString(vec![Cigar::Match(5), Cigar::SoftClip(2), Cigar::HardClip(3)]));
+
+        let clipped_cigar = CigarString(vec![Cigar::Match(5), Cigar::SoftClip(1)]);
+        assert_eq!(clip_cigar_to_query_len(&clipped_cigar, 7), CigarString(vec![Cigar::Match(5), Cigar::SoftClip(2)]));
+    }
+
+    #[test]
+    fn trim_cigar_query_test() {
+        let cigar = CigarString(vec![Cigar::HardClip(1), Cigar::Match(3), Cigar::Del(2), Cigar::Match(4), Cigar::HardClip(2)]);
+        assert_eq!(trim_cigar_query(&cigar, 3, 5), (5, CigarString(vec![Cigar::Match(2)])));
+        assert_eq!(trim_cigar_query(&cigar, 1, 3), (1, CigarString(vec![Cigar::Match(2)])));
+        assert_eq!(trim_cigar_query(&cigar, 0, 7), (0, cigar.clone()));
+    }
+}
\ No newline at end of file
@@ -0,0 +1,252 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::ops::Range;
+use std::path::Path;
+use clap::ValueEnum;
+use rust_htslib::bam::record::Aux;
+use rust_htslib::bam::Record;
+use rust_htslib::bgzf;
+
+// IUPAC complement of a base, keeping its case
+fn complement(base: u8) -> u8 {
+    let complement = match base.to_ascii_uppercase() {
+        b'A' => b'T',
+        b'C' => b'G',
+        b'G' => b'C',
+        b'T' | b'U' => b'A',
+        b'R' => b'Y',
+        b'Y' => b'R',
+        b'K' => b'M',
+        b'M' => b'K',
+        b'B' => b'V',
+        b'V' => b'B',
+        b'D' => b'H',
+        b'H' => b'D',
+        other => other,
+    };
+    if base.is_ascii_lowercase() { complement.to_ascii_lowercase() } else { complement }
+}
+
+/// Reverse complement of `seq`
+pub fn reverse_complement(seq: &[u8]) -> Vec<u8> {
+    seq.iter().rev().map(|&base| complement(base)).collect()
+}
+
+/// Bases of `rec` as they were sequenced, undoing the reverse complement applied to reverse strand alignments
+pub fn original_bases(rec: &Record) -> Vec<u8> {
+    let bases = rec.seq().as_bytes();
+    if rec.is_reverse() { reverse_complement(&bases) } else { bases }
+}
+
+/// Base qualities of `rec` as Phred+33 text in their original orientation, or '"' (Q1) if it has none, as samtools fastq does
+pub fn original_qualities(rec: &Record) -> Vec<u8> {
+    let qual = rec.qual();
+    let mut text = if qual.first() == Some(&255) {
+        vec![b'"'; qual.len()]
+    } else {
+        qual.iter().map(|q| q.saturating_add(33)).collect()
+    };
+    if rec.is_reverse() {
+        text.reverse();
+    }
+    text
+}
+
+/// How the bases next to internal chunk boundaries are masked
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum MaskStyle {
+    /// Lowercase them, as soft-masked sequence is
+    Lower,
+    /// Replace them with N
+    N,
+}
+
+// Mask up to `len` bases at the start and end of `bases`, if they're cut from a neighbouring chunk
+fn mask_ends(bases: &mut [u8], len: usize, style: MaskStyle, (start, end): (bool, bool)) {
+    let total = bases.len();
+    let masked = |i: usize| (start && i < len) || (end && i + len >= total);
+    for (_, base) in bases.iter_mut().enumerate().filter(|(i, _)| masked(*i)) {
+        *base = match style {
+            MaskStyle::Lower => base.to_ascii_lowercase(),
+            MaskStyle::N => b'N',
+        };
+    }
+}
+
+const DEFAULT_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Aux tags copied into the FASTA/FASTQ header comment when present, by add_origin_tags
+const ORIGIN_TAGS: [&[u8; 2]; 2] = [b"oc", b"qo"];
+
+/// Tag `chunk` with where it came from: its reference span in the original alignment (oc, 1-based
+/// inclusive, or * if unmapped) and its offsets within the parent's SEQ (qo, 0-based half-open)
+pub fn add_origin_tags(chunk: &mut Record, contig: Option<&[u8]>, query_range: &Range<usize>) -> io::Result<()> {
+    let origin = match contig {
+        Some(contig) if !chunk.is_unmapped() => format!("{}:{}-{}", String::from_utf8_lossy(contig), chunk.pos() + 1, chunk.cigar().end_pos()),
+        _ => String::from("*"),
+    };
+    chunk.push_aux(b"oc", Aux::String(&origin)).map_err(io::Error::other)?;
+    chunk.push_aux(b"qo", Aux::String(&format!("{}-{}", query_range.start, query_range.end))).map_err(io::Error::other)
+}
+
+/// Writes records as unwrapped FASTA, or FASTQ with qualities, one entry per record named by its qname.
+///
+/// Reverse strand records are written in their original orientation, as samtools fasta/fastq do. Origin
+/// tags added by add_origin_tags are written in the header comment, e.g. `@read-3 oc:chr1:10501-10650 qo:300-450`.
+pub struct FastxWriter {
+    writer: Box<dyn Write>,
+    qualities: bool,
+    mask: Option<(usize, MaskStyle)>,
+}
+
+impl FastxWriter {
+    /// Write to `path` as FASTQ if `qualities`, else FASTA, bgzipped if `compress`
+    pub fn from_path(path: &Path, qualities: bool, compress: bool) -> io::Result<Self> {
+        Self::with_capacity(path, qualities, compress, DEFAULT_BUFFER_SIZE)
+    }
+
+    /// Like `from_path`, but buffering `capacity` bytes between writes to the file
+    pub fn with_capacity(path: &Path, qualities: bool, compress: bool, capacity: usize) -> io::Result<Self> {
+        let writer: Box<dyn Write> = if compress {
+            Box::new(BufWriter::with_capacity(capacity, bgzf::Writer::from_path(path).map_err(io::Error::other)?))
+        } else {
+            Box::new(BufWriter::with_capacity(capacity, File::create(path)?))
+        };
+        Ok(Self { writer, qualities, mask: None })
+    }
+
+    pub fn to_stdout(qualities: bool) -> Self {
+        Self { writer: Box::new(BufWriter::new(io::stdout())), qualities, mask: None }
+    }
+
+    /// Mask the `len` bases next to each boundary that write_chunk is told a chunk was cut at
+    pub fn set_mask_boundary(&mut self, len: usize, style: MaskStyle) {
+        self.mask = Some((len, style));
+    }
+
+    pub fn write(&mut self, rec: &Record) -> io::Result<()> {
+        self.write_chunk(rec, (false, false))
+    }
+
+    /// Write `rec`, given whether its start and end in SEQ order were cut from neighbouring chunks of its parent
+    pub fn write_chunk(&mut self, rec: &Record, cut_ends: (bool, bool)) -> io::Result<()> {
+        self.writer.write_all(if self.qualities { b"@" } else { b">" })?;
+        self.writer.write_all(rec.qname())?;
+        for tag in ORIGIN_TAGS {
+            if let Ok(Aux::String(value)) = rec.aux(tag) {
+                self.writer.write_all(b" ")?;
+                self.writer.write_all(tag)?;
+                write!(self.writer, ":{}", value)?;
+            }
+        }
+        self.writer.write_all(b"\n")?;
+        match self.mask {
+            Some((len, style)) if cut_ends != (false, false) => {
+                let mut bases = rec.seq().as_bytes();
+                mask_ends(&mut bases, len, style, cut_ends);
+                self.writer.write_all(&if rec.is_reverse() { reverse_complement(&bases) } else { bases })?;
+            }
+            _ => self.writer.write_all(&original_bases(rec))?,
+        }
+        self.writer.write_all(b"\n")?;
+        if self.qualities {
+            self.writer.write_all(b"+\n")?;
+            self.writer.write_all(&original_qualities(rec))?;
+            self.writer.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use rust_htslib::bam::record::{Cigar, CigarString};
+
+    #[test]
+    fn reverse_complement_test() {
+        assert_eq!(reverse_complement(b"AACGTN"), b"NACGTT");
+        assert_eq!(reverse_complement(b"acgRY"), b"RYcgt");
+    }
+
+    #[test]
+    fn fastx_writer_test() {
+        let dir = std::env::temp_dir().join(format!("chop_reads_fasta_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let cigar = CigarString(vec![Cigar::Match(4)]);
+        let mut forward = Record::new();
+        forward.set(b"r1-0", Some(&cigar), b"AACG", b"IIII");
+        let mut reverse = Record::new();
+        reverse.set(b"r2-0", Some(&cigar), b"AACG", b"IIII");
+        reverse.set_reverse();
+
+        let path = dir.join("chunks.fa");
+        let mut writer = FastxWriter::from_path(&path, false, false).unwrap();
+        writer.write(&forward).unwrap();
+        writer.write(&reverse).unwrap();
+        drop(writer);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), ">r1-0\nAACG\n>r2-0\nCGTT\n");
+
+        let gz_path = dir.join("chunks.fa.gz");
+        let mut writer = FastxWriter::from_path(&gz_path, false, true).unwrap();
+        writer.write(&forward).unwrap();
+        drop(writer);
+        let mut contents = String::new();
+        bgzf::Reader::from_path(&gz_path).unwrap().read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, ">r1-0\nAACG\n");
+
+        let fq_path = dir.join("chunks.fq");
+        let mut writer = FastxWriter::from_path(&fq_path, true, false).unwrap();
+        reverse.set(b"r2-0", Some(&cigar), b"AACG", &[10, 20, 30, 40]);
+        reverse.set_pos(99);
+        add_origin_tags(&mut reverse, Some(b"chr1"), &(4..8)).unwrap();
+        writer.write(&reverse).unwrap();
+        let mut unmapped = Record::new();
+        unmapped.set(b"r3-1", None, b"AC", &[255, 255]);
+        unmapped.set_unmapped();
+        add_origin_tags(&mut unmapped, None, &(2..4)).unwrap();
+        writer.write(&unmapped).unwrap();
+        drop(writer);
+        assert_eq!(std::fs::read_to_string(&fq_path).unwrap(), "@r2-0 oc:chr1:100-103 qo:4-8\nCGTT\n+\nI?5+\n@r3-1 oc:* qo:2-4\nAC\n+\n\"\"\n");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn mask_boundary_test() {
+        let dir = std::env::temp_dir().join(format!("chop_reads_mask_boundary_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let cigar = CigarString(vec![Cigar::Match(6)]);
+        let mut forward = Record::new();
+        forward.set(b"r1-1", Some(&cigar), b"AACCGG", &[40; 6]);
+        let mut reverse = Record::new();
+        reverse.set(b"r2-0", Some(&cigar), b"AACCGG", &[40; 6]);
+        reverse.set_reverse();
+
+        // Only cut ends are masked, with the 3' end of a reverse strand chunk's SEQ at the start of its original bases
+        let path = dir.join("lower.fa");
+        let mut writer = FastxWriter::from_path(&path, false, false).unwrap();
+        writer.set_mask_boundary(2, MaskStyle::Lower);
+        writer.write_chunk(&forward, (true, true)).unwrap();
+        writer.write_chunk(&reverse, (false, true)).unwrap();
+        writer.write(&forward).unwrap();
+        drop(writer);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), ">r1-1\naaCCgg\n>r2-0\nccGGTT\n>r1-1\nAACCGG\n");
+
+        // Chunks shorter than both masks are masked throughout
+        let path = dir.join("n.fq");
+        let mut writer = FastxWriter::from_path(&path, true, false).unwrap();
+        writer.set_mask_boundary(4, MaskStyle::N);
+        writer.write_chunk(&forward, (true, false)).unwrap();
+        writer.write_chunk(&forward, (true, true)).unwrap();
+        drop(writer);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "@r1-1\nNNNNGG\n+\nIIIIII\n@r1-1\nNNNNNN\n+\nIIIIII\n");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
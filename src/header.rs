@@ -0,0 +1,367 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use clap::ValueEnum;
+use rust_htslib::bam::header::{Header, HeaderRecord};
+use rust_htslib::bam::HeaderView;
+
+/// Read group to add to the output header
+#[derive(Debug, Clone)]
+pub struct ReadGroup {
+    pub id: String,
+    pub sample: Option<String>,
+}
+
+/// IDs of the @PG lines in a header, in file order
+fn program_ids(header_text: &str) -> Vec<String> {
+    header_text.lines()
+        .filter(|line| line.starts_with("@PG"))
+        .filter_map(|line| line.split('\t').find_map(|field| field.strip_prefix("ID:")))
+        .map(|id| id.to_string())
+        .collect()
+}
+
+/// Point @SQ lines without a UR tag at `reference`.
+///
+/// The CRAM writer reads the header before a reference can be attached to it, so this is how it
+/// finds the sequences to compute M5 checksums and compress against.
+pub fn add_reference_uri(template: &HeaderView, reference: &Path) -> HeaderView {
+    let reference = std::path::absolute(reference).unwrap_or_else(|_| reference.to_path_buf());
+    let text = String::from_utf8_lossy(template.as_bytes()).lines()
+        .map(|line| {
+            if line.starts_with("@SQ") && !line.split('\t').any(|field| field.starts_with("UR:")) {
+                format!("{}\tUR:file:{}\n", line, reference.display())
+            } else {
+                format!("{}\n", line)
+            }
+        })
+        .collect::<String>();
+    HeaderView::from_bytes(text.as_bytes())
+}
+
+/// Drop the @RG lines of `template` other than the one for `id`, or all of them if `id` is None
+pub fn only_read_group(template: &HeaderView, id: Option<&str>) -> HeaderView {
+    let text = String::from_utf8_lossy(template.as_bytes()).lines()
+        .filter(|line| !line.starts_with("@RG") || (id.is_some() && tag(line, "ID:") == id))
+        .map(|line| format!("{}\n", line))
+        .collect::<String>();
+    HeaderView::from_bytes(text.as_bytes())
+}
+
+/// Set the SO tag of `template`'s @HD line to `sort_order`, adding an @HD line if there isn't one
+pub fn set_sort_order(template: &HeaderView, sort_order: &str) -> HeaderView {
+    let text = String::from_utf8_lossy(template.as_bytes()).into_owned();
+    let mut lines = text.lines().map(|line| line.to_string()).collect::<Vec<_>>();
+    match lines.iter_mut().find(|line| line.starts_with("@HD")) {
+        Some(hd) => {
+            let fields = hd.split('\t').filter(|field| !field.starts_with("SO:")).map(|field| field.to_string()).collect::<Vec<_>>();
+            *hd = format!("{}\tSO:{}", fields.join("\t"), sort_order);
+        }
+        None => lines.insert(0, format!("@HD\tVN:1.6\tSO:{}", sort_order)),
+    }
+    let text = lines.iter().map(|line| format!("{}\n", line)).collect::<String>();
+    HeaderView::from_bytes(text.as_bytes())
+}
+
+/// What to do with records whose RG tag names a read group missing from the header
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum MissingReadGroupPolicy {
+    /// Add a bare @RG line for it (takes an extra pass over the inputs)
+    Add,
+    /// Remove the RG tag from the record
+    Strip,
+    /// Abort, naming the record
+    Error,
+}
+
+/// IDs of the @RG lines in `header`
+pub fn read_group_ids(header: &HeaderView) -> HashSet<String> {
+    String::from_utf8_lossy(header.as_bytes()).lines()
+        .filter(|line| line.starts_with("@RG"))
+        .filter_map(|line| tag(line, "ID:"))
+        .map(|id| id.to_string())
+        .collect()
+}
+
+/// Sample (SM) of each @RG line in `header` that names one, by read group ID
+pub fn read_group_samples(header: &HeaderView) -> HashMap<String, String> {
+    String::from_utf8_lossy(header.as_bytes()).lines()
+        .filter(|line| line.starts_with("@RG"))
+        .filter_map(|line| Some((tag(line, "ID:")?.to_string(), tag(line, "SM:")?.to_string())))
+        .collect()
+}
+
+/// Append a bare @RG line to `template` for each of `ids`
+pub fn add_read_groups(template: &HeaderView, ids: &[String]) -> HeaderView {
+    let mut text = String::from_utf8_lossy(template.as_bytes()).into_owned();
+    if !text.is_empty() && !text.ends_with('\n') {
+        text.push('\n');
+    }
+    ids.iter().for_each(|id| text.push_str(&format!("@RG\tID:{}\n", id)));
+    HeaderView::from_bytes(text.as_bytes())
+}
+
+/// Replace the @SQ lines of `template` with `sq_lines`, keeping the other lines in place
+pub fn replace_sequences(template: &HeaderView, sq_lines: &[String]) -> HeaderView {
+    let mut text = String::new();
+    let mut inserted = false;
+    for line in String::from_utf8_lossy(template.as_bytes()).lines() {
+        if line.starts_with("@SQ") || (!inserted && !line.starts_with("@HD")) {
+            if !inserted {
+                sq_lines.iter().for_each(|sq| text.push_str(&format!("{}\n", sq)));
+                inserted = true;
+            }
+            if line.starts_with("@SQ") {
+                continue;
+            }
+        }
+        text.push_str(&format!("{}\n", line));
+    }
+    if !inserted {
+        sq_lines.iter().for_each(|sq| text.push_str(&format!("{}\n", sq)));
+    }
+    HeaderView::from_bytes(text.as_bytes())
+}
+
+fn tag<'a>(line: &'a str, tag: &str) -> Option<&'a str> {
+    line.split('\t').find_map(|field| field.strip_prefix(tag))
+}
+
+/// SO value of the @HD line, if there is one
+pub fn sort_order(header: &HeaderView) -> Option<String> {
+    let text = String::from_utf8_lossy(header.as_bytes()).into_owned();
+    let hd = text.lines().find(|line| line.starts_with("@HD"))?;
+    tag(hd, "SO:").map(|so| so.to_string())
+}
+
+/// Merge the headers of several inputs, returning the merged header and each input's tid map into it.
+///
+/// @SQ lines are unioned in first-seen order and panic if the same contig has different lengths.
+/// @RG lines with the same ID must be identical, while clashing @PG IDs from different inputs are
+/// renamed (with PP updated to match) since every lane tends to have its own aligner line.
+pub fn merge_headers(headers: &[HeaderView]) -> (HeaderView, Vec<Vec<Option<i32>>>) {
+    let mut hd_line = None;
+    let mut sq_lines: Vec<String> = Vec::new();
+    let mut tids: HashMap<String, (i32, String)> = HashMap::new();
+    let mut other_lines: Vec<String> = Vec::new();
+    let mut read_groups: HashMap<String, String> = HashMap::new();
+    let mut program_ids: Vec<String> = Vec::new();
+    let mut tid_maps = Vec::new();
+
+    for header in headers {
+        let text = String::from_utf8_lossy(header.as_bytes()).to_string();
+        let mut tid_map = Vec::new();
+        let mut renamed_programs: HashMap<String, String> = HashMap::new();
+
+        for line in text.lines() {
+            if line.starts_with("@HD") {
+                hd_line.get_or_insert_with(|| line.to_string());
+            } else if line.starts_with("@SQ") {
+                let name = tag(line, "SN:").unwrap_or_default().to_string();
+                let length = tag(line, "LN:").unwrap_or_default().to_string();
+                match tids.get(&name) {
+                    Some((_, merged_length)) if *merged_length != length => panic!("Contig {} has length {} in one input but {} in another", name, merged_length, length),
+                    Some((tid, _)) => tid_map.push(Some(*tid)),
+                    None => {
+                        tids.insert(name, (sq_lines.len() as i32, length));
+                        tid_map.push(Some(sq_lines.len() as i32));
+                        sq_lines.push(line.to_string());
+                    }
+                }
+            } else if line.starts_with("@RG") {
+                let id = tag(line, "ID:").unwrap_or_default().to_string();
+                match read_groups.get(&id) {
+                    Some(merged_line) if merged_line != line => panic!("Read group {} differs between inputs", id),
+                    Some(_) => continue,
+                    None => {
+                        read_groups.insert(id, line.to_string());
+                        other_lines.push(line.to_string());
+                    }
+                }
+            } else if line.starts_with("@PG") {
+                let line = match tag(line, "PP:").and_then(|pp| renamed_programs.get(pp)) {
+                    Some(new_pp) => line.split('\t').map(|field| if field.starts_with("PP:") { format!("PP:{}", new_pp) } else { field.to_string() }).collect::<Vec<_>>().join("\t"),
+                    None => line.to_string(),
+                };
+                if other_lines.contains(&line) {
+                    continue;
+                }
+                let id = tag(&line, "ID:").unwrap_or_default().to_string();
+                let mut new_id = id.clone();
+                let mut suffix = 1;
+                while program_ids.contains(&new_id) {
+                    new_id = format!("{}.{}", id, suffix);
+                    suffix += 1;
+                }
+                let line = if new_id != id {
+                    renamed_programs.insert(id.clone(), new_id.clone());
+                    line.replacen(&format!("ID:{}", id), &format!("ID:{}", new_id), 1)
+                } else {
+                    line
+                };
+                program_ids.push(new_id);
+                other_lines.push(line);
+            } else if !other_lines.iter().any(|other| other == line) {
+                other_lines.push(line.to_string());
+            }
+        }
+        tid_maps.push(tid_map);
+    }
+
+    // Concatenated inputs are no longer sorted as a whole
+    let hd_line = hd_line.map(|hd| {
+        match (headers.len() > 1, tag(&hd, "SO:")) {
+            (true, Some(so)) => hd.replacen(&format!("SO:{}", so), "SO:unsorted", 1),
+            _ => hd,
+        }
+    });
+
+    let text = hd_line.into_iter().chain(sq_lines).chain(other_lines).map(|line| format!("{}\n", line)).collect::<String>();
+    (HeaderView::from_bytes(text.as_bytes()), tid_maps)
+}
+
+/// Build the output header from the input header.
+///
+/// New records are always appended after the template's lines in the same order (@RG, @PG, then
+/// @CO in the order given), so identical inputs and arguments produce byte-identical headers.
+pub fn build_output_header(template: &HeaderView, read_group: Option<&ReadGroup>, command_line: &str, comments: &[String]) -> Header {
+    let mut header = Header::from_template(template);
+
+    if let Some(rg) = read_group {
+        let mut header_record = HeaderRecord::new(b"RG");
+        header_record.push_tag(b"ID", &rg.id);
+        if let Some(sn) = &rg.sample {
+            header_record.push_tag(b"SM", sn);
+        }
+        header.push_record(&header_record);
+    }
+
+    // Chain onto the last program in the input, and avoid clashing with an earlier chop_reads run
+    let existing_ids = program_ids(&String::from_utf8_lossy(template.as_bytes()));
+    let mut pg_id = String::from("chop_reads");
+    let mut suffix = 1;
+    while existing_ids.contains(&pg_id) {
+        pg_id = format!("chop_reads.{}", suffix);
+        suffix += 1;
+    }
+
+    let mut pg_record = HeaderRecord::new(b"PG");
+    pg_record.push_tag(b"ID", &pg_id);
+    pg_record.push_tag(b"PN", "chop_reads");
+    if let Some(previous) = existing_ids.last() {
+        pg_record.push_tag(b"PP", previous);
+    }
+    pg_record.push_tag(b"VN", env!("CARGO_PKG_VERSION"));
+    pg_record.push_tag(b"CL", command_line);
+    header.push_record(&pg_record);
+
+    for comment in comments {
+        header.push_comment(comment.as_bytes());
+    }
+
+    header
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replace_sequences_test() {
+        let sq_lines = vec![String::from("@SQ\tSN:chrB\tLN:20"), String::from("@SQ\tSN:chrA\tLN:10")];
+
+        let template = HeaderView::from_bytes(b"@HD\tVN:1.6\n@SQ\tSN:chr1\tLN:1000\n@SQ\tSN:chr2\tLN:500\n@RG\tID:rg\n");
+        assert_eq!(replace_sequences(&template, &sq_lines).as_bytes(), b"@HD\tVN:1.6\n@SQ\tSN:chrB\tLN:20\n@SQ\tSN:chrA\tLN:10\n@RG\tID:rg\n");
+
+        let no_sq_template = HeaderView::from_bytes(b"@HD\tVN:1.6\n");
+        assert_eq!(replace_sequences(&no_sq_template, &sq_lines).as_bytes(), b"@HD\tVN:1.6\n@SQ\tSN:chrB\tLN:20\n@SQ\tSN:chrA\tLN:10\n");
+    }
+
+    #[test]
+    fn add_reference_uri_test() {
+        let template = HeaderView::from_bytes(b"@HD\tVN:1.6\n@SQ\tSN:chr1\tLN:1000\n@SQ\tSN:chr2\tLN:500\tUR:file:/other.fa\n");
+        let header = add_reference_uri(&template, Path::new("/refs/ref.fa"));
+        assert_eq!(header.as_bytes(), b"@HD\tVN:1.6\n@SQ\tSN:chr1\tLN:1000\tUR:file:/refs/ref.fa\n@SQ\tSN:chr2\tLN:500\tUR:file:/other.fa\n");
+    }
+
+    #[test]
+    fn set_sort_order_test() {
+        let sorted = set_sort_order(&HeaderView::from_bytes(b"@HD\tVN:1.6\tSO:unsorted\tGO:query\n@SQ\tSN:chr1\tLN:1000\n"), "coordinate");
+        assert_eq!(sorted.as_bytes(), b"@HD\tVN:1.6\tGO:query\tSO:coordinate\n@SQ\tSN:chr1\tLN:1000\n");
+
+        let added = set_sort_order(&HeaderView::from_bytes(b"@SQ\tSN:chr1\tLN:1000\n"), "coordinate");
+        assert_eq!(added.as_bytes(), b"@HD\tVN:1.6\tSO:coordinate\n@SQ\tSN:chr1\tLN:1000\n");
+    }
+
+    #[test]
+    fn only_read_group_test() {
+        let template = HeaderView::from_bytes(b"@SQ\tSN:chr1\tLN:1000\n@RG\tID:rg1\tSM:a\n@RG\tID:rg2\tSM:b\n@PG\tID:bwa\n");
+        assert_eq!(only_read_group(&template, Some("rg2")).as_bytes(), b"@SQ\tSN:chr1\tLN:1000\n@RG\tID:rg2\tSM:b\n@PG\tID:bwa\n");
+        assert_eq!(only_read_group(&template, None).as_bytes(), b"@SQ\tSN:chr1\tLN:1000\n@PG\tID:bwa\n");
+    }
+
+    #[test]
+    fn read_groups_test() {
+        let template = make_template();
+        assert_eq!(read_group_ids(&template), HashSet::from([String::from("old")]));
+
+        let added = add_read_groups(&template, &[String::from("lane2"), String::from("lane3")]);
+        assert_eq!(read_group_ids(&added), HashSet::from([String::from("old"), String::from("lane2"), String::from("lane3")]));
+        assert!(String::from_utf8_lossy(added.as_bytes()).ends_with("@PG\tID:bwa\tPN:bwa\n@RG\tID:lane2\n@RG\tID:lane3\n"));
+    }
+
+    #[test]
+    fn read_group_samples_test() {
+        let header = HeaderView::from_bytes(b"@RG\tID:rg1\tSM:NA12878\n@RG\tID:rg2\tLB:lib\tSM:NA24385\n@RG\tID:rg3\n");
+        assert_eq!(read_group_samples(&header), HashMap::from([
+            (String::from("rg1"), String::from("NA12878")),
+            (String::from("rg2"), String::from("NA24385")),
+        ]));
+    }
+
+    #[test]
+    fn sort_order_test() {
+        assert_eq!(sort_order(&make_template()), Some(String::from("coordinate")));
+        assert_eq!(sort_order(&HeaderView::from_bytes(b"@HD\tVN:1.6\n@CO\tSO:queryname\n")), None);
+        assert_eq!(sort_order(&HeaderView::from_bytes(b"@SQ\tSN:chr1\tLN:1000\n")), None);
+    }
+
+    fn make_template() -> HeaderView {
+        HeaderView::from_bytes(b"@HD\tVN:1.6\tSO:coordinate\n@SQ\tSN:chr1\tLN:1000\n@SQ\tSN:chr2\tLN:500\n@RG\tID:old\tSM:s0\n@PG\tID:chop_reads\tPN:chop_reads\n@PG\tID:bwa\tPN:bwa\n")
+    }
+
+    #[test]
+    fn deterministic_header_test() {
+        let rg = ReadGroup { id: String::from("rg1"), sample: Some(String::from("s1")) };
+        let comments = vec![String::from("first"), String::from("second")];
+
+        let header_a = build_output_header(&make_template(), Some(&rg), "chop-reads -s 5", &comments).to_bytes();
+        let header_b = build_output_header(&make_template(), Some(&rg), "chop-reads -s 5", &comments).to_bytes();
+        assert_eq!(header_a, header_b);
+
+        let expected = format!("@HD\tVN:1.6\tSO:coordinate\n@SQ\tSN:chr1\tLN:1000\n@SQ\tSN:chr2\tLN:500\n@RG\tID:old\tSM:s0\n\
+            @PG\tID:chop_reads\tPN:chop_reads\n@PG\tID:bwa\tPN:bwa\n\
+            @RG\tID:rg1\tSM:s1\n\
+            @PG\tID:chop_reads.1\tPN:chop_reads\tPP:bwa\tVN:{}\tCL:chop-reads -s 5\n\
+            @CO\tfirst\n@CO\tsecond", env!("CARGO_PKG_VERSION"));
+        assert_eq!(String::from_utf8(header_a).unwrap(), expected);
+    }
+
+    #[test]
+    fn merge_headers_test() {
+        let lane1 = HeaderView::from_bytes(b"@HD\tVN:1.6\tSO:coordinate\n@SQ\tSN:chr1\tLN:1000\n@SQ\tSN:chr2\tLN:500\n@RG\tID:lane1\n@PG\tID:bwa\tCL:bwa lane1\n@PG\tID:samtools\tPP:bwa\n");
+        let lane2 = HeaderView::from_bytes(b"@HD\tVN:1.6\tSO:coordinate\n@SQ\tSN:chr2\tLN:500\n@SQ\tSN:chr3\tLN:50\n@RG\tID:lane2\n@PG\tID:bwa\tCL:bwa lane2\n@PG\tID:samtools\tPP:bwa\n@CO\tlane 2\n");
+
+        let (merged, tid_maps) = merge_headers(&[lane1, lane2]);
+        assert_eq!(String::from_utf8_lossy(merged.as_bytes()), "@HD\tVN:1.6\tSO:unsorted\n\
+            @SQ\tSN:chr1\tLN:1000\n@SQ\tSN:chr2\tLN:500\n@SQ\tSN:chr3\tLN:50\n\
+            @RG\tID:lane1\n@PG\tID:bwa\tCL:bwa lane1\n@PG\tID:samtools\tPP:bwa\n\
+            @RG\tID:lane2\n@PG\tID:bwa.1\tCL:bwa lane2\n@PG\tID:samtools.1\tPP:bwa.1\n@CO\tlane 2\n");
+        assert_eq!(tid_maps, vec![vec![Some(0), Some(1)], vec![Some(1), Some(2)]]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Contig chr1 has length 1000 in one input but 999 in another")]
+    fn merge_headers_length_mismatch_test() {
+        merge_headers(&[HeaderView::from_bytes(b"@SQ\tSN:chr1\tLN:1000\n"), HeaderView::from_bytes(b"@SQ\tSN:chr1\tLN:999\n")]);
+    }
+}
@@ -0,0 +1,72 @@
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// A minimal IGV session referencing the chopped output and any companion tracks
+#[derive(Debug, Clone)]
+pub struct IgvSession {
+    genome: Option<PathBuf>,
+    resources: Vec<PathBuf>,
+}
+
+impl IgvSession {
+    pub fn new(genome: Option<PathBuf>) -> Self {
+        Self {
+            genome,
+            resources: Vec::new(),
+        }
+    }
+
+    pub fn add_resource(&mut self, path: &Path) {
+        self.resources.push(path.to_path_buf());
+    }
+
+    fn escape(path: &Path) -> io::Result<String> {
+        // IGV resolves relative paths against the session file, so always store absolute ones
+        let path = std::path::absolute(path)?;
+        Ok(path.to_string_lossy()
+            .replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+            .replace('"', "&quot;"))
+    }
+
+    fn render(&self) -> io::Result<String> {
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"no\"?>\n");
+        match &self.genome {
+            Some(genome) => xml.push_str(&format!("<Session genome=\"{}\" locus=\"All\" version=\"8\">\n", Self::escape(genome)?)),
+            None => xml.push_str("<Session locus=\"All\" version=\"8\">\n"),
+        }
+        xml.push_str("    <Resources>\n");
+        for resource in &self.resources {
+            xml.push_str(&format!("        <Resource path=\"{}\"/>\n", Self::escape(resource)?));
+        }
+        xml.push_str("    </Resources>\n");
+        xml.push_str("</Session>\n");
+        Ok(xml)
+    }
+
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let mut file = File::create(path)?;
+        file.write_all(self.render()?.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_test() {
+        let mut session = IgvSession::new(Some(PathBuf::from("/refs/hg38.fa")));
+        session.add_resource(Path::new("/data/a&b.bam"));
+
+        let expected = "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"no\"?>\n\
+            <Session genome=\"/refs/hg38.fa\" locus=\"All\" version=\"8\">\n    \
+            <Resources>\n        \
+            <Resource path=\"/data/a&amp;b.bam\"/>\n    \
+            </Resources>\n\
+            </Session>\n";
+        assert_eq!(session.render().unwrap(), expected);
+    }
+}
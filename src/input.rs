@@ -0,0 +1,292 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use rust_htslib::bam::{HeaderView, IndexedReader, Read as BamRead, Reader, Record};
+use rust_htslib::errors::Result as HtsResult;
+use url::Url;
+use crate::region::{resolve_regions, Region, TargetRegion};
+
+/// Whether the file at `path` is a CRAM, based on its magic bytes
+pub fn is_cram(path: &Path) -> io::Result<bool> {
+    let mut magic = [0u8; 4];
+    let mut file = File::open(path)?;
+    match file.read_exact(&mut magic) {
+        Ok(()) => Ok(&magic == b"CRAM"),
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Parse `path` as a URL for htslib to read or write remotely (e.g. https://, s3://, gs://), if it is one
+pub fn hts_url(path: &Path) -> Option<Url> {
+    let path = path.to_str()?;
+    if !path.contains("://") {
+        return None;
+    }
+    Url::parse(path).ok()
+}
+
+/// Expand any glob patterns among `inputs` into the files they match, in sorted order.
+///
+/// Stdin, URLs and plain paths are passed through as given, while a pattern matching nothing is an
+/// error rather than silently contributing no records.
+pub fn expand_globs(inputs: &[PathBuf]) -> io::Result<Vec<PathBuf>> {
+    let mut expanded = Vec::new();
+    for input in inputs {
+        let pattern = input.to_string_lossy();
+        if input.as_os_str() == "-" || hts_url(input).is_some() || !pattern.contains(['*', '?', '[']) {
+            expanded.push(input.clone());
+            continue;
+        }
+
+        let paths = glob::glob(&pattern).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid input pattern {}: {}", pattern, e)))?;
+        let mut matches = paths.collect::<Result<Vec<_>, _>>().map_err(io::Error::from)?;
+        if matches.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::NotFound, format!("No inputs match {}", pattern)));
+        }
+        matches.sort();
+        expanded.extend(matches);
+    }
+    Ok(expanded)
+}
+
+/// An input streamed from start to end, or restricted to regions through its index
+pub enum InputReader {
+    Stream(Reader),
+    Indexed {
+        reader: IndexedReader,
+        targets: Vec<TargetRegion>,
+        next: usize,
+        fetched: bool,
+    },
+}
+
+impl InputReader {
+    /// Open `input` (a path, URL or - for stdin), fetching only records overlapping `regions` if given.
+    ///
+    /// Regions need an index next to the input. Each record is read once, even if it overlaps
+    /// several of them.
+    pub fn open(input: &Path, regions: Option<&[Region]>) -> io::Result<Self> {
+        let url = hts_url(input);
+        let reader = match (regions, &url) {
+            (None, _) if input.as_os_str() == "-" => Reader::from_stdin().map(InputReader::Stream),
+            (None, Some(url)) => Reader::from_url(url).map(InputReader::Stream),
+            (None, None) => Reader::from_path(input).map(InputReader::Stream),
+            (Some(regions), _) => {
+                let reader = match &url {
+                    Some(url) => IndexedReader::from_url(url),
+                    None => IndexedReader::from_path(input),
+                }.map_err(io::Error::other)?;
+                let targets = resolve_regions(regions, reader.header())?;
+                Ok(InputReader::Indexed { reader, targets, next: 0, fetched: false })
+            }
+        };
+        reader.map_err(io::Error::other)
+    }
+
+    pub fn read(&mut self, record: &mut Record) -> Option<HtsResult<()>> {
+        let (reader, targets, next, fetched) = match self {
+            InputReader::Stream(reader) => return reader.read(record),
+            InputReader::Indexed { reader, targets, next, fetched } => (reader, targets, next, fetched),
+        };
+        loop {
+            let target = targets.get(*next)?;
+            if !*fetched {
+                if let Err(e) = reader.fetch((target.tid, target.start, target.end)) {
+                    return Some(Err(e));
+                }
+                *fetched = true;
+            }
+            match reader.read(record) {
+                None => {
+                    *next += 1;
+                    *fetched = false;
+                }
+                // Records reaching back into the previous (merged, so disjoint) region were already read
+                Some(Ok(())) if *next > 0 && targets[*next - 1].tid == record.tid() && record.pos() < targets[*next - 1].end => continue,
+                result => return result,
+            }
+        }
+    }
+
+    pub fn header(&self) -> &HeaderView {
+        match self {
+            InputReader::Stream(reader) => reader.header(),
+            InputReader::Indexed { reader, .. } => reader.header(),
+        }
+    }
+
+    pub fn set_reference(&mut self, path: &Path) -> HtsResult<()> {
+        match self {
+            InputReader::Stream(reader) => reader.set_reference(path),
+            InputReader::Indexed { reader, .. } => reader.set_reference(path),
+        }
+    }
+
+    pub fn set_threads(&mut self, threads: usize) -> HtsResult<()> {
+        match self {
+            InputReader::Stream(reader) => reader.set_threads(threads),
+            InputReader::Indexed { reader, .. } => reader.set_threads(threads),
+        }
+    }
+}
+
+// Fields identifying an alignment line for duplicate detection
+#[derive(Debug, PartialEq, Eq)]
+struct AlignmentKey {
+    qname: Vec<u8>,
+    flags: u16,
+    tid: i32,
+    pos: i64,
+    cigar: Vec<u32>,
+}
+
+impl AlignmentKey {
+    fn matches(&self, rec: &Record) -> bool {
+        self.qname == rec.qname() && self.flags == rec.flags() && self.tid == rec.tid() && self.pos == rec.pos() && self.cigar == rec.raw_cigar()
+    }
+}
+
+/// Detects exact duplicate alignments arriving back to back, as left behind by naive merges
+#[derive(Debug, Default)]
+pub struct DuplicateFilter {
+    previous: Option<AlignmentKey>,
+    duplicates: u64,
+}
+
+impl DuplicateFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether `rec` has the same qname, flags, position and CIGAR as the previous record
+    pub fn is_duplicate(&mut self, rec: &Record) -> bool {
+        if self.previous.as_ref().is_some_and(|key| key.matches(rec)) {
+            self.duplicates += 1;
+            return true;
+        }
+        self.previous = Some(AlignmentKey {
+            qname: rec.qname().to_vec(),
+            flags: rec.flags(),
+            tid: rec.tid(),
+            pos: rec.pos(),
+            cigar: rec.raw_cigar().to_vec(),
+        });
+        false
+    }
+
+    /// Number of duplicates seen so far
+    pub fn duplicates(&self) -> u64 {
+        self.duplicates
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_htslib::bam;
+    use rust_htslib::bam::record::{Cigar, CigarString};
+
+    fn make_record(qname: &str, flags: u16, pos: i64, cigar: &CigarString) -> Record {
+        let mut rec = Record::default();
+        rec.set(qname.as_bytes(), Some(cigar), b"ACGTA", b"IIIII");
+        rec.set_pos(pos);
+        rec.set_flags(flags);
+        rec
+    }
+
+    #[test]
+    fn duplicate_filter_test() {
+        let cigar = CigarString(vec![Cigar::Match(5)]);
+        let other_cigar = CigarString(vec![Cigar::Match(3), Cigar::SoftClip(2)]);
+        let mut filter = DuplicateFilter::new();
+
+        assert!(!filter.is_duplicate(&make_record("a", 0, 100, &cigar)));
+        assert!(filter.is_duplicate(&make_record("a", 0, 100, &cigar)));
+        assert!(!filter.is_duplicate(&make_record("a", 0, 100, &other_cigar)));
+        assert!(!filter.is_duplicate(&make_record("a", 256, 100, &other_cigar)));
+        assert!(!filter.is_duplicate(&make_record("b", 256, 100, &other_cigar)));
+        assert!(!filter.is_duplicate(&make_record("a", 256, 100, &other_cigar)));
+        assert_eq!(filter.duplicates(), 1);
+    }
+
+    #[test]
+    fn hts_url_test() {
+        assert_eq!(hts_url(Path::new("s3://bucket/reads.bam")).map(|url| url.scheme().to_string()), Some(String::from("s3")));
+        assert!(hts_url(Path::new("https://example.org/reads.cram")).is_some());
+        assert!(hts_url(Path::new("data/reads.bam")).is_none());
+        assert!(hts_url(Path::new("-")).is_none());
+    }
+
+    #[test]
+    fn expand_globs_test() {
+        let dir = std::env::temp_dir().join(format!("chop_reads_globs_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in ["lane2.bam", "lane1.bam", "lane1.bam.bai"] {
+            std::fs::write(dir.join(name), b"").unwrap();
+        }
+
+        let inputs = vec![dir.join("lane*.bam"), PathBuf::from("-"), PathBuf::from("s3://bucket/*.bam")];
+        assert_eq!(expand_globs(&inputs).unwrap(), vec![dir.join("lane1.bam"), dir.join("lane2.bam"), PathBuf::from("-"), PathBuf::from("s3://bucket/*.bam")]);
+        assert_eq!(expand_globs(&[dir.join("*.cram")]).unwrap_err().kind(), io::ErrorKind::NotFound);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn regions_test() {
+        let dir = std::env::temp_dir().join(format!("chop_reads_region_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let bam_path = dir.join("test.bam");
+
+        let mut header = bam::Header::new();
+        header.push_record(bam::header::HeaderRecord::new(b"SQ").push_tag(b"SN", "chr1").push_tag(b"LN", 1000));
+        let mut writer = bam::Writer::from_path(&bam_path, &header, bam::Format::Bam).unwrap();
+        let cigar = CigarString(vec![Cigar::Match(5)]);
+        for (qname, pos) in [("a", 100), ("b", 200), ("c", 300)] {
+            let mut rec = make_record(qname, 0, pos, &cigar);
+            rec.set_tid(0);
+            writer.write(&rec).unwrap();
+        }
+        drop(writer);
+        bam::index::build(&bam_path, None, bam::index::Type::Bai, 1).unwrap();
+
+        let read_qnames = |regions: Option<&[Region]>| {
+            let mut reader = InputReader::open(&bam_path, regions).unwrap();
+            let mut rec = Record::new();
+            let mut qnames = Vec::new();
+            while let Some(r) = reader.read(&mut rec) {
+                r.unwrap();
+                qnames.push(String::from_utf8(rec.qname().to_vec()).unwrap());
+            }
+            qnames
+        };
+        assert_eq!(read_qnames(None), vec!["a", "b", "c"]);
+        assert_eq!(read_qnames(Some(&[Region::parse("chr1:203-301").unwrap()])), vec!["b", "c"]);
+        // b overlaps both regions but is only read once
+        let regions = [Region::parse("chr1:201-202").unwrap(), Region::parse("chr1:204-210").unwrap(), Region::parse("chr1:90-101").unwrap()];
+        assert_eq!(read_qnames(Some(&regions)), vec!["a", "b"]);
+        assert!(InputReader::open(&bam_path, Some(&[Region::parse("chr2:1-10").unwrap()])).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn is_cram_test() {
+        let dir = std::env::temp_dir().join(format!("chop_reads_input_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let cram_path = dir.join("test.cram");
+        std::fs::write(&cram_path, b"CRAM\x03\x01").unwrap();
+        let sam_path = dir.join("test.sam");
+        std::fs::write(&sam_path, b"@HD\tVN:1.6\n").unwrap();
+        let empty_path = dir.join("empty");
+        std::fs::write(&empty_path, b"").unwrap();
+
+        assert!(is_cram(&cram_path).unwrap());
+        assert!(!is_cram(&sam_path).unwrap());
+        assert!(!is_cram(&empty_path).unwrap());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,71 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use rust_htslib::bam::Record;
+
+/// Writes the reference positions where adjacent chunks of a read meet as BED intervals
+pub struct JunctionWriter {
+    writer: BufWriter<File>,
+}
+
+impl JunctionWriter {
+    pub fn from_path(path: &Path) -> io::Result<Self> {
+        Ok(Self {
+            writer: BufWriter::new(File::create(path)?),
+        })
+    }
+
+    /// Write one BED line per junction between `chunks`, covering the first reference base of the right-hand chunk,
+    /// given with its index as by AlignmentChopper::chunk_junctions
+    pub fn write_junctions(&mut self, contig: &[u8], chunks: &[Record], junctions: &[(i64, usize)]) -> io::Result<()> {
+        for &(pos, right) in junctions {
+            self.writer.write_all(contig)?;
+            write!(self.writer, "\t{}\t{}\t", pos, pos + 1)?;
+            self.writer.write_all(chunks[right - 1].qname())?;
+            self.writer.write_all(b"|")?;
+            self.writer.write_all(chunks[right].qname())?;
+            writeln!(self.writer)?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alignment_chopper::{AlignmentChopper, ClipPolicy};
+    use rust_htslib::bam::record::{Cigar, CigarString};
+
+    #[test]
+    fn junctions_test() {
+        let dir = std::env::temp_dir().join(format!("chop_reads_junctions_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("junctions.bed");
+
+        // Separate clips take the first and last names, so the junctions lie between r1-1, r1-2 and r1-3
+        let mut rec = Record::default();
+        rec.set(b"r1", Some(&CigarString(vec![Cigar::SoftClip(2), Cigar::Match(15), Cigar::SoftClip(3)])), &[b'A'; 20], &[30; 20]);
+        rec.set_pos(100);
+        let mut chopper = AlignmentChopper::new(5, 0, ClipPolicy::Separate, None);
+        chopper.chop_read(&rec);
+        let mut writer = JunctionWriter::from_path(&path).unwrap();
+        writer.write_junctions(b"chr1", chopper.chunks(), &chopper.chunk_junctions()).unwrap();
+
+        // Chunks left out leave out the junctions either side of them too
+        chopper.set_max_chunks(Some(3));
+        chopper.chop_read(&rec);
+        writer.write_junctions(b"chr1", chopper.chunks(), &chopper.chunk_junctions()).unwrap();
+        writer.flush().unwrap();
+        drop(writer);
+
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "chr1\t105\t106\tr1-1|r1-2\n\
+            chr1\t110\t111\tr1-2|r1-3\n\
+            chr1\t105\t106\tr1-1|r1-2\n");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
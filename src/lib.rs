@@ -1 +1,21 @@
-pub mod alignment_chopper;
\ No newline at end of file
+pub mod alignment_chopper;
+pub mod cigar_utils;
+pub mod fastx;
+pub mod header;
+pub mod igv_session;
+pub mod input;
+pub mod junctions;
+pub mod liftover;
+pub mod output;
+pub mod pairs;
+pub mod prefetch;
+pub mod preset;
+pub mod reference;
+pub mod region;
+pub mod report;
+pub mod sequence_dict;
+pub mod sort;
+pub mod split;
+pub mod status_server;
+pub mod strategy;
+pub mod validate;
\ No newline at end of file
@@ -0,0 +1,78 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::ops::Range;
+use std::path::Path;
+use rust_htslib::bam::Record;
+use rust_htslib::bgzf;
+
+/// Writes a TSV mapping each chunk back to the parent query positions and reference span it came from.
+///
+/// Query ranges are 0-based and half-open on the parent's SEQ, reference ranges likewise on the
+/// contig, and unmapped chunks get "*" and "." in place of the contig and reference range. Paths
+/// ending in .gz are bgzipped.
+pub struct LiftoverWriter {
+    writer: Box<dyn Write>,
+}
+
+impl LiftoverWriter {
+    pub fn from_path(path: &Path) -> io::Result<Self> {
+        let writer: Box<dyn Write> = if path.extension().is_some_and(|ext| ext == "gz") {
+            Box::new(bgzf::Writer::from_path(path).map_err(io::Error::other)?)
+        } else {
+            Box::new(BufWriter::new(File::create(path)?))
+        };
+        let mut liftover_writer = Self { writer };
+        writeln!(liftover_writer.writer, "#chunk\tparent\tquery_start\tquery_end\tcontig\tref_start\tref_end")?;
+        Ok(liftover_writer)
+    }
+
+    pub fn write_chunk(&mut self, chunk: &Record, parent_qname: &[u8], contig: Option<&[u8]>, query_range: &Range<usize>) -> io::Result<()> {
+        self.writer.write_all(chunk.qname())?;
+        self.writer.write_all(b"\t")?;
+        self.writer.write_all(parent_qname)?;
+        write!(self.writer, "\t{}\t{}\t", query_range.start, query_range.end)?;
+        match contig {
+            Some(contig) if !chunk.is_unmapped() => {
+                self.writer.write_all(contig)?;
+                writeln!(self.writer, "\t{}\t{}", chunk.pos(), chunk.cigar().end_pos())
+            }
+            _ => writeln!(self.writer, "*\t.\t."),
+        }
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_htslib::bam::record::{Cigar, CigarString};
+
+    #[test]
+    fn liftover_test() {
+        let dir = std::env::temp_dir().join(format!("chop_reads_liftover_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("liftover.tsv");
+
+        let mut chunk = Record::default();
+        chunk.set(b"read-1", Some(&CigarString(vec![Cigar::Match(2), Cigar::Del(3), Cigar::Match(2)])), b"ACGT", b"IIII");
+        chunk.set_pos(104);
+        let mut unmapped = Record::default();
+        unmapped.set(b"read-2", None, b"ACGT", b"IIII");
+        unmapped.set_unmapped();
+
+        let mut writer = LiftoverWriter::from_path(&path).unwrap();
+        writer.write_chunk(&chunk, b"read", Some(b"chr1"), &(6..10)).unwrap();
+        writer.write_chunk(&unmapped, b"read", None, &(10..14)).unwrap();
+        writer.flush().unwrap();
+        drop(writer);
+
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "#chunk\tparent\tquery_start\tquery_end\tcontig\tref_start\tref_end\n\
+            read-1\tread\t6\t10\tchr1\t104\t111\n\
+            read-2\tread\t10\t14\t*\t.\t.\n");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,41 +1,268 @@
-use std::path::PathBuf;
-use std::sync::Mutex;
-use rayon::prelude::*;
-use std::sync::mpsc::sync_channel;
+use std::collections::{BTreeSet, HashMap};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
 use rust_htslib::bam as hts_bam;
-use rust_htslib::bam::{Format, Read};
+use rust_htslib::bam::record::Aux;
 use std::time::Instant;
-use clap::Parser;
-use rayon::iter::{IntoParallelIterator, ParallelBridge, ParallelIterator};
-use rust_htslib::bam::header::HeaderRecord;
-use chop_reads::alignment_chopper::AlignmentChopper;
+use clap::{CommandFactory, Parser, ValueEnum};
+use clap::error::ErrorKind;
+use clap::parser::ValueSource;
+use chop_reads::alignment_chopper::{AlignmentChopper, BinAnchor, ChunkBy, CigarSeqMismatchPolicy, ClipMode, ClipPolicy, DeletionPolicy, MapqPolicy, ParentMark, RemainderPolicy};
+use chop_reads::header::{add_read_groups, add_reference_uri, build_output_header, merge_headers, only_read_group, read_group_ids, read_group_samples, replace_sequences, set_sort_order, sort_order, MissingReadGroupPolicy, ReadGroup};
+use chop_reads::igv_session::IgvSession;
+use chop_reads::input::{expand_globs, hts_url, is_cram, DuplicateFilter, InputReader};
+use chop_reads::junctions::JunctionWriter;
+use chop_reads::liftover::LiftoverWriter;
+use chop_reads::fastx::{add_origin_tags, FastxWriter, MaskStyle};
+use chop_reads::output::{check_output_path, sync_path, temp_output_path, write_index, CramLayout, CramWriter, FsyncPolicy, IndexKind, OutputFormat, RecordWriter, TileBins};
+use chop_reads::pairs::{PairSynthesizer, SyntheticPairs};
+use chop_reads::prefetch::PrefetchReader;
+use chop_reads::preset::Preset;
+use chop_reads::reference::Reference;
+use chop_reads::region::{read_bed, read_gtf_exons, read_positions, region_breakpoints, resolve_regions, Region};
+use chop_reads::report::RunReport;
+use chop_reads::sequence_dict::{AbsentContigPolicy, SequenceDictionary, TidRemapper};
+use chop_reads::sort::{ExternalSorter, SortOrder};
+use chop_reads::split::{check_template, manifest_path, split_output_path, shard_name, write_manifest, SplitOutputs, UNASSIGNED_READ_GROUP};
+use chop_reads::status_server::{start_status_server, ProgressCounters};
+use chop_reads::strategy::{BreakpointList, ChunkSizeDist, ChunkSizeTable, IndelSplit, Jitter, LowQualityStretch, RandomSize, RefSkipSplit, SupplementarySplit};
+use chop_reads::validate::check_record;
 
 
 #[derive(Parser, Debug)]
+#[command(name = "chop-reads")]
 struct Cli {
-    /// Input file to chop records from
-    #[arg(short, long)]
-    input: PathBuf,
+    /// Input file or htslib URL (e.g. https://, s3://, gs://) to chop records from, or - for stdin. May be repeated or a quoted glob
+    #[arg(short, long, required_unless_present("input_list"))]
+    input: Vec<PathBuf>,
+
+    /// File listing further inputs, one per line
+    #[arg(long)]
+    input_list: Option<PathBuf>,
+
+    /// Only chop records overlapping this region (chr, chr:start or chr:start-end), fetched through the inputs' indexes
+    #[arg(short='L', long, value_parser=Region::parse)]
+    region: Option<Region>,
+
+    /// Only chop records overlapping the intervals of this BED file, reading each record once even if it spans several
+    #[arg(long, conflicts_with("region"))]
+    regions_bed: Option<PathBuf>,
 
     /// Path to reference file to use with crams
-    #[arg(short, long)]
+    #[arg(short, long, visible_short_alias='T')]
     reference: Option<PathBuf>,
 
-    /// Path to write output to
+    /// Path or htslib URL to write output to, or - for stdout
     #[arg(short, long)]
     output: PathBuf,
 
-    /// Length of chunks to split records into
-    #[arg(short='s', long)]
-    chunk_size: u32,
+    /// Also write every chunk as FASTQ to this path (bgzipped if ending in .gz), alongside the main output
+    #[arg(long)]
+    fastq: Option<PathBuf>,
+
+    /// Also write every chunk as FASTA to this path (bgzipped if ending in .gz), alongside the main output
+    #[arg(long)]
+    fasta: Option<PathBuf>,
+
+    /// Format to write output in [default: from output extension, else bam]
+    #[arg(short='O', long, value_enum)]
+    output_format: Option<OutputFormat>,
+
+    /// Write uncompressed (level 0) BAM, for piping into another tool
+    #[arg(short='u', long, conflicts_with("compression_level"))]
+    uncompressed: bool,
+
+    /// BGZF compression level for BAM output, from 0 (none) to 9 (smallest)
+    #[arg(long, value_parser=clap::value_parser!(u32).range(0..=9))]
+    compression_level: Option<u32>,
+
+    /// Records per slice of CRAM output [default: htslib's]
+    #[arg(long, value_parser=clap::value_parser!(u32).range(1..))]
+    cram_seqs_per_slice: Option<u32>,
+
+    /// Slices per container of CRAM output [default: htslib's]
+    #[arg(long, value_parser=clap::value_parser!(u32).range(1..))]
+    cram_slices_per_container: Option<u32>,
+
+    /// With --chunk-by tile and --sort coordinate, start a new CRAM container at every tile, so fetching a tile only decodes containers within it
+    #[arg(long)]
+    cram_tile_containers: bool,
+
+    /// Overwrite existing output files
+    #[arg(long)]
+    force: bool,
+
+    /// Write directly to the output path instead of a temp file renamed on completion (e.g. for FIFOs)
+    #[arg(long)]
+    no_atomic: bool,
+
+    /// Buffer size in KiB for FASTA/FASTQ outputs (htslib sizes BAM/SAM/CRAM buffers from the filesystem's block size)
+    #[arg(long, default_value_t=64, value_parser=clap::value_parser!(u32).range(1..))]
+    write_buffer_size: u32,
+
+    /// When to fsync outputs: never, end, or periodic:N to also sync every N chunks
+    #[arg(long, default_value="never")]
+    fsync: FsyncPolicy,
+
+    /// Index the output once written, as .bai (.csi if a contig is over 2^29 bp) or .crai. The output must be coordinate-sorted
+    #[arg(long)]
+    write_index: bool,
+
+    /// Sort the output, spilling sorted runs to temporary files when it doesn't fit in --sort-memory
+    #[arg(long, value_enum)]
+    sort: Option<SortOrder>,
+
+    /// Memory in MiB to buffer records in before spilling a sorted run to disk
+    #[arg(long, default_value_t=768, requires("sort"))]
+    sort_memory: usize,
+
+    /// Directory for spilled sort runs [default: next to the output]
+    #[arg(long, requires("sort"))]
+    sort_tmp_dir: Option<PathBuf>,
+
+    /// Write one output per read group in the header, named like out.<rg>.bam or by an output path template like out/{rg}.bam, and list them in out.manifest.tsv
+    #[arg(long, conflicts_with_all(["sort", "igv_session", "read_group"]))]
+    split_by_rg: bool,
+
+    /// Spread reads round-robin across this many outputs, named like out.<n>.bam or by an output path template like out/{shard}.bam, and listed in out.manifest.tsv
+    #[arg(long, value_parser=clap::value_parser!(u32).range(1..), conflicts_with_all(["split_by_rg", "sort", "igv_session"]))]
+    shards: Option<u32>,
+
+    /// With FASTA or FASTQ output, note each chunk's reference span and offsets in its parent in the header comment (oc:chr:start-end qo:start-end)
+    #[arg(long)]
+    origin_comment: bool,
+
+    /// With FASTA or FASTQ output, mask this many bases next to each boundary where a read was cut into chunks
+    #[arg(long, conflicts_with_all(["sort", "pair_chunks"]))]
+    mask_boundary: Option<usize>,
+
+    /// How --mask-boundary masks bases
+    #[arg(long, value_enum, default_value_t=MaskStyle::Lower, requires("mask_boundary"))]
+    mask_style: MaskStyle,
+
+    /// Instead of chopping, cut each read into synthetic R1/R2 pairs of --chunk-size bases for FASTQ output, interleaved unless --r2-output is given
+    #[arg(long, conflicts_with_all(["skip_clipped_bases", "origin_comment", "liftover_out", "emit_junctions", "igv_session"]))]
+    pair_chunks: bool,
+
+    /// Bases between the two mates of each --pair-chunks fragment
+    #[arg(long, default_value_t=0, requires("pair_chunks"))]
+    inner_distance: usize,
+
+    /// Also cut an R1/R2 pair from each chunk for FASTQ output, as gap=G,len=L: R1 is the chunk's first L bases and R2 the reverse
+    /// complement of the L bases from G bases after the chunk starts, which may lie in the next chunk
+    #[arg(long, conflicts_with_all(["pair_chunks", "origin_comment", "mask_boundary", "igv_session"]))]
+    synthetic_pairs: Option<SyntheticPairs>,
+
+    /// Path to write R2 of --pair-chunks or --synthetic-pairs to, leaving R1 in the output
+    #[arg(long, conflicts_with_all(["sort", "split_by_rg", "shards"]))]
+    r2_output: Option<PathBuf>,
+
+    /// Length of chunks to split records into, or 0 to only split at --breakpoints-bed
+    #[arg(short='s', long, required_unless_present_any(["preset", "num_chunks", "chunk_size_dist", "chunk_size_table", "breakpoints_bed", "exons_gtf", "split_at_n", "split_at_indel", "split_at_lowq", "split_at_sa"]))]
+    chunk_size: Option<u32>,
+
+    /// Draw each chunk's length from a distribution instead of using --chunk-size: normal:MEAN,SD or uniform:MIN,MAX
+    #[arg(long, conflicts_with_all(["chunk_size", "num_chunks", "balance", "overlap", "step", "pair_chunks"]))]
+    chunk_size_dist: Option<ChunkSizeDist>,
+
+    /// Size chunks by read length instead of using --chunk-size, as LEN:SIZE rows giving the chunk size for reads of at least LEN bases, starting from 0 (e.g. 0:150,1000:500,10000:2000)
+    #[arg(long, conflicts_with_all(["chunk_size", "chunk_size_dist", "num_chunks", "balance", "overlap", "step", "pair_chunks"]))]
+    chunk_size_table: Option<ChunkSizeTable>,
+
+    /// Shift each boundary between chunks randomly by up to this many bases either way, so they don't fall at regular intervals
+    #[arg(long, default_value_t=0, conflicts_with_all(["num_chunks", "balance", "pair_chunks"]))]
+    jitter: u32,
+
+    /// Split each read into this many chunks of near-equal length instead of by --chunk-size
+    #[arg(short='k', long, value_parser=clap::value_parser!(u32).range(1..), conflicts_with_all(["chunk_size", "overlap", "step", "pair_chunks"]))]
+    num_chunks: Option<u32>,
+
+    /// Split each read into chunks of near-equal length as close to --chunk-size as possible, rather than leaving a short remainder
+    #[arg(long, conflicts_with_all(["num_chunks", "overlap", "step", "pair_chunks"]))]
+    balance: bool,
+
+    /// Bases shared by consecutive chunks, so chunks start every --chunk-size minus this many bases
+    #[arg(long, default_value_t=0, conflicts_with("pair_chunks"))]
+    overlap: u32,
+
+    /// Start a chunk every this many bases, up to --chunk-size, sliding a window of --chunk-size bases along each read
+    #[arg(long, conflicts_with_all(["overlap", "pair_chunks"]))]
+    step: Option<u32>,
+
+    /// Also split every record overlapping an interval of this BED file at its start and end
+    #[arg(long, conflicts_with("pair_chunks"))]
+    breakpoints_bed: Option<PathBuf>,
+
+    /// Move chunk boundaries falling inside an interval of this BED file (e.g. repeats) to its nearer end
+    #[arg(long, conflicts_with("pair_chunks"))]
+    avoid_bed: Option<PathBuf>,
+
+    /// Move chunk boundaries out of homopolymers of the reference longer than this, where splitting biases indel calls
+    #[arg(long, value_parser=clap::value_parser!(u64).range(1..), requires("reference"), conflicts_with("pair_chunks"))]
+    avoid_homopolymers: Option<u64>,
+
+    /// Move each chunk boundary to the nearest position listed in this file (`contig position` per line, 1-based) within --snap-tolerance, so chunks start on it
+    #[arg(long, conflicts_with("pair_chunks"))]
+    snap_to: Option<PathBuf>,
+
+    /// How far in reference bases a boundary may move to reach a position of --snap-to
+    #[arg(long, default_value_t=50, requires("snap_to"))]
+    snap_tolerance: u32,
+
+    /// Also split records at the ends of every exon of this GTF or GFF file and at reference skips (as --split-at-n), so each chunk lies within an exon
+    #[arg(long, conflicts_with_all(["num_chunks", "balance", "overlap", "step", "pair_chunks"]))]
+    exons_gtf: Option<PathBuf>,
+
+    /// Also split records at every reference skip (N), e.g. introns of RNA-seq alignments, leaving it out of the chunks
+    #[arg(long, conflicts_with_all(["num_chunks", "balance", "overlap", "step", "pair_chunks"]))]
+    split_at_n: bool,
+
+    /// Also split records at every insertion or deletion of at least this many bases, leaving it out of the chunks
+    #[arg(long, value_parser=clap::value_parser!(u32).range(1..), conflicts_with_all(["num_chunks", "balance", "overlap", "step", "pair_chunks"]))]
+    split_at_indel: Option<u32>,
+
+    /// Also split records around every run of at least LEN bases with mean base quality below QUAL, given as QUAL,LEN, so it forms chunks of its own
+    #[arg(long, conflicts_with_all(["num_chunks", "balance", "overlap", "step", "pair_chunks"]))]
+    split_at_lowq: Option<LowQualityStretch>,
+
+    /// Also split primary records of chimeric reads where each alignment in their SA tag starts and ends, so every chunk comes from one linear segment
+    #[arg(long, conflicts_with_all(["num_chunks", "balance", "overlap", "step", "pair_chunks"]))]
+    split_at_sa: bool,
+
+    /// Start from parameters suited to a platform, which flags given explicitly override, whether the same or conflicting with them
+    #[arg(long, value_enum)]
+    preset: Option<Preset>,
+
+    /// Which bases count towards --chunk-size and --min-length
+    #[arg(long, value_enum, default_value_t=ChunkBy::Query)]
+    chunk_by: ChunkBy,
+
+    /// Where --chunk-by tile counts tiles from: zero (the contig start), pos (each read's start) or offset:N (0-based position N)
+    #[arg(long, default_value="zero")]
+    bin_anchor: BinAnchor,
 
     /// Min record length to include in chopped outputs when handling final chunk
     #[arg(long, default_value_t=0)]
     min_length: u32,
 
-    // /// Toggle whether to skip softclipped bases at edges of record
-    // #[arg(long, default_value_t=true)]
-    // skip_clipped_bases: bool,
+    /// What to do with final chunks shorter than --min-length
+    #[arg(long, value_enum, default_value_t=RemainderPolicy::Drop, conflicts_with_all(["overlap", "step"]))]
+    remainder: RemainderPolicy,
+
+    /// What becomes of soft clipped bases at the edges of records, which take up chunk numbers whichever it is, so
+    /// chunks are named alike under each
+    #[arg(long, value_enum, default_value_t=ClipPolicy::Attach)]
+    clip_policy: ClipPolicy,
+
+    /// Same as --clip-policy drop
+    #[arg(long, conflicts_with("clip_policy"))]
+    skip_clipped_bases: bool,
+
+    /// How chunk CIGARs represent the bases of the parent read outside them
+    #[arg(long, value_enum, default_value_t=ClipMode::None)]
+    clip_mode: ClipMode,
 
     /// Read group value to use for new split records
     #[arg(short='g', long)]
@@ -45,40 +272,956 @@ struct Cli {
     #[arg(short='n', long, requires("read_group"))]
     sample_name: Option<String>,
 
-    /// Number of threads to use
-    // #[arg(short, long, default_value_t=1)]
-    // threads: u32,
+    /// Tag each chunk with its fractional start position within the parent read as sequenced (fp:f)
+    #[arg(long)]
+    position_tag: bool,
+
+    /// Tag each chunk with its estimated raw signal samples (sn:i), shared out from the parent's ns/ts tags in proportion to its bases
+    #[arg(long)]
+    signal_tag: bool,
+
+    /// Raw signal samples per base to estimate sn:i from for parents without an ns tag
+    #[arg(long, default_value_t=10.0, requires("signal_tag"))]
+    samples_per_base: f64,
+
+    /// How to handle records whose CIGAR and SEQ lengths disagree
+    #[arg(long, value_enum, default_value_t=CigarSeqMismatchPolicy::Error)]
+    cigar_seq_mismatch: CigarSeqMismatchPolicy,
+
+    /// Sequence dictionary (e.g. ref.dict) whose @SQ lines replace the input's, remapping records onto it
+    #[arg(long)]
+    target_dict: Option<PathBuf>,
+
+    /// What to do with records whose RG is missing from the header's @RG lines [default: leave them as they are]
+    #[arg(long, value_enum)]
+    missing_rg: Option<MissingReadGroupPolicy>,
+
+    /// Abort unless every record's read group has this sample (SM), to catch mixed-up inputs before chopping
+    #[arg(long)]
+    expect_sample: Option<String>,
+
+    /// What to do with records on contigs absent from --target-dict
+    #[arg(long, value_enum, default_value_t=AbsentContigPolicy::Drop, requires("target_dict"))]
+    absent_contig: AbsentContigPolicy,
+
+    /// Check every chunk against the SAM spec (QNAME, CIGAR, POS and mandatory fields), stopping at the first that breaks it
+    #[arg(long)]
+    strict: bool,
+
+    /// Only chop the first of consecutive records with identical qname, flags, position and CIGAR
+    #[arg(long)]
+    skip_duplicate_records: bool,
+
+    /// Trim this many bases from both ends of every chunk, where alignments near the cut are least certain
+    #[arg(long, default_value_t=0, conflicts_with_all(["remainder", "pair_chunks"]))]
+    chunk_trim: u32,
+
+    /// Also end chunks once they span this many reference bases, splitting long deletions between chunks and leaving the pieces at chunk ends out
+    #[arg(long, value_parser=clap::value_parser!(u32).range(1..), conflicts_with_all(["num_chunks", "balance", "overlap", "step", "remainder", "pair_chunks"]))]
+    max_ref_span: Option<u32>,
+
+    /// What becomes of deletions split where chunks end, as --chunk-by ref and tile do to deletions spanning a chunk boundary
+    #[arg(long, value_enum, default_value_t=DeletionPolicy::Split)]
+    deletion_policy: DeletionPolicy,
+
+    /// Leave the low quality ends of reads out of their chunks, trimmed with this quality threshold as bwa -q does
+    #[arg(long)]
+    qual_trim: Option<u8>,
+
+    /// Drop chunks whose mean base quality is below this value
+    #[arg(long)]
+    min_mean_qual: Option<f64>,
+
+    /// Only write the first this many chunks of each read from its 5' end, e.g. 1 for just the start of every read
+    #[arg(long, value_parser=clap::value_parser!(u64).range(1..), conflicts_with("pair_chunks"))]
+    max_chunks: Option<u64>,
+
+    /// Fraction of chunks to drop at random from each read, for augmented training data. A read's last remaining chunk is always kept
+    #[arg(long, default_value_t=0.0)]
+    dropout: f64,
+
+    /// Seed for --dropout, --chunk-size-dist and --jitter, so every run drops the same chunks and draws the same sizes
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// MAPQ for chunks: inherit, cap:N, zero or length-scaled
+    #[arg(long, default_value="inherit")]
+    mapq_policy: MapqPolicy,
+
+    /// Path to write records dropped whole to, tagged with the reason (rj:Z); SAM if it ends in .sam, else BAM
+    #[arg(long)]
+    rejects: Option<PathBuf>,
+
+    /// Write each original record, untouched other than --parent-mark (e.g. tag, to tell it from its chunks), ahead of its chunks
+    #[arg(long, visible_alias="keep-original")]
+    keep_parent: bool,
+
+    /// Write reads with fewer bases than this as they are, with all their tags, instead of chopping them
+    #[arg(long, conflicts_with("pair_chunks"))]
+    passthrough_below: Option<u32>,
+
+    /// How to mark records written by --keep-parent
+    #[arg(long, value_enum, default_value_t=ParentMark::None, requires("keep_parent"))]
+    parent_mark: ParentMark,
+
+    /// Copy the parent's aux tags onto each chunk, except per-base tags (e.g. MM/ML/mv) which are stripped. MC is dropped and MQ follows --mapq-policy
+    #[arg(long)]
+    keep_tags: bool,
+
+    /// Path to write a TSV mapping each chunk to its parent's query and reference coordinates (bgzipped if ending in .gz)
+    #[arg(long)]
+    liftover_out: Option<PathBuf>,
+
+    /// Path to write a BED of reference positions where adjacent chunks of a read meet
+    #[arg(long)]
+    emit_junctions: Option<PathBuf>,
+
+    /// Path to write an IGV session file referencing the output
+    #[arg(long)]
+    igv_session: Option<PathBuf>,
+
+    /// Comment line to add to the output header as @CO (may be repeated)
+    #[arg(long)]
+    comment: Vec<String>,
+
+    /// Tag chunks with this many reference bases before (lf) and after (rf) them
+    #[arg(long, requires("reference"))]
+    ref_context: Option<u64>,
+
+    /// Compare each chunk against the reference and tag those whose inherited CIGAR doesn't fit it (mr:f)
+    #[arg(long, requires("reference"))]
+    check_alignment: bool,
+
+    /// Fraction of mismatched aligned bases above which --check-alignment flags a chunk
+    #[arg(long, default_value_t=0.1, requires("check_alignment"))]
+    max_mismatch_rate: f64,
+
+    /// Path to write an HTML summary of the run to
+    #[arg(long)]
+    report: Option<PathBuf>,
+
+    /// Path to write run statistics as JSON, broken down per contig and per read group
+    #[arg(long)]
+    stats_json: Option<PathBuf>,
+
+    /// Port to serve JSON progress on over HTTP
+    #[arg(long)]
+    status_port: Option<u16>,
+
+    /// Address to bind the status endpoint to
+    #[arg(long, default_value="127.0.0.1", requires("status_port"))]
+    status_bind: String,
+
+    /// Number of htslib threads to use for reading and writing, unless overridden
+    #[arg(short='@', long, default_value_t=1)]
+    threads: usize,
+
+    /// Number of htslib threads to use for decompressing input [default: --threads]
+    #[arg(long)]
+    read_threads: Option<usize>,
+
+    /// Number of records to read ahead on a separate thread while chopping, or 0 to read inline
+    #[arg(long, default_value_t=1024)]
+    prefetch: usize,
+
+    /// Number of htslib threads to use for compressing output [default: --threads]
+    #[arg(long)]
+    write_threads: Option<usize>,
+}
+
+// Parse the command line, adding the flags of any --preset that weren't given explicitly ahead of the rest
+fn parse_args(mut argv: Vec<OsString>) -> Cli {
+    // Explicit flags may lean on the preset's (e.g. --inner-distance on --pair-chunks), so only check them once it's applied
+    let command = Cli::command();
+    let matches = command.clone().ignore_errors(true).get_matches_from(&argv);
+    let Some(preset) = matches.get_one::<Preset>("preset") else {
+        return Cli::parse_from(argv);
+    };
+    let explicit = command.get_arguments().filter(|arg| matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine)).collect::<Vec<_>>();
+    let given = |flag: &str| explicit.iter().any(|arg| arg.get_id() == flag.replace('-', "_").as_str());
+    // Explicit flags also override the preset's that can't be given with them, e.g. --num-chunks its --chunk-size
+    let conflicting = |flag: &str| {
+        let arg = command.get_arguments().find(|arg| arg.get_id() == flag.replace('-', "_").as_str()).unwrap();
+        let conflicts = |a: &clap::Arg, b: &clap::Arg| command.get_arg_conflicts_with(a).iter().any(|conflict| conflict.get_id() == b.get_id());
+        explicit.iter().any(|other| conflicts(arg, other) || conflicts(other, arg))
+    };
+    let output_format = matches.get_one::<OutputFormat>("output_format").copied().or_else(|| OutputFormat::from_path(matches.get_one::<PathBuf>("output")?));
+    // A conflict drops the flags that go with the preset's too, while a flag given explicitly only replaces its own
+    let preset_args = preset.args(output_format == Some(OutputFormat::Fastq)).into_iter()
+        .filter(|group| !group.iter().any(|(flag, _)| conflicting(flag)))
+        .flatten()
+        .filter(|(flag, _)| !given(flag))
+        .flat_map(|(flag, value)| std::iter::once(format!("--{}", flag)).chain(value.map(String::from)));
+    argv.splice(1..1, preset_args.map(OsString::from));
+    Cli::parse_from(argv)
 }
 
 fn main() {
     let now = Instant::now();
 
-    let args = Cli::parse();
+    let args = parse_args(std::env::args_os().collect());
+
+    let output_format = args.output_format.or_else(|| OutputFormat::from_path(&args.output)).unwrap_or(OutputFormat::Bam);
+    if output_format == OutputFormat::Cram && args.reference.is_none() {
+        Cli::command().error(ErrorKind::MissingRequiredArgument, "CRAM output requires --reference").exit();
+    }
+    if output_format != OutputFormat::Cram && (args.cram_seqs_per_slice.is_some() || args.cram_slices_per_container.is_some() || args.cram_tile_containers) {
+        Cli::command().error(ErrorKind::ArgumentConflict, "--cram-seqs-per-slice, --cram-slices-per-container and --cram-tile-containers need CRAM output").exit();
+    }
+    // Containers can only line up with tiles fixed on the reference if records arrive in order
+    if args.cram_tile_containers && (args.chunk_by != ChunkBy::Tile || args.bin_anchor == BinAnchor::Pos || args.sort != Some(SortOrder::Coordinate)) {
+        Cli::command().error(ErrorKind::ArgumentConflict, "--cram-tile-containers needs --chunk-by tile with --bin-anchor zero or offset:N, and --sort coordinate").exit();
+    }
+    let to_stdout = args.output.as_os_str() == "-";
+    let to_url = hts_url(&args.output).is_some();
+    if (to_stdout || to_url) && args.igv_session.is_some() {
+        Cli::command().error(ErrorKind::ArgumentConflict, "--igv-session needs a local output file").exit();
+    }
+    // Field each split output is named by, filling the placeholder of an output path template
+    let split_field = if args.shards.is_some() { Some("shard") } else { args.split_by_rg.then_some("rg") };
+    let splitting = split_field.is_some();
+    if (to_stdout || to_url) && splitting {
+        Cli::command().error(ErrorKind::ArgumentConflict, "--split-by-rg and --shards need a local output path to name their outputs after").exit();
+    }
+    if let Some(e) = split_field.and_then(|field| check_template(&args.output, field).err()) {
+        Cli::command().error(ErrorKind::ValueValidation, e).exit();
+    }
+    if (to_stdout || to_url) && args.write_index {
+        Cli::command().error(ErrorKind::ArgumentConflict, "--write-index needs a local output file").exit();
+    }
+    if to_url && output_format.is_fastx() {
+        Cli::command().error(ErrorKind::ArgumentConflict, "FASTA and FASTQ output need a local output path or stdout").exit();
+    }
+    if !output_format.is_fastx() && args.origin_comment {
+        Cli::command().error(ErrorKind::ArgumentConflict, "--origin-comment needs FASTA or FASTQ output").exit();
+    }
+    if !output_format.is_fastx() && args.fastq.is_none() && args.fasta.is_none() && args.mask_boundary.is_some() {
+        Cli::command().error(ErrorKind::ArgumentConflict, "--mask-boundary needs FASTA or FASTQ output, or --fastq or --fasta").exit();
+    }
+    // Random chunk sizes are scaled against their mean, e.g. for --mapq-policy length-scaled
+    let chunk_size = args.chunk_size.or(args.chunk_size_dist.map(|dist| dist.mean().round() as u32)).unwrap_or(0);
+    if chunk_size == 0 && args.chunk_size_table.is_none() && args.breakpoints_bed.is_none() && args.exons_gtf.is_none() && args.num_chunks.is_none() && !args.split_at_n && args.split_at_indel.is_none() && args.split_at_lowq.is_none() && !args.split_at_sa {
+        Cli::command().error(ErrorKind::ValueValidation, "--chunk-size must be positive unless splitting at --breakpoints-bed, --exons-gtf, --split-at-n, --split-at-indel, --split-at-lowq or --split-at-sa").exit();
+    }
+    if (args.num_chunks.is_some() || args.balance || args.chunk_size_dist.is_some() || args.chunk_size_table.is_some() || args.jitter > 0) && args.chunk_by == ChunkBy::Tile {
+        Cli::command().error(ErrorKind::ArgumentConflict, "--num-chunks, --balance, --chunk-size-dist, --chunk-size-table and --jitter can't anchor chunks to tiles with --chunk-by tile").exit();
+    }
+    if args.bin_anchor != BinAnchor::Zero && args.chunk_by != ChunkBy::Tile {
+        Cli::command().error(ErrorKind::ArgumentConflict, "--bin-anchor needs --chunk-by tile").exit();
+    }
+    if args.step.is_some_and(|step| step == 0 || step > chunk_size) {
+        Cli::command().error(ErrorKind::ValueValidation, "--step must be between 1 and --chunk-size").exit();
+    }
+    if chunk_size > 0 && args.overlap >= chunk_size {
+        Cli::command().error(ErrorKind::ValueValidation, "--overlap must be less than --chunk-size").exit();
+    }
+    if args.jitter > 0 && 2 * args.jitter >= args.step.unwrap_or(chunk_size.saturating_sub(args.overlap)) {
+        Cli::command().error(ErrorKind::ValueValidation, "--jitter must be less than half the distance between chunk starts").exit();
+    }
+    if !(0.0..=1.0).contains(&args.dropout) {
+        Cli::command().error(ErrorKind::ValueValidation, "--dropout must be between 0 and 1").exit();
+    }
+    if output_format != OutputFormat::Fastq && args.pair_chunks {
+        Cli::command().error(ErrorKind::ArgumentConflict, "--pair-chunks needs FASTQ output").exit();
+    }
+    if output_format != OutputFormat::Fastq && args.synthetic_pairs.is_some() {
+        Cli::command().error(ErrorKind::ArgumentConflict, "--synthetic-pairs needs FASTQ output").exit();
+    }
+    if args.r2_output.is_some() && !args.pair_chunks && args.synthetic_pairs.is_none() {
+        Cli::command().error(ErrorKind::MissingRequiredArgument, "--r2-output needs --pair-chunks or --synthetic-pairs").exit();
+    }
+    if (output_format == OutputFormat::Sam || output_format.is_fastx()) && args.write_index {
+        Cli::command().error(ErrorKind::ArgumentConflict, "--write-index needs BAM or CRAM output").exit();
+    }
+
+    let mut inputs = args.input.clone();
+    if let Some(list_path) = &args.input_list {
+        let list = std::fs::read_to_string(list_path).expect("Unable to read input list");
+        inputs.extend(list.lines().map(str::trim).filter(|line| !line.is_empty()).map(PathBuf::from));
+    }
+    let inputs = expand_globs(&inputs).unwrap_or_else(|e| panic!("{}", e));
+    let regions = match (&args.region, &args.regions_bed) {
+        (Some(region), _) => Some(vec![region.clone()]),
+        (None, Some(bed_path)) => Some(read_bed(bed_path).unwrap_or_else(|e| panic!("Unable to read {}: {}", bed_path.display(), e))),
+        (None, None) => None,
+    };
+    if regions.is_some() && inputs.iter().any(|input| input.as_os_str() == "-") {
+        Cli::command().error(ErrorKind::ArgumentConflict, "--region and --regions-bed need indexed inputs, not stdin").exit();
+    }
 
-    let mut hts_reader = hts_bam::Reader::from_path(args.input).unwrap();
-    let mut header = hts_bam::header::Header::from_template(hts_reader.header());
+    // Refuse to clobber an input or earlier output before opening anything
+    let local_inputs = inputs.iter().filter(|input| input.as_os_str() != "-" && hts_url(input).is_none()).cloned().collect::<Vec<_>>();
+    if !to_stdout && !to_url && !splitting {
+        if let Err(e) = check_output_path(&args.output, &local_inputs, args.force) {
+            Cli::command().error(ErrorKind::ArgumentConflict, e).exit();
+        }
+    }
+    // Extra sequence outputs, with whether each has qualities
+    let extra_outputs = [(&args.fastq, true), (&args.fasta, false)].into_iter()
+        .filter_map(|(path, qualities)| path.clone().map(|path| (path, qualities)))
+        .collect::<Vec<_>>();
+    // Side outputs streamed as records are chopped, renamed into place at the end like the main output
+    let side_outputs = [&args.rejects, &args.emit_junctions, &args.liftover_out].into_iter().flatten().collect::<Vec<_>>();
+    let summaries = [&args.report, &args.stats_json, &args.igv_session].into_iter().flatten();
+    for path in args.r2_output.iter().chain(extra_outputs.iter().map(|(path, _)| path)).chain(side_outputs.iter().copied()).chain(summaries) {
+        if let Err(e) = check_output_path(path, &local_inputs, args.force) {
+            Cli::command().error(ErrorKind::ArgumentConflict, e).exit();
+        }
+    }
 
-    if let Some(rg) = &args.read_group {
-        let mut header_record = HeaderRecord::new(b"RG");
-        header_record.push_tag(b"ID", rg);
-        if let Some(sn) = args.sample_name {
-            header_record.push_tag(b"SM", &sn);
+    let read_threads = args.read_threads.unwrap_or(args.threads);
+    let mut hts_readers = inputs.iter().map(|input| {
+        let from_stdin = input.as_os_str() == "-";
+        let url = hts_url(input);
+        let mut hts_reader = InputReader::open(input, regions.as_deref()).unwrap_or_else(|e| panic!("Unable to open {}: {}", input.display(), e));
+        if let Some(reference) = &args.reference {
+            hts_reader.set_reference(reference).expect("Unable to set reference for input");
+        } else if !from_stdin && url.is_none() && is_cram(input).unwrap_or(false) {
+            eprintln!("Warning: CRAM input {} given without --reference, htslib will look up sequences via REF_PATH/REF_CACHE", input.display());
         }
+        if read_threads > 1 {
+            hts_reader.set_threads(read_threads).expect("Unable to set reader threads");
+        }
+        PrefetchReader::new(hts_reader, args.prefetch)
+    }).collect::<Vec<_>>();
+
+    // Records from each input are moved onto the merged header's contigs
+    let (mut template, mut merge_remappers) = if hts_readers.len() > 1 {
+        let headers = hts_readers.iter().map(|reader| reader.header().clone()).collect::<Vec<_>>();
+        let (merged, tid_maps) = merge_headers(&headers);
+        (merged, tid_maps.into_iter().map(|tid_map| Some(TidRemapper::new(tid_map, AbsentContigPolicy::Drop))).collect())
+    } else {
+        (hts_readers[0].header().clone(), vec![None])
+    };
+    let read_group = args.read_group.as_ref().map(|rg| ReadGroup { id: rg.clone(), sample: args.sample_name.clone() });
+    let command_line = std::env::args().collect::<Vec<_>>().join(" ");
+    let mut tid_remapper = None;
+    if let Some(dict_path) = &args.target_dict {
+        let dict = SequenceDictionary::from_path(dict_path).expect("Unable to read target dictionary");
+        tid_remapper = Some(TidRemapper::new(dict.tid_map(&template), args.absent_contig));
+        template = replace_sequences(&template, dict.sq_lines());
+    }
+    if let (Some(reference), OutputFormat::Cram) = (&args.reference, output_format) {
+        template = add_reference_uri(&template, reference);
+    }
+    if let Some(order) = args.sort {
+        template = set_sort_order(&template, order.header_value());
+    }
+    if args.write_index && sort_order(&template).as_deref() != Some("coordinate") {
+        Cli::command().error(ErrorKind::ArgumentConflict, "--write-index needs coordinate-sorted input (@HD SO:coordinate) or --sort coordinate").exit();
+    }
+    let mut known_read_groups = read_group_ids(&template);
+    if args.missing_rg == Some(MissingReadGroupPolicy::Add) {
+        if inputs.iter().any(|input| input.as_os_str() == "-") {
+            Cli::command().error(ErrorKind::ArgumentConflict, "--missing-rg add needs a first pass over the inputs, so can't read stdin").exit();
+        }
+        // The header is written before any records, so find the missing read groups up front
+        let mut missing = BTreeSet::new();
+        let mut record = hts_bam::Record::new();
+        for input in &inputs {
+            let mut scan_reader = InputReader::open(input, regions.as_deref()).unwrap_or_else(|e| panic!("Unable to open {}: {}", input.display(), e));
+            while let Some(r) = scan_reader.read(&mut record) {
+                r.expect("Failed to parse record");
+                if let Ok(Aux::String(rg)) = record.aux(b"RG") {
+                    if !known_read_groups.contains(rg) {
+                        missing.insert(rg.to_string());
+                    }
+                }
+            }
+        }
+        if !missing.is_empty() {
+            eprintln!("Adding @RG lines for read groups missing from the header: {}", missing.iter().cloned().collect::<Vec<_>>().join(", "));
+            template = add_read_groups(&template, &missing.iter().cloned().collect::<Vec<_>>());
+            known_read_groups.extend(missing);
+        }
+    }
+    let header = build_output_header(&template, read_group.as_ref(), &command_line, &args.comment);
+    let samples = read_group_samples(&template);
+
+    let compression_level = match (args.uncompressed, args.compression_level) {
+        (true, _) => Some(hts_bam::CompressionLevel::Uncompressed),
+        (false, Some(level)) => Some(hts_bam::CompressionLevel::Level(level)),
+        (false, None) => None,
+    };
+    let write_threads = args.write_threads.unwrap_or(args.threads);
+    let cram_layout = CramLayout {
+        seqs_per_slice: args.cram_seqs_per_slice,
+        slices_per_container: args.cram_slices_per_container,
+        tile_bins: args.cram_tile_containers.then(|| TileBins {
+            anchor: if let BinAnchor::Offset(offset) = args.bin_anchor { offset } else { 0 },
+            width: args.step.unwrap_or(chunk_size - args.overlap) as i64,
+        }),
+    };
+    // FASTA/FASTQ paths are checked for .gz before the temp suffix is added
+    let gzip_fastx = args.output.extension().is_some_and(|ext| ext == "gz");
+    // Writes to stdout if not given a path
+    let open_writer = |path: Option<&Path>, header: &hts_bam::Header| {
+        let Some(format) = output_format.hts_format() else {
+            let qualities = output_format == OutputFormat::Fastq;
+            let mut writer = match path {
+                Some(path) => FastxWriter::with_capacity(path, qualities, gzip_fastx, args.write_buffer_size as usize * 1024).expect("Unable to create output"),
+                None => FastxWriter::to_stdout(qualities),
+            };
+            if let Some(len) = args.mask_boundary {
+                writer.set_mask_boundary(len, args.mask_style);
+            }
+            return RecordWriter::Fastx(writer);
+        };
+        if output_format == OutputFormat::Cram {
+            let mut writer = CramWriter::from_path(path.unwrap_or(Path::new("-")), header, args.reference.as_ref().unwrap(), cram_layout).unwrap_or_else(|e| panic!("{}", e));
+            if let Some(level) = compression_level {
+                writer.set_compression_level(level).expect("Unable to set compression level");
+            }
+            if write_threads > 1 {
+                writer.set_threads(write_threads).expect("Unable to set writer threads");
+            }
+            return RecordWriter::Cram(writer);
+        }
+        let mut writer = match path {
+            Some(path) => hts_bam::Writer::from_path(path, header, format),
+            None => hts_bam::Writer::from_stdout(header, format),
+        }.unwrap();
+        if let Some(level) = compression_level {
+            writer.set_compression_level(level).expect("Unable to set compression level");
+        }
+        if write_threads > 1 {
+            writer.set_threads(write_threads).expect("Unable to set writer threads");
+        }
+        RecordWriter::Hts(writer)
+    };
+    // Existing FIFOs and devices (e.g. /dev/null) must be written in place, as renaming over them would replace them
+    let atomic_path = |path: &Path| {
+        let special = std::fs::metadata(path).is_ok_and(|metadata| !metadata.is_file());
+        if args.no_atomic || special { path.to_path_buf() } else { temp_output_path(path) }
+    };
+
+    // Remote outputs can't be renamed into place, so are always written directly
+    let write_path = if to_stdout || to_url { args.output.clone() } else { atomic_path(&args.output) };
+    let mut hts_writer = (!splitting).then(|| open_writer((!to_stdout).then_some(write_path.as_path()), &header));
+    let r2_write_path = args.r2_output.as_deref().map(atomic_path);
+    let mut r2_writer = r2_write_path.as_deref().map(|path| open_writer(Some(path), &header));
+    let mut extra_writers = extra_outputs.iter().map(|(path, qualities)| {
+        let write_path = atomic_path(path);
+        let mut writer = FastxWriter::with_capacity(&write_path, *qualities, path.extension().is_some_and(|ext| ext == "gz"), args.write_buffer_size as usize * 1024)
+            .unwrap_or_else(|e| panic!("Unable to create {}: {}", path.display(), e));
+        if let Some(len) = args.mask_boundary {
+            writer.set_mask_boundary(len, args.mask_style);
+        }
+        (path, write_path, writer)
+    }).collect::<Vec<_>>();
+
+    let split_path = |name: &str| split_output_path(&args.output, split_field.unwrap(), name);
+    // Output path templates may put splits in directories of their own
+    let open_split = |path: &Path, header: &hts_bam::Header| {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir).unwrap_or_else(|e| panic!("Unable to create {}: {}", dir.display(), e));
+        }
+        open_writer(Some(&atomic_path(path)), header)
+    };
+    // Each read group in the header gets its own output, while records without one go to an unassigned output on demand
+    let mut split_outputs = args.split_by_rg.then(|| {
+        let mut outputs = SplitOutputs::new();
+        let mut ids = known_read_groups.iter().cloned().collect::<Vec<_>>();
+        ids.sort();
+        for id in ids.iter().map(String::as_str).chain([UNASSIGNED_READ_GROUP]) {
+            if let Err(e) = check_output_path(&split_path(id), &local_inputs, args.force) {
+                Cli::command().error(ErrorKind::ArgumentConflict, e).exit();
+            }
+        }
+        for id in &ids {
+            let path = split_path(id);
+            let header = build_output_header(&only_read_group(&template, Some(id)), None, &command_line, &args.comment);
+            outputs.add(id, path.clone(), atomic_path(&path), open_split(&path, &header));
+        }
+        outputs
+    }).or_else(|| args.shards.map(|shards| {
+        let mut outputs = SplitOutputs::new();
+        for shard in 0..shards {
+            let name = shard_name(shard, shards);
+            let path = split_path(&name);
+            if let Err(e) = check_output_path(&path, &local_inputs, args.force) {
+                Cli::command().error(ErrorKind::ArgumentConflict, e).exit();
+            }
+            outputs.add(&name, path.clone(), atomic_path(&path), open_split(&path, &header));
+        }
+        outputs
+    }));
+
+    let clip_policy = if args.skip_clipped_bases { ClipPolicy::Drop } else { args.clip_policy };
+    let mut alignment_chopper = AlignmentChopper::new(chunk_size, args.min_length, clip_policy, args.read_group.clone());
+    alignment_chopper.set_chunk_by(args.chunk_by);
+    alignment_chopper.set_bin_anchor(args.bin_anchor);
+    alignment_chopper.set_num_chunks(args.num_chunks);
+    alignment_chopper.set_balance(args.balance);
+    alignment_chopper.set_remainder(args.remainder);
+    alignment_chopper.set_clip_mode(args.clip_mode);
+    alignment_chopper.set_overlap(args.overlap);
+    if let Some(step) = args.step {
+        alignment_chopper.set_step(step);
+    }
+    if args.breakpoints_bed.is_some() || args.exons_gtf.is_some() {
+        let mut regions = match &args.breakpoints_bed {
+            Some(bed_path) => read_bed(bed_path).unwrap_or_else(|e| panic!("Unable to read {}: {}", bed_path.display(), e)),
+            None => Vec::new(),
+        };
+        if let Some(gtf_path) = &args.exons_gtf {
+            // Annotations often cover contigs the alignments don't, which have no records to split
+            let exons = read_gtf_exons(gtf_path).unwrap_or_else(|e| panic!("Unable to read {}: {}", gtf_path.display(), e));
+            regions.extend(exons.into_iter().filter(|exon| template.tid(exon.contig.as_bytes()).is_some()));
+        }
+        let breakpoints = region_breakpoints(&regions, &template).unwrap_or_else(|e| panic!("{}", e));
+        alignment_chopper.add_strategy(Box::new(BreakpointList::new(breakpoints)));
+    }
+    if args.split_at_n || args.exons_gtf.is_some() {
+        alignment_chopper.add_strategy(Box::new(RefSkipSplit));
+    }
+    if let Some(min_len) = args.split_at_indel {
+        alignment_chopper.add_strategy(Box::new(IndelSplit { min_len }));
+    }
+    if let Some(split_at_lowq) = args.split_at_lowq {
+        alignment_chopper.add_strategy(Box::new(split_at_lowq));
+    }
+    if args.split_at_sa {
+        alignment_chopper.add_strategy(Box::new(SupplementarySplit));
+    }
+    if let Some(dist) = args.chunk_size_dist {
+        alignment_chopper.add_strategy(Box::new(RandomSize::new(dist, args.seed)));
+    }
+    if let Some(table) = args.chunk_size_table.clone() {
+        alignment_chopper.add_strategy(Box::new(table));
+    }
+    if args.jitter > 0 {
+        alignment_chopper.add_strategy(Box::new(Jitter::new(args.jitter, args.seed)));
+    }
+    if args.avoid_bed.is_some() || args.avoid_homopolymers.is_some() {
+        let mut regions = match &args.avoid_bed {
+            Some(bed_path) => read_bed(bed_path).unwrap_or_else(|e| panic!("Unable to read {}: {}", bed_path.display(), e)),
+            None => Vec::new(),
+        };
+        if let Some(min_len) = args.avoid_homopolymers {
+            // Contigs missing from the reference have no runs to avoid
+            let reference = Reference::from_path(args.reference.as_ref().unwrap()).expect("Unable to load reference");
+            for contig in template.target_names().into_iter().map(|name| String::from_utf8_lossy(name).into_owned()) {
+                let runs = reference.homopolymers(&contig, min_len).unwrap_or_default();
+                regions.extend(runs.into_iter().map(|(start, end)| Region { contig: contig.clone(), start: start as i64, end: Some(end as i64) }));
+            }
+        }
+        alignment_chopper.set_avoid_intervals(&resolve_regions(&regions, &template).unwrap_or_else(|e| panic!("{}", e)));
+    }
+    if let Some(positions_path) = &args.snap_to {
+        // As with annotations, positions on contigs the alignments don't cover are never needed
+        let mut positions: HashMap<i32, Vec<i64>> = HashMap::new();
+        for (contig, pos) in read_positions(positions_path).unwrap_or_else(|e| panic!("Unable to read {}: {}", positions_path.display(), e)) {
+            if let Some(tid) = template.tid(contig.as_bytes()) {
+                positions.entry(tid as i32).or_default().push(pos);
+            }
+        }
+        alignment_chopper.set_snap_positions(positions, args.snap_tolerance);
+    }
+    alignment_chopper.set_position_tag(args.position_tag);
+    alignment_chopper.set_cigar_seq_mismatch(args.cigar_seq_mismatch);
+    alignment_chopper.set_signal_tag(args.signal_tag.then_some(args.samples_per_base));
+    alignment_chopper.set_min_mean_qual(args.min_mean_qual);
+    alignment_chopper.set_qual_trim(args.qual_trim);
+    alignment_chopper.set_chunk_trim(args.chunk_trim);
+    alignment_chopper.set_max_ref_span(args.max_ref_span);
+    alignment_chopper.set_deletion_policy(args.deletion_policy);
+    alignment_chopper.set_passthrough_below(args.passthrough_below);
+    alignment_chopper.set_mapq_policy(args.mapq_policy);
+    alignment_chopper.set_max_chunks(args.max_chunks.map(|max_chunks| max_chunks as usize));
+    alignment_chopper.set_dropout(args.dropout, args.seed);
+    alignment_chopper.set_keep_tags(args.keep_tags);
+    let mut pair_synthesizer = match args.synthetic_pairs {
+        Some(pairs) => Some(PairSynthesizer::at_chunks(pairs)),
+        None => args.pair_chunks.then(|| PairSynthesizer::new(chunk_size as usize, args.inner_distance)),
+    };
+
+    let mut rejects_writer = args.rejects.as_ref().map(|path| {
+        let format = if OutputFormat::from_path(path) == Some(OutputFormat::Sam) { hts_bam::Format::Sam } else { hts_bam::Format::Bam };
+        hts_bam::Writer::from_path(atomic_path(path), &header, format).expect("Unable to create rejects file")
+    });
+    let mut junction_writer = args.emit_junctions.as_ref().map(|path| JunctionWriter::from_path(&atomic_path(path)).expect("Unable to create junctions file"));
+    let mut liftover_writer = args.liftover_out.as_ref().map(|path| LiftoverWriter::from_path(&atomic_path(path)).expect("Unable to create liftover table"));
+    let header_view = template.clone();
 
-        header.push_record(&header_record);
+    let reference = (args.ref_context.is_some() || args.check_alignment).then(|| Reference::from_path(args.reference.as_ref().unwrap()).expect("Unable to load reference"));
+    let mut misaligned_chunks = 0;
+    let mut duplicate_filter = DuplicateFilter::new();
+    let mut stripped_read_groups = 0;
+
+    let progress = Arc::new(ProgressCounters::new());
+    if let Some(port) = args.status_port {
+        let addr = start_status_server(&format!("{}:{}", args.status_bind, port), progress.clone()).expect("Unable to start status server");
+        eprintln!("Serving status on http://{}", addr);
     }
 
-    let mut hts_writer = hts_bam::Writer::from_path(args.output, &header, Format::Bam).unwrap();
+    let mut report = (args.report.is_some() || args.stats_json.is_some()).then(|| {
+        let mut report = RunReport::new();
+        report.add_parameter("Command line", &command_line);
+        report.add_parameter("Input", &inputs.iter().map(|input| input.to_string_lossy()).collect::<Vec<_>>().join(", "));
+        report.add_parameter("Output", &args.output.to_string_lossy());
+        if let Some(preset) = args.preset {
+            report.add_parameter("Preset", preset.to_possible_value().unwrap().get_name());
+        }
+        match (args.num_chunks, args.chunk_size_dist) {
+            (Some(num_chunks), _) => report.add_parameter("Chunks per read", &num_chunks.to_string()),
+            (None, Some(dist)) => report.add_parameter("Chunk size distribution", &format!("{:?}", dist)),
+            (None, None) => report.add_parameter("Chunk size", &chunk_size.to_string()),
+        }
+        report.add_parameter("Chunk by", args.chunk_by.to_possible_value().unwrap().get_name());
+        if args.chunk_by == ChunkBy::Tile {
+            report.add_parameter("Bin anchor", &format!("{:?}", args.bin_anchor));
+        }
+        report.add_parameter("Overlap", &args.step.map_or(args.overlap, |step| chunk_size - step).to_string());
+        report.add_parameter("Jitter", &args.jitter.to_string());
+        report.add_parameter("Min length", &args.min_length.to_string());
+        report.add_parameter("Clip policy", clip_policy.to_possible_value().unwrap().get_name());
+        report.add_parameter("Clip mode", args.clip_mode.to_possible_value().unwrap().get_name());
+        report
+    });
 
-    let mut alignment_chopper = AlignmentChopper::new(args.chunk_size, args.min_length, args.skip_clipped_bases, args.read_group.clone());
+    // Spill runs go next to the output unless told otherwise
+    let mut sorter = args.sort.map(|order| {
+        let tmp_prefix = match &args.sort_tmp_dir {
+            Some(dir) => dir.join(format!("chop-reads.{}", std::process::id())),
+            None if to_stdout || to_url => std::env::temp_dir().join(format!("chop-reads.{}", std::process::id())),
+            None => write_path.clone(),
+        };
+        ExternalSorter::new(order, &header, args.sort_memory << 20, &tmp_prefix)
+    });
+    // Outputs other than split ones, which can be added as records arrive, to fsync periodically
+    let mut sync_paths = extra_writers.iter().map(|(_, write_path, _)| write_path.clone()).chain(r2_write_path.clone()).collect::<Vec<_>>();
+    if !to_stdout && !to_url && !splitting {
+        sync_paths.push(write_path.clone());
+    }
+    let mut chunks_since_sync = 0;
+    let mut write_chunk = |cr: &hts_bam::Record, parent_rg: Option<&str>, split_name: Option<&str>, cut_ends: (bool, bool)| {
+        for (_, _, writer) in extra_writers.iter_mut() {
+            writer.write_chunk(cr, cut_ends).expect("Cannot write record.");
+        }
+        match (sorter.as_mut(), split_outputs.as_mut(), hts_writer.as_mut()) {
+            (Some(sorter), _, _) => sorter.push(cr.clone()).expect("Unable to spill sorted records"),
+            (None, Some(outputs), _) if args.shards.is_some() => outputs.writer(split_name.unwrap()).unwrap().write_chunk(cr, cut_ends).expect("Cannot write record."),
+            (None, Some(outputs), _) => {
+                let name = split_name.unwrap();
+                if !outputs.contains(name) {
+                    let path = split_path(name);
+                    let header = build_output_header(&only_read_group(&template, None), None, &command_line, &args.comment);
+                    outputs.add(name, path.clone(), atomic_path(&path), open_split(&path, &header));
+                }
+                // Chunks only keep their parent's tags with --keep-tags, but should still say which read group they're in
+                let mut cr = cr.clone();
+                if let (Some(rg), Err(_)) = (parent_rg, cr.aux(b"RG")) {
+                    cr.push_aux(b"RG", Aux::String(rg)).expect("Unable to add RG tag");
+                }
+                outputs.writer(name).unwrap().write_chunk(&cr, cut_ends).expect("Cannot write record.");
+            }
+            (None, None, Some(writer)) => match r2_writer.as_mut() {
+                Some(r2_writer) if cr.is_last_in_template() => r2_writer.write(cr).expect("Cannot write record."),
+                _ => writer.write_chunk(cr, cut_ends).expect("Cannot write record."),
+            },
+            (None, None, None) => unreachable!("No output to write to"),
+        }
+        if let FsyncPolicy::Periodic(period) = args.fsync {
+            chunks_since_sync += 1;
+            if chunks_since_sync == period {
+                chunks_since_sync = 0;
+                let split_paths = split_outputs.iter().flat_map(|outputs| outputs.write_paths());
+                for path in sync_paths.iter().map(PathBuf::as_path).chain(split_paths) {
+                    sync_path(path).unwrap_or_else(|e| panic!("Unable to sync {}: {}", path.display(), e));
+                }
+            }
+        }
+    };
 
+    let mut next_shard = 0;
     let mut record = hts_bam::Record::new();
-    while let Some(r) = hts_reader.read(&mut record) {
-        r.expect("Failed to parse record");
-        for cr in alignment_chopper.chop_read(&record) {
-            hts_writer.write(&cr).expect("Cannot write record.");
+    for ((input, hts_reader), merge_remapper) in inputs.iter().zip(hts_readers.iter_mut()).zip(merge_remappers.iter_mut()) {
+        while let Some(r) = hts_reader.read(&mut record) {
+            r.expect("Failed to parse record");
+            progress.records_read.fetch_add(1, Ordering::Relaxed);
+            if let Some(remapper) = merge_remapper.as_mut() {
+                remapper.remap(&mut record);
+            }
+            if let Some(policy) = args.missing_rg {
+                let missing_rg = match record.aux(b"RG") {
+                    Ok(Aux::String(rg)) if !known_read_groups.contains(rg) => Some(rg.to_string()),
+                    _ => None,
+                };
+                match (policy, missing_rg) {
+                    (MissingReadGroupPolicy::Strip, Some(_)) => {
+                        record.remove_aux(b"RG").expect("Unable to remove RG tag");
+                        stripped_read_groups += 1;
+                    }
+                    (MissingReadGroupPolicy::Error, Some(rg)) => panic!("Record {} has read group {} missing from the header", String::from_utf8_lossy(record.qname()), rg),
+                    _ => {}
+                }
+            }
+            if let Some(expected) = &args.expect_sample {
+                let sample = match record.aux(b"RG") {
+                    Ok(Aux::String(rg)) => samples.get(rg).map(String::as_str),
+                    _ => None,
+                };
+                if sample != Some(expected.as_str()) {
+                    let rg = match record.aux(b"RG") {
+                        Ok(Aux::String(rg)) => rg.to_string(),
+                        _ => String::from("none"),
+                    };
+                    panic!("Record {} from {} has read group {} with sample {}, expected {}", String::from_utf8_lossy(record.qname()), input.display(), rg, sample.unwrap_or("none"), expected);
+                }
+            }
+            if args.skip_duplicate_records && duplicate_filter.is_duplicate(&record) {
+                continue;
+            }
+            if let Some(remapper) = tid_remapper.as_mut() {
+                if !remapper.remap(&mut record) {
+                    continue;
+                }
+            }
+            let parent_rg = match record.aux(b"RG") {
+                Ok(Aux::String(rg)) => Some(rg),
+                _ => None,
+            };
+            let chunks = match pair_synthesizer.as_mut() {
+                Some(synthesizer) if args.pair_chunks => synthesizer.synthesize(&record),
+                Some(synthesizer) => {
+                    alignment_chopper.chop_read(&record);
+                    synthesizer.synthesize_chunks(&record, alignment_chopper.chunks(), &alignment_chopper.query_ranges())
+                }
+                None => {
+                    alignment_chopper.chop_read(&record);
+                    alignment_chopper.chunks()
+                }
+            };
+            // Shards take whole reads in turn, so all chunks of a read end up together
+            let split_name = match args.shards {
+                _ if args.split_by_rg => Some(parent_rg.filter(|rg| known_read_groups.contains(*rg)).unwrap_or(UNASSIGNED_READ_GROUP).to_string()),
+                Some(shards) if !chunks.is_empty() || args.keep_parent || alignment_chopper.passed_through() => {
+                    next_shard = (next_shard + 1) % shards;
+                    Some(shard_name((next_shard + shards - 1) % shards, shards))
+                }
+                _ => None,
+            };
+            if args.keep_parent {
+                let mut parent = record.clone();
+                args.parent_mark.apply(&mut parent);
+                write_chunk(&parent, parent_rg, split_name.as_deref(), (false, false));
+            } else if alignment_chopper.passed_through() {
+                write_chunk(&record, parent_rg, split_name.as_deref(), (false, false));
+            }
+            let query_ranges = if args.origin_comment { alignment_chopper.query_ranges() } else { Vec::new() };
+            let cut_ends = if args.mask_boundary.is_some() { alignment_chopper.cut_ends() } else { Vec::new() };
+            for (i, cr) in chunks.iter().enumerate() {
+                let cut_ends = cut_ends.get(i).copied().unwrap_or_default();
+                if args.strict {
+                    if let Err(e) = check_record(cr, &header_view) {
+                        let contig = if cr.tid() >= 0 && (cr.tid() as u32) < header_view.target_count() { String::from_utf8_lossy(header_view.tid2name(cr.tid() as u32)) } else { "*".into() };
+                        panic!("Chunk {} of {} (flag {}, {}:{}, CIGAR {}) breaks the SAM spec: {}", String::from_utf8_lossy(cr.qname()), String::from_utf8_lossy(record.qname()), cr.flags(), contig, cr.pos() + 1, cr.cigar(), e);
+                    }
+                }
+                if reference.is_none() && !args.origin_comment {
+                    write_chunk(cr, parent_rg, split_name.as_deref(), cut_ends);
+                    continue;
+                }
+                let mut cr = cr.clone();
+                let contig = (record.tid() >= 0).then(|| header_view.tid2name(record.tid() as u32));
+                if let (Some(reference), Some(contig)) = (&reference, contig) {
+                    let contig = String::from_utf8_lossy(contig);
+                    if let Some(flank) = args.ref_context {
+                        reference.add_ref_context(&mut cr, &contig, flank);
+                    }
+                    if let Some(rate) = reference.mismatch_rate(&cr, &contig).filter(|rate| args.check_alignment && *rate > args.max_mismatch_rate) {
+                        misaligned_chunks += 1;
+                        cr.push_aux(b"mr", Aux::Float(rate as f32)).expect("Unable to add mr tag");
+                    }
+                }
+                if let Some(query_range) = query_ranges.get(i) {
+                    add_origin_tags(&mut cr, contig, query_range).expect("Unable to add origin tags");
+                }
+                write_chunk(&cr, parent_rg, split_name.as_deref(), cut_ends);
+            }
+            progress.chunks_written.fetch_add(chunks.len() as u64, Ordering::Relaxed);
+            if let Some(report) = report.as_mut() {
+                let contig = if record.tid() >= 0 { String::from_utf8_lossy(header_view.tid2name(record.tid() as u32)) } else { "*".into() };
+                report.add_record(&record, &contig, chunks);
+            }
+            progress.records_skipped.store(alignment_chopper.skipped_records() + alignment_chopper.malformed_records() + alignment_chopper.empty_records(), Ordering::Relaxed);
+
+            if let (Some(rw), Some(reason)) = (rejects_writer.as_mut(), alignment_chopper.last_rejection()) {
+                let mut rejected = record.clone();
+                rejected.push_aux(b"rj", Aux::String(reason.as_str())).expect("Unable to add rj tag");
+                rw.write(&rejected).expect("Cannot write rejected record.");
+            }
+
+            if let Some(jw) = junction_writer.as_mut() {
+                if record.tid() >= 0 {
+                    jw.write_junctions(header_view.tid2name(record.tid() as u32), alignment_chopper.chunks(), &alignment_chopper.chunk_junctions()).expect("Cannot write junctions.");
+                }
+            }
+
+            if let Some(lw) = liftover_writer.as_mut() {
+                let contig = (record.tid() >= 0).then(|| header_view.tid2name(record.tid() as u32));
+                for (cr, query_range) in alignment_chopper.chunks().iter().zip(alignment_chopper.query_ranges()) {
+                    lw.write_chunk(cr, record.qname(), contig, &query_range).expect("Cannot write liftover table.");
+                }
+            }
+        }
+
+    }
+
+    if let (Some(sorter), Some(writer)) = (sorter, hts_writer.as_mut()) {
+        sorter.finish(writer).expect("Unable to merge sorted records");
+    }
+    let written = if let Some(outputs) = split_outputs {
+        let finished = outputs.finish().expect("Unable to move outputs into place");
+        let column = if args.split_by_rg { "read_group" } else { "shard" };
+        write_manifest(&manifest_path(&args.output), column, &finished).expect("Unable to write manifest");
+        finished.into_iter().map(|(_, path)| path).collect::<Vec<_>>()
+    } else {
+        if let Some(writer) = hts_writer {
+            writer.finish().expect("Cannot write output.");
+        }
+        if write_path != args.output {
+            std::fs::rename(&write_path, &args.output).expect("Unable to move output into place");
+        }
+        if let (Some(writer), Some(r2_write_path), Some(r2_path)) = (r2_writer, &r2_write_path, &args.r2_output) {
+            writer.finish().expect("Cannot write R2 output.");
+            if r2_write_path != r2_path {
+                std::fs::rename(r2_write_path, r2_path).expect("Unable to move R2 output into place");
+            }
+        }
+        vec![args.output.clone()]
+    };
+    for (path, write_path, mut writer) in extra_writers {
+        writer.flush().unwrap_or_else(|e| panic!("Unable to write {}: {}", path.display(), e));
+        if write_path != *path {
+            std::fs::rename(&write_path, path).expect("Unable to move output into place");
+        }
+    }
+    drop(rejects_writer);
+    if let Some(mut jw) = junction_writer {
+        jw.flush().expect("Cannot write junctions.");
+    }
+    if let Some(mut lw) = liftover_writer {
+        lw.flush().expect("Cannot write liftover table.");
+    }
+    for path in &side_outputs {
+        let write_path = atomic_path(path);
+        if write_path != **path {
+            std::fs::rename(&write_path, path).expect("Unable to move output into place");
+        }
+    }
+    // Renames only last once the directories holding them are synced too
+    if args.fsync != FsyncPolicy::Never {
+        let outputs = written.iter().filter(|_| !to_stdout && !to_url)
+            .chain(splitting.then(|| manifest_path(&args.output)).as_ref())
+            .chain(extra_outputs.iter().map(|(path, _)| path))
+            .chain(args.r2_output.as_ref())
+            .chain(side_outputs.iter().copied())
+            .filter(|path| std::fs::metadata(path).is_ok_and(|metadata| metadata.is_file()))
+            .cloned().collect::<Vec<_>>();
+        let dirs = outputs.iter().map(|path| match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+            _ => PathBuf::from("."),
+        }).collect::<BTreeSet<_>>();
+        for path in outputs.iter().chain(&dirs) {
+            sync_path(path).unwrap_or_else(|e| panic!("Unable to sync {}: {}", path.display(), e));
+        }
+    }
+    progress.done.store(true, Ordering::Relaxed);
+    if args.write_index {
+        let kind = IndexKind::for_output(output_format, &template).unwrap();
+        for path in &written {
+            write_index(path, kind, write_threads).unwrap_or_else(|e| panic!("{}", e));
+            eprintln!("Wrote {} index for {}", kind.extension(), path.display());
         }
     }
 
-    println!("Runtime: {}s", now.elapsed().as_secs());
+    if let Some(session_path) = &args.igv_session {
+        let mut session = IgvSession::new(args.reference.clone());
+        session.add_resource(&args.output);
+        if let Some(junctions_path) = &args.emit_junctions {
+            session.add_resource(junctions_path);
+        }
+        session.write(session_path).expect("Unable to write IGV session");
+    }
+
+    let mut warnings = Vec::new();
+    if let Some(remapper) = &tid_remapper {
+        if remapper.absent() > 0 {
+            warnings.push(format!("Records on contigs absent from target dictionary: {}", remapper.absent()));
+        }
+    }
+    if stripped_read_groups > 0 {
+        warnings.push(format!("Stripped RG tags naming read groups missing from the header: {}", stripped_read_groups));
+    }
+    if duplicate_filter.duplicates() > 0 {
+        warnings.push(format!("Skipped duplicate records: {}", duplicate_filter.duplicates()));
+    }
+    if alignment_chopper.skipped_records() > 0 {
+        warnings.push(format!("Skipped records with mismatched CIGAR and SEQ: {}", alignment_chopper.skipped_records()));
+    }
+    if alignment_chopper.malformed_records() > 0 {
+        warnings.push(format!("Skipped records with fewer base qualities than bases: {}", alignment_chopper.malformed_records()));
+    }
+    if alignment_chopper.empty_records() > 0 {
+        warnings.push(format!("Skipped unaligned records without bases: {}", alignment_chopper.empty_records()));
+    }
+    if alignment_chopper.stripped_tag_chunks() > 0 {
+        warnings.push(format!("Stripped per-base tags that can't be sliced from {} chunks", alignment_chopper.stripped_tag_chunks()));
+    }
+    if misaligned_chunks > 0 {
+        warnings.push(format!("Chunks whose CIGAR disagrees with the reference: {}", misaligned_chunks));
+    }
+    if alignment_chopper.low_quality_chunks() > 0 {
+        warnings.push(format!("Dropped low quality chunks: {}", alignment_chopper.low_quality_chunks()));
+    }
+    if alignment_chopper.dropped_chunks() > 0 {
+        warnings.push(format!("Dropped chunks at random: {}", alignment_chopper.dropped_chunks()));
+    }
+
+    // Report on stderr so output written to stdout stays clean
+    for warning in &warnings {
+        eprintln!("{}", warning);
+    }
+    if let Some(report) = report.as_mut() {
+        for warning in &warnings {
+            report.add_warning(warning);
+        }
+        if let Some(report_path) = &args.report {
+            report.write(report_path).expect("Unable to write report");
+        }
+        if let Some(stats_path) = &args.stats_json {
+            report.write_json(stats_path).expect("Unable to write stats");
+        }
+    }
+    eprintln!("Runtime: {}s", now.elapsed().as_secs());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn preset_args_test() {
+        let parse = |args: &str| parse_args(std::iter::once("chop-reads").chain(args.split(' ')).map(OsString::from).collect());
+        let args = parse("--preset ont-ultralong -i in.bam -o out.bam");
+        assert_eq!((args.chunk_size, args.clip_policy), (Some(10000), ClipPolicy::Drop));
+
+        // Explicit flags win over the preset's
+        let args = parse("--preset ont-ultralong --clip-policy attach -i in.bam -o out.bam");
+        assert_eq!(args.clip_policy, ClipPolicy::Attach);
+        let args = parse("--preset illumina --clip-policy separate -i in.bam -o out.fq");
+        assert_eq!((args.pair_chunks, args.clip_policy), (true, ClipPolicy::Separate));
+
+        // as do flags that can't be given with the preset's, taking the flags that only go with those along
+        let args = parse("--preset ont-ultralong --skip-clipped-bases -i in.bam -o out.bam");
+        assert_eq!((args.skip_clipped_bases, args.clip_policy, args.keep_tags), (true, ClipPolicy::Attach, true));
+        let args = parse("--preset illumina --synthetic-pairs gap=4,len=3 -i in.bam -o out.fq");
+        assert_eq!((args.pair_chunks, args.inner_distance, args.chunk_size), (false, 0, Some(150)));
+        let args = parse("--preset pacbio-hifi --num-chunks 4 -i in.bam -o out.bam");
+        assert_eq!((args.chunk_size, args.num_chunks, args.min_length), (None, Some(4), 500));
+        let args = parse("--preset hi-c --inner-distance 50 -i in.bam -o out.fq");
+        assert_eq!((args.pair_chunks, args.inner_distance), (true, 50));
+    }
 }
@@ -0,0 +1,409 @@
+use std::ffi::{CString, OsString};
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use clap::ValueEnum;
+use rust_htslib::bam::{index, CompressionLevel, Format, Header, HeaderView, Record, Writer};
+use rust_htslib::htslib;
+use crate::fastx::FastxWriter;
+
+// BAI bins can't address positions past 2^29, so longer contigs need a CSI index
+const BAI_MAX_CONTIG_LEN: u64 = 1 << 29;
+const CSI_MIN_SHIFT: u32 = 14;
+
+/// Format to write chopped records in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    Bam,
+    /// Plain text SAM
+    Sam,
+    /// Requires --reference
+    Cram,
+    /// Sequences only, in their original orientation (bgzipped if the output ends in .gz)
+    Fasta,
+    /// Sequences and qualities, in their original orientation (bgzipped if the output ends in .gz)
+    Fastq,
+}
+
+impl OutputFormat {
+    /// Guess the format from the path's extension
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "bam" => Some(OutputFormat::Bam),
+            "sam" => Some(OutputFormat::Sam),
+            "cram" => Some(OutputFormat::Cram),
+            "fa" | "fasta" | "fna" => Some(OutputFormat::Fasta),
+            "fq" | "fastq" => Some(OutputFormat::Fastq),
+            // Only sequence formats are recognised through .gz, e.g. chunks.fa.gz
+            "gz" => Self::from_path(Path::new(path.file_stem()?)).filter(|format| format.is_fastx()),
+            _ => None,
+        }
+    }
+
+    /// The htslib format to write with, or None for formats written without htslib
+    pub fn hts_format(&self) -> Option<Format> {
+        match self {
+            OutputFormat::Bam => Some(Format::Bam),
+            OutputFormat::Sam => Some(Format::Sam),
+            OutputFormat::Cram => Some(Format::Cram),
+            OutputFormat::Fasta | OutputFormat::Fastq => None,
+        }
+    }
+
+    /// Whether this is a sequence-only format (FASTA or FASTQ)
+    pub fn is_fastx(&self) -> bool {
+        matches!(self, OutputFormat::Fasta | OutputFormat::Fastq)
+    }
+}
+
+/// Tiles of the reference, `width` bases wide and counted from `anchor`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TileBins {
+    pub anchor: i64,
+    pub width: i64,
+}
+
+impl TileBins {
+    /// Index of the tile holding 0-based position `pos`
+    pub fn bin(&self, pos: i64) -> i64 {
+        (pos - self.anchor).div_euclid(self.width)
+    }
+}
+
+/// How CRAM output lays out its containers and slices, leaving htslib's defaults where unset
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CramLayout {
+    pub seqs_per_slice: Option<u32>,
+    pub slices_per_container: Option<u32>,
+    /// Start a new container whenever a record starts in a different tile than the last, so no container spans two
+    pub tile_bins: Option<TileBins>,
+}
+
+/// CRAM writer talking to htslib directly.
+///
+/// `bam::Writer` keeps its htsFile private, so it can't set the container options of CramLayout or
+/// end a container early.
+pub struct CramWriter {
+    fp: *mut htslib::htsFile,
+    header: HeaderView,
+    tile_bins: Option<TileBins>,
+    // Contig and tile of the last mapped record written
+    last_bin: Option<(i32, i64)>,
+}
+
+impl CramWriter {
+    /// Create a CRAM at `path` (- for stdout) compressed against `reference`
+    pub fn from_path(path: &Path, header: &Header, reference: &Path, layout: CramLayout) -> io::Result<Self> {
+        let c_string = |s: &str| CString::new(s).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e));
+        let c_path = c_string(&path.to_string_lossy())?;
+        let fp = unsafe { htslib::hts_open(c_path.as_ptr(), c"wc".as_ptr()) };
+        if fp.is_null() {
+            return Err(io::Error::other(format!("Unable to create {}", path.display())));
+        }
+        // Closes fp if setting it up fails
+        let writer = Self { fp, header: HeaderView::from_header(header), tile_bins: layout.tile_bins, last_bin: None };
+
+        // Everything shaping the CRAM has to be set before the header is written
+        let c_reference = c_string(&reference.to_string_lossy())?;
+        if unsafe { htslib::hts_set_fai_filename(fp, c_reference.as_ptr()) } != 0 {
+            return Err(io::Error::other(format!("Unable to set reference {} for {}", reference.display(), path.display())));
+        }
+        let options = [(htslib::hts_fmt_option_CRAM_OPT_SEQS_PER_SLICE, layout.seqs_per_slice), (htslib::hts_fmt_option_CRAM_OPT_SLICES_PER_CONTAINER, layout.slices_per_container)];
+        for (option, value) in options.into_iter().filter_map(|(option, value)| value.map(|value| (option, value))) {
+            if unsafe { htslib::hts_set_opt(fp, option, value as std::os::raw::c_int) } != 0 {
+                return Err(io::Error::other(format!("Unable to set CRAM option {} for {}", option, path.display())));
+            }
+        }
+        if unsafe { htslib::sam_hdr_write(fp, writer.header.inner_ptr()) } != 0 {
+            return Err(io::Error::other(format!("Unable to write header to {}", path.display())));
+        }
+        Ok(writer)
+    }
+
+    pub fn set_compression_level(&mut self, level: CompressionLevel) -> io::Result<()> {
+        let level = match level {
+            CompressionLevel::Uncompressed => 0,
+            CompressionLevel::Fastest => 1,
+            CompressionLevel::Maximum => 9,
+            CompressionLevel::Level(level) => level,
+        };
+        match unsafe { htslib::hts_set_opt(self.fp, htslib::hts_fmt_option_HTS_OPT_COMPRESSION_LEVEL, level as std::os::raw::c_int) } {
+            0 => Ok(()),
+            _ => Err(io::Error::other(format!("Invalid compression level {}", level))),
+        }
+    }
+
+    pub fn set_threads(&mut self, threads: usize) -> io::Result<()> {
+        match unsafe { htslib::hts_set_threads(self.fp, threads as std::os::raw::c_int) } {
+            0 => Ok(()),
+            _ => Err(io::Error::other("Unable to set writer threads")),
+        }
+    }
+
+    pub fn write(&mut self, rec: &Record) -> io::Result<()> {
+        if let Some(tile_bins) = self.tile_bins.filter(|_| !rec.is_unmapped()) {
+            let bin = (rec.tid(), tile_bins.bin(rec.pos()));
+            // Flushing ends the current container, and the next record starts another
+            if self.last_bin.is_some_and(|last_bin| last_bin != bin) && unsafe { htslib::hts_flush(self.fp) } != 0 {
+                return Err(io::Error::other("Unable to end CRAM container"));
+            }
+            self.last_bin = Some(bin);
+        }
+        match unsafe { htslib::sam_write1(self.fp, self.header.inner_ptr(), rec.inner()) } {
+            -1 => Err(io::Error::other("Unable to write record")),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Drop for CramWriter {
+    fn drop(&mut self) {
+        unsafe { htslib::hts_close(self.fp) };
+    }
+}
+
+/// Destination for chopped records, in whichever format the output was asked for
+pub enum RecordWriter {
+    Hts(Writer),
+    Cram(CramWriter),
+    Fastx(FastxWriter),
+}
+
+impl RecordWriter {
+    pub fn write(&mut self, rec: &Record) -> io::Result<()> {
+        match self {
+            RecordWriter::Hts(writer) => writer.write(rec).map_err(io::Error::other),
+            RecordWriter::Cram(writer) => writer.write(rec),
+            RecordWriter::Fastx(writer) => writer.write(rec),
+        }
+    }
+
+    /// Write a chunk, passing on to FASTA/FASTQ outputs whether its ends were cut from neighbouring chunks
+    pub fn write_chunk(&mut self, rec: &Record, cut_ends: (bool, bool)) -> io::Result<()> {
+        match self {
+            RecordWriter::Fastx(writer) => writer.write_chunk(rec, cut_ends),
+            _ => self.write(rec),
+        }
+    }
+
+    /// Flush and close the output
+    pub fn finish(self) -> io::Result<()> {
+        match self {
+            RecordWriter::Hts(_) | RecordWriter::Cram(_) => Ok(()),
+            RecordWriter::Fastx(mut writer) => writer.flush(),
+        }
+    }
+}
+
+/// When to fsync outputs to make sure they've reached storage
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsyncPolicy {
+    /// Leave it to the OS
+    Never,
+    /// Once every output is finished and in place
+    End,
+    /// Every this many chunks written, and at the end
+    Periodic(u64),
+}
+
+impl FromStr for FsyncPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "never" => Ok(FsyncPolicy::Never),
+            "end" => Ok(FsyncPolicy::End),
+            _ => match s.strip_prefix("periodic:") {
+                Some(n) => n.parse().ok().filter(|n| *n > 0).map(FsyncPolicy::Periodic).ok_or_else(|| format!("Invalid fsync period: {}", n)),
+                None => Err(format!("Unknown fsync policy: {} (expected never, end or periodic:N)", s)),
+            }
+        }
+    }
+}
+
+/// Flush what has been written to `path` (a file or directory) through to storage
+pub fn sync_path(path: &Path) -> io::Result<()> {
+    File::open(path)?.sync_all()
+}
+
+/// Path the output is written to before being renamed into place on success
+pub fn temp_output_path(path: &Path) -> PathBuf {
+    let mut temp_path = OsString::from(path.as_os_str());
+    temp_path.push(".tmp");
+    PathBuf::from(temp_path)
+}
+
+// Whether two paths name the same file, including through hardlinks or symlinks
+fn same_file(a: &Path, b: &Path) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        match (std::fs::metadata(a), std::fs::metadata(b)) {
+            (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+            _ => false,
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        matches!((a.canonicalize(), b.canonicalize()), (Ok(a), Ok(b)) if a == b)
+    }
+}
+
+/// Check that writing `output` won't destroy data, returning why not if it would.
+///
+/// An output that is one of `inputs` is always refused, while an existing regular file is only
+/// replaced with `force`. Other existing files, like FIFOs and /dev/null, are written to as usual.
+pub fn check_output_path(output: &Path, inputs: &[PathBuf], force: bool) -> Result<(), String> {
+    if let Some(input) = inputs.iter().find(|input| same_file(input, output)) {
+        return Err(format!("Output {} is the same file as input {}", output.display(), input.display()));
+    }
+    match std::fs::metadata(output) {
+        Ok(metadata) if metadata.is_file() && !force => Err(format!("Output {} already exists, use --force to overwrite it", output.display())),
+        _ => Ok(()),
+    }
+}
+
+/// Index written alongside an output
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexKind {
+    Bai,
+    /// Needed when a contig is too long for BAI
+    Csi,
+    /// Always used for CRAM
+    Crai,
+}
+
+impl IndexKind {
+    /// Kind of index to build for an output with `header`, or None if the format can't be indexed
+    pub fn for_output(format: OutputFormat, header: &HeaderView) -> Option<Self> {
+        match format {
+            OutputFormat::Sam | OutputFormat::Fasta | OutputFormat::Fastq => None,
+            OutputFormat::Cram => Some(IndexKind::Crai),
+            OutputFormat::Bam if (0..header.target_count()).any(|tid| header.target_len(tid).unwrap_or(0) > BAI_MAX_CONTIG_LEN) => Some(IndexKind::Csi),
+            OutputFormat::Bam => Some(IndexKind::Bai),
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            IndexKind::Bai => "bai",
+            IndexKind::Csi => "csi",
+            IndexKind::Crai => "crai",
+        }
+    }
+}
+
+/// Index a finished, coordinate-sorted output in place
+pub fn write_index(path: &Path, kind: IndexKind, threads: usize) -> io::Result<()> {
+    let idx_type = match kind {
+        IndexKind::Csi => index::Type::Csi(CSI_MIN_SHIFT),
+        // htslib picks .crai from the file format for CRAM
+        IndexKind::Bai | IndexKind::Crai => index::Type::Bai,
+    };
+    index::build(path, None, idx_type, threads as u32)
+        .map_err(|e| io::Error::other(format!("Unable to index {} (is it coordinate-sorted?): {}", path.display(), e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_path_test() {
+        assert_eq!(OutputFormat::from_path(Path::new("out/chunks.cram")), Some(OutputFormat::Cram));
+        assert_eq!(OutputFormat::from_path(Path::new("chunks.sam")), Some(OutputFormat::Sam));
+        assert_eq!(OutputFormat::from_path(Path::new("chunks.bam")), Some(OutputFormat::Bam));
+        assert_eq!(OutputFormat::from_path(Path::new("chunks")), None);
+        assert_eq!(OutputFormat::from_path(Path::new("chunks.txt")), None);
+        assert_eq!(OutputFormat::from_path(Path::new("chunks.fasta")), Some(OutputFormat::Fasta));
+        assert_eq!(OutputFormat::from_path(Path::new("chunks.fa.gz")), Some(OutputFormat::Fasta));
+        assert_eq!(OutputFormat::from_path(Path::new("chunks.fq.gz")), Some(OutputFormat::Fastq));
+        assert_eq!(OutputFormat::from_path(Path::new("chunks.bam.gz")), None);
+    }
+
+    #[test]
+    fn fsync_policy_test() {
+        assert_eq!("never".parse(), Ok(FsyncPolicy::Never));
+        assert_eq!("end".parse(), Ok(FsyncPolicy::End));
+        assert_eq!("periodic:1000".parse(), Ok(FsyncPolicy::Periodic(1000)));
+        assert!("periodic:0".parse::<FsyncPolicy>().is_err());
+        assert!("always".parse::<FsyncPolicy>().is_err());
+    }
+
+    #[test]
+    fn check_output_path_test() {
+        let dir = std::env::temp_dir().join(format!("chop_reads_check_output_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("in.bam");
+        std::fs::write(&input, b"").unwrap();
+        let link = dir.join("link.bam");
+        std::fs::hard_link(&input, &link).unwrap();
+        let existing = dir.join("old.bam");
+        std::fs::write(&existing, b"").unwrap();
+
+        let inputs = vec![input.clone()];
+        assert!(check_output_path(&dir.join("new.bam"), &inputs, false).is_ok());
+        assert!(check_output_path(&existing, &inputs, false).unwrap_err().contains("already exists"));
+        assert!(check_output_path(&existing, &inputs, true).is_ok());
+        assert!(check_output_path(&input, &inputs, true).unwrap_err().contains("same file as input"));
+        assert!(check_output_path(&link, &inputs, true).unwrap_err().contains("same file as input"));
+        assert!(check_output_path(Path::new("/dev/null"), &inputs, false).is_ok());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn cram_tile_containers_test() {
+        use std::io::Read;
+        use rust_htslib::bam::record::{Cigar, CigarString};
+
+        let dir = std::env::temp_dir().join(format!("chop_reads_cram_layout_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let reference = dir.join("ref.fa");
+        std::fs::write(&reference, format!(">chr1\n{}\n", "ACGT".repeat(100))).unwrap();
+        let header = Header::from_template(&HeaderView::from_bytes(b"@HD\tVN:1.6\tSO:coordinate\n@SQ\tSN:chr1\tLN:400\n"));
+        let records = [0, 10, 100, 150, 199, 300].map(|pos| {
+            let mut rec = Record::new();
+            rec.set(b"read", Some(&CigarString(vec![Cigar::Match(4)])), b"ACGT", &[30; 4]);
+            rec.set_tid(0);
+            rec.set_pos(pos);
+            rec
+        });
+
+        // Each line of the .crai is a slice, and each container here holds one
+        let slices = |layout: CramLayout| {
+            let path = dir.join("chunks.cram");
+            let mut writer = CramWriter::from_path(&path, &header, &reference, layout).unwrap();
+            for rec in &records {
+                writer.write(rec).unwrap();
+            }
+            drop(writer);
+            write_index(&path, IndexKind::Crai, 1).unwrap();
+            let mut crai = String::new();
+            rust_htslib::bgzf::Reader::from_path(dir.join("chunks.cram.crai")).unwrap().read_to_string(&mut crai).unwrap();
+            crai.lines().map(|line| line.split('\t').take(3).collect::<Vec<_>>().join(" ")).collect::<Vec<_>>()
+        };
+        assert_eq!(slices(CramLayout::default()), vec!["0 1 304"]);
+        let tile_bins = Some(TileBins { anchor: 0, width: 100 });
+        assert_eq!(slices(CramLayout { tile_bins, ..Default::default() }), vec!["0 1 14", "0 101 103", "0 301 4"]);
+        // Slices still hold at most --cram-seqs-per-slice records within a tile
+        assert_eq!(slices(CramLayout { seqs_per_slice: Some(2), tile_bins, ..Default::default() }), vec!["0 1 14", "0 101 54", "0 200 4", "0 301 4"]);
+        let tile_bins = Some(TileBins { anchor: 50, width: 100 });
+        assert_eq!(slices(CramLayout { tile_bins, ..Default::default() }), vec!["0 1 14", "0 101 4", "0 151 53", "0 301 4"]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn index_kind_test() {
+        let header = HeaderView::from_bytes(b"@SQ\tSN:chr1\tLN:1000\n");
+        assert_eq!(IndexKind::for_output(OutputFormat::Bam, &header), Some(IndexKind::Bai));
+        assert_eq!(IndexKind::for_output(OutputFormat::Cram, &header), Some(IndexKind::Crai));
+        assert_eq!(IndexKind::for_output(OutputFormat::Sam, &header), None);
+        assert_eq!(IndexKind::for_output(OutputFormat::Fasta, &header), None);
+
+        let long_header = HeaderView::from_bytes(b"@SQ\tSN:chr1\tLN:1000\n@SQ\tSN:chr1A\tLN:594102056\n");
+        assert_eq!(IndexKind::for_output(OutputFormat::Bam, &long_header), Some(IndexKind::Csi));
+    }
+}
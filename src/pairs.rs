@@ -0,0 +1,179 @@
+use std::ops::Range;
+use std::str::FromStr;
+use rust_htslib::bam::Record;
+use crate::cigar_utils::reg2bin;
+use crate::fastx::{original_bases, reverse_complement};
+
+// Flags of the synthetic mates: paired, unmapped, mate unmapped, and first or second in template
+const R1_FLAGS: u16 = 0x1 | 0x4 | 0x8 | 0x40;
+const R2_FLAGS: u16 = 0x1 | 0x4 | 0x8 | 0x80;
+
+/// Mate pairs cut from the chunks of each read, as `gap=G,len=L`: R1 is the first L bases of a
+/// chunk and R2 the reverse complement of the L bases from G bases after the chunk starts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SyntheticPairs {
+    pub gap: usize,
+    pub len: usize,
+}
+
+impl FromStr for SyntheticPairs {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (mut gap, mut len) = (None, None);
+        for param in s.split(',') {
+            match param.split_once('=') {
+                Some(("gap", value)) => gap = Some(value.parse().map_err(|_| format!("Invalid synthetic pair gap: {}", value))?),
+                Some(("len", value)) => len = value.parse().ok().filter(|len| *len > 0).map(Some).ok_or_else(|| format!("Invalid synthetic pair length: {}", value))?,
+                _ => return Err(format!("Unknown synthetic pair parameter: {} (expected gap=G,len=L)", param)),
+            }
+        }
+        match (gap, len) {
+            (Some(gap), Some(len)) => Ok(SyntheticPairs { gap, len }),
+            _ => Err(format!("Synthetic pairs need both a gap and a length: {} (expected gap=G,len=L)", s)),
+        }
+    }
+}
+
+/// Simulates a paired short-read library from long reads.
+///
+/// Each read is taken in its original orientation and cut into fragments of two mates of
+/// `read_len` bases with `inner_distance` bases between them. R1 is the start of the fragment and
+/// R2 the reverse complement of its end, as a sequencer would read them. Both mates of fragment k of
+/// read q are named q-k, and fragments that would run off the end of the read are dropped.
+///
+/// Made with `at_chunks`, fragments instead start at the 5' end of each chunk the read was cut into
+/// and are named after their chunk, for mate-pair-like libraries spanning chunk boundaries.
+#[derive(Debug, Clone)]
+pub struct PairSynthesizer {
+    read_len: usize,
+    // Bases from the start of R1 to the start of R2
+    r2_offset: usize,
+    pairs: Vec<Record>,
+}
+
+impl PairSynthesizer {
+    pub fn new(read_len: usize, inner_distance: usize) -> Self {
+        Self { read_len, r2_offset: read_len + inner_distance, pairs: Vec::new() }
+    }
+
+    /// Synthesize pairs from the chunks of each read, as --synthetic-pairs does
+    pub fn at_chunks(pairs: SyntheticPairs) -> Self {
+        Self { read_len: pairs.len, r2_offset: pairs.gap, pairs: Vec::new() }
+    }
+
+    /// Mates synthesized from `rec`, R1 then R2 for each fragment in turn
+    pub fn synthesize(&mut self, rec: &Record) -> &Vec<Record> {
+        let fragment_len = self.r2_offset + self.read_len;
+        let starts = (0..).step_by(fragment_len.max(1)).take_while(|start| start + fragment_len <= rec.seq_len());
+        let fragments = (0..).zip(starts).map(|(k, start)| ([rec.qname(), b"-", k.to_string().as_bytes()].concat(), start)).collect::<Vec<_>>();
+        self.synthesize_fragments(rec, fragments)
+    }
+
+    /// Mates synthesized from the `chunks` of `rec`, one pair per chunk starting at its 5' end, given
+    /// the query ranges the chunks cover in the parent's SEQ
+    pub fn synthesize_chunks(&mut self, rec: &Record, chunks: &[Record], query_ranges: &[Range<usize>]) -> &Vec<Record> {
+        let fragments = chunks.iter().zip(query_ranges).map(|(chunk, range)| {
+            (chunk.qname().to_vec(), if rec.is_reverse() { rec.seq_len() - range.end } else { range.start })
+        }).collect::<Vec<_>>();
+        self.synthesize_fragments(rec, fragments)
+    }
+
+    // Mates of the fragments named and starting as given in the original orientation of `rec`, dropping those running off its end
+    fn synthesize_fragments(&mut self, rec: &Record, fragments: Vec<(Vec<u8>, usize)>) -> &Vec<Record> {
+        self.pairs.clear();
+        let bases = original_bases(rec);
+        let mut quals = rec.qual().to_vec();
+        if rec.is_reverse() {
+            quals.reverse();
+        }
+
+        if self.read_len == 0 || quals.len() != bases.len() {
+            return &self.pairs;
+        }
+        for (qname, start) in fragments.into_iter().filter(|(_, start)| start + self.r2_offset + self.read_len <= bases.len()) {
+            let r2_start = start + self.r2_offset;
+            let r2_quals = quals[r2_start..r2_start + self.read_len].iter().rev().copied().collect::<Vec<_>>();
+            self.pairs.push(Self::mate(&qname, &bases[start..start + self.read_len], &quals[start..start + self.read_len], R1_FLAGS));
+            self.pairs.push(Self::mate(&qname, &reverse_complement(&bases[r2_start..r2_start + self.read_len]), &r2_quals, R2_FLAGS));
+        }
+        &self.pairs
+    }
+
+    fn mate(qname: &[u8], seq: &[u8], qual: &[u8], flags: u16) -> Record {
+        let mut mate = Record::new();
+        mate.set(qname, None, seq, qual);
+        mate.set_flags(flags);
+        mate.set_tid(-1);
+        mate.set_pos(-1);
+        mate.set_mtid(-1);
+        mate.set_mpos(-1);
+        mate.set_bin(reg2bin(-1, 0));
+        mate
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_htslib::bam::record::{Cigar, CigarString};
+
+    #[test]
+    fn synthesize_test() {
+        let cigar = CigarString(vec![Cigar::Match(12)]);
+        let mut rec = Record::new();
+        rec.set(b"read", Some(&cigar), b"AACCGGTTACGT", &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11]);
+
+        // Fragments of 2 + 1 + 2 bases fit twice into 12
+        let mut synthesizer = PairSynthesizer::new(2, 1);
+        let mates = synthesizer.synthesize(&rec).iter().map(|mate| {
+            (String::from_utf8(mate.qname().to_vec()).unwrap(), mate.is_first_in_template(), mate.seq().as_bytes(), mate.qual().to_vec())
+        }).collect::<Vec<_>>();
+        assert_eq!(mates, vec![
+            (String::from("read-0"), true, b"AA".to_vec(), vec![0, 1]),
+            (String::from("read-0"), false, b"CG".to_vec(), vec![4, 3]),
+            (String::from("read-1"), true, b"GT".to_vec(), vec![5, 6]),
+            (String::from("read-1"), false, b"GT".to_vec(), vec![9, 8]),
+        ]);
+
+        // Reverse strand reads are cut in the orientation they were sequenced in
+        rec.set_reverse();
+        let mates = PairSynthesizer::new(3, 0).synthesize(&rec).iter().map(|mate| mate.seq().as_bytes()).collect::<Vec<_>>();
+        assert_eq!(mates, vec![b"ACG".to_vec(), b"TTA".to_vec(), b"CCG".to_vec(), b"AAC".to_vec()]);
+    }
+
+    #[test]
+    fn synthesize_chunks_test() {
+        assert_eq!("gap=5,len=2".parse::<SyntheticPairs>(), Ok(SyntheticPairs { gap: 5, len: 2 }));
+        assert_eq!("len=2,gap=0".parse::<SyntheticPairs>(), Ok(SyntheticPairs { gap: 0, len: 2 }));
+        assert!("gap=5".parse::<SyntheticPairs>().is_err());
+        assert!("gap=5,len=0".parse::<SyntheticPairs>().is_err());
+        assert!("gap=5,size=2".parse::<SyntheticPairs>().is_err());
+
+        let cigar = CigarString(vec![Cigar::Match(12)]);
+        let mut rec = Record::new();
+        rec.set(b"read", Some(&cigar), b"AACCGGTTACGT", &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11]);
+        let mut chunks = [Record::new(), Record::new(), Record::new()];
+        for (k, chunk) in chunks.iter_mut().enumerate() {
+            chunk.set(format!("read-{}", k).as_bytes(), None, b"", &[]);
+        }
+        let query_ranges = [0..4, 4..8, 8..12];
+
+        // R2 of each chunk's pair may come from the next chunk, while pairs running off the end of the read are dropped
+        let mut synthesizer = PairSynthesizer::at_chunks(SyntheticPairs { gap: 5, len: 2 });
+        let mates = synthesizer.synthesize_chunks(&rec, &chunks, &query_ranges).iter().map(|mate| {
+            (String::from_utf8(mate.qname().to_vec()).unwrap(), mate.is_first_in_template(), mate.seq().as_bytes(), mate.qual().to_vec())
+        }).collect::<Vec<_>>();
+        assert_eq!(mates, vec![
+            (String::from("read-0"), true, b"AA".to_vec(), vec![0, 1]),
+            (String::from("read-0"), false, b"AC".to_vec(), vec![6, 5]),
+            (String::from("read-1"), true, b"GG".to_vec(), vec![4, 5]),
+            (String::from("read-1"), false, b"CG".to_vec(), vec![10, 9]),
+        ]);
+
+        // Reverse strand chunks start at the end of their query range
+        rec.set_reverse();
+        let mates = synthesizer.synthesize_chunks(&rec, &chunks[1..], &query_ranges[1..]).iter().map(|mate| mate.seq().as_bytes()).collect::<Vec<_>>();
+        assert_eq!(mates, vec![b"AA".to_vec(), b"AC".to_vec(), b"AC".to_vec(), b"GT".to_vec()]);
+    }
+}
@@ -0,0 +1,112 @@
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender};
+use std::thread;
+use rust_htslib::bam::{HeaderView, Record};
+use rust_htslib::errors::Result as HtsResult;
+use rust_htslib::htslib;
+use crate::input::InputReader;
+
+/// Reads records ahead on a dedicated thread, so htslib decoding overlaps with chopping.
+///
+/// At most `capacity` decoded records wait to be picked up, and records handed back through `read`
+/// are recycled to hold later ones instead of allocating a new record each time. Records cross
+/// threads without a header attached, since the header is reference counted with `Rc`.
+pub struct PrefetchReader {
+    header: HeaderView,
+    source: Source,
+}
+
+enum Source {
+    Direct(InputReader),
+    Prefetched {
+        records: Receiver<HtsResult<Record>>,
+        recycle: Sender<Record>,
+    },
+}
+
+impl PrefetchReader {
+    /// Prefetch up to `capacity` records from `reader`, or read them inline if `capacity` is 0
+    pub fn new(mut reader: InputReader, capacity: usize) -> Self {
+        let header = reader.header().clone();
+        if capacity == 0 {
+            return Self { header, source: Source::Direct(reader) };
+        }
+
+        let (record_sender, records) = mpsc::sync_channel(capacity);
+        let (recycle, recycled) = mpsc::channel();
+        thread::spawn(move || Self::prefetch(&mut reader, record_sender, recycled));
+        Self { header, source: Source::Prefetched { records, recycle } }
+    }
+
+    fn prefetch(reader: &mut InputReader, records: SyncSender<HtsResult<Record>>, recycled: Receiver<Record>) {
+        let mut scratch = Record::new();
+        while let Some(result) = reader.read(&mut scratch) {
+            let result = result.map(|_| {
+                // Copy into a headerless record, which is safe to hand to another thread
+                let mut slot = recycled.try_recv().unwrap_or_else(|_| Record::new());
+                unsafe { htslib::bam_copy1(slot.inner_mut(), scratch.inner()) };
+                slot
+            });
+            let failed = result.is_err();
+            // Stop if the reading side has gone away or the input can't be parsed further
+            if records.send(result).is_err() || failed {
+                return;
+            }
+        }
+    }
+
+    pub fn header(&self) -> &HeaderView {
+        &self.header
+    }
+
+    /// Read the next record into `record`, like `bam::Read::read`
+    pub fn read(&mut self, record: &mut Record) -> Option<HtsResult<()>> {
+        match &mut self.source {
+            Source::Direct(reader) => reader.read(record),
+            Source::Prefetched { records, recycle } => match records.recv().ok()? {
+                Ok(mut next) => {
+                    std::mem::swap(record, &mut next);
+                    // Fine to fail once the prefetch thread has finished
+                    let _ = recycle.send(next);
+                    Some(Ok(()))
+                }
+                Err(e) => Some(Err(e)),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn prefetch_test() {
+        let dir = std::env::temp_dir().join(format!("chop_reads_prefetch_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let sam_path = dir.join("in.sam");
+        let mut sam = String::from("@SQ\tSN:chr1\tLN:1000\n");
+        for i in 0..50 {
+            sam.push_str(&format!("r{}\t0\tchr1\t{}\t60\t4M\t*\t0\t0\tACGT\tIIII\n", i, i + 1));
+        }
+        std::fs::write(&sam_path, sam).unwrap();
+
+        let read_all = |capacity: usize| {
+            let mut reader = PrefetchReader::new(InputReader::open(Path::new(&sam_path), None).unwrap(), capacity);
+            assert_eq!(reader.header().target_count(), 1);
+            let mut record = Record::new();
+            let mut records = Vec::new();
+            while let Some(r) = reader.read(&mut record) {
+                r.unwrap();
+                records.push((String::from_utf8(record.qname().to_vec()).unwrap(), record.pos()));
+            }
+            records
+        };
+        let direct = read_all(0);
+        assert_eq!(direct.len(), 50);
+        assert_eq!(direct[49], (String::from("r49"), 49));
+        assert_eq!(read_all(4), direct);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
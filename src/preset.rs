@@ -0,0 +1,51 @@
+use clap::ValueEnum;
+
+/// Bundles of parameters suited to chopping reads from, or into the shape of, common sequencing platforms
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Preset {
+    /// 10kb chunks of ONT ultra-long reads, keeping their tags and dropping clipped bases
+    OntUltralong,
+    /// 2kb chunks of PacBio HiFi reads, keeping their tags
+    PacbioHifi,
+    /// 150bp reads, as R1/R2 pairs 200bp apart when writing FASTQ
+    Illumina,
+    /// 150bp reads, as R1/R2 pairs 1kb apart when writing FASTQ, like contacts between distant fragments
+    HiC,
+}
+
+impl Preset {
+    /// Long flags (without the leading --) and values, if they take one, that this preset stands for, grouped
+    /// with those they only apply alongside (e.g. --inner-distance with --pair-chunks).
+    ///
+    /// Pairing only applies to FASTQ output, as it can't be written otherwise.
+    pub fn args(&self, fastq: bool) -> Vec<Vec<(&'static str, Option<&'static str>)>> {
+        let mut args = match self {
+            Preset::OntUltralong => vec![vec![("chunk-size", Some("10000"))], vec![("min-length", Some("1000"))], vec![("clip-policy", Some("drop"))], vec![("keep-tags", None)]],
+            Preset::PacbioHifi => vec![vec![("chunk-size", Some("2000"))], vec![("min-length", Some("500"))], vec![("keep-tags", None)]],
+            Preset::Illumina | Preset::HiC => vec![vec![("chunk-size", Some("150"))], vec![("min-length", Some("150"))]],
+        };
+        let inner_distance = match self {
+            Preset::Illumina => "200",
+            Preset::HiC => "1000",
+            _ => return args,
+        };
+        if fastq {
+            args.push(vec![("pair-chunks", None), ("inner-distance", Some(inner_distance))]);
+        } else {
+            args.push(vec![("clip-policy", Some("drop"))]);
+        }
+        args
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn args_test() {
+        assert_eq!(Preset::PacbioHifi.args(true), vec![vec![("chunk-size", Some("2000"))], vec![("min-length", Some("500"))], vec![("keep-tags", None)]]);
+        assert_eq!(Preset::HiC.args(true)[2..], [vec![("pair-chunks", None), ("inner-distance", Some("1000"))]]);
+        assert_eq!(Preset::Illumina.args(false)[2..], [vec![("clip-policy", Some("drop"))]]);
+    }
+}
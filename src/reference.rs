@@ -0,0 +1,331 @@
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+use std::sync::Mutex;
+use memmap2::Mmap;
+use rust_htslib::bam::Record;
+use rust_htslib::bam::record::{Aux, Cigar};
+use rust_htslib::htslib;
+
+// Where a contig's bases start in a plain FASTA and how its lines are wrapped, from the .fai
+#[derive(Debug, Clone, Copy)]
+struct FaiEntry {
+    len: u64,
+    offset: u64,
+    line_bases: u64,
+    line_width: u64,
+}
+
+// Owned htslib faidx handle.
+//
+// This talks to htslib directly rather than through `faidx::Reader`, which never frees the
+// sequences it fetches and so leaks memory on every per-chunk lookup.
+struct Faidx(*mut htslib::faidx_t);
+
+// Only ever used from behind a Mutex
+unsafe impl Send for Faidx {}
+
+impl Faidx {
+    fn load(path: &Path) -> io::Result<Self> {
+        let c_path = CString::new(path.to_string_lossy().as_bytes()).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let fai = unsafe { htslib::fai_load(c_path.as_ptr()) };
+        if fai.is_null() {
+            return Err(io::Error::other(format!("Unable to load FASTA index for {}", path.display())));
+        }
+        Ok(Self(fai))
+    }
+
+    fn seq_len(&self, contig: &str) -> Option<u64> {
+        let c_contig = CString::new(contig).ok()?;
+        let len = unsafe { htslib::faidx_seq_len64(self.0, c_contig.as_ptr()) };
+        (len >= 0).then_some(len as u64)
+    }
+
+    // Uppercased bases of the whole contig
+    fn fetch_contig(&self, contig: &str) -> Option<Vec<u8>> {
+        let end = self.seq_len(contig)?;
+        if end == 0 {
+            return Some(Vec::new());
+        }
+
+        let c_contig = CString::new(contig).ok()?;
+        let mut len: htslib::hts_pos_t = 0;
+        unsafe {
+            let ptr = htslib::faidx_fetch_seq64(self.0, c_contig.as_ptr(), 0, end as htslib::hts_pos_t - 1, &mut len);
+            if ptr.is_null() || len < 0 {
+                return None;
+            }
+            let bases = std::slice::from_raw_parts(ptr as *const u8, len as usize).to_ascii_uppercase();
+            htslib::free(ptr as *mut std::ffi::c_void);
+            Some(bases)
+        }
+    }
+}
+
+impl Drop for Faidx {
+    fn drop(&mut self) {
+        unsafe { htslib::fai_destroy(self.0) };
+    }
+}
+
+enum Sequences {
+    /// Plain FASTA, read straight out of the mapped file
+    Mapped { mmap: Mmap, entries: HashMap<String, FaiEntry> },
+    /// bgzipped FASTA, decompressed a contig at a time and kept until another contig is asked for
+    Compressed { fai: Mutex<Faidx>, cached: Mutex<Option<(String, Vec<u8>)>> },
+}
+
+/// Indexed FASTA reference, building the .fai if it's missing.
+///
+/// Plain FASTA files are memory mapped, so lookups are just slicing and the page cache is shared by
+/// every thread holding the reference. bgzipped ones are decompressed one contig at a time, which
+/// suits coordinate-sorted input.
+pub struct Reference {
+    sequences: Sequences,
+}
+
+impl Reference {
+    pub fn from_path(path: &Path) -> io::Result<Self> {
+        // Loading through htslib first builds the .fai (and .gzi) if needed
+        let fai = Faidx::load(path)?;
+
+        let mut magic = [0u8; 2];
+        let mut file = File::open(path)?;
+        let compressed = file.read_exact(&mut magic).is_ok() && magic == [0x1f, 0x8b];
+        if compressed {
+            return Ok(Self { sequences: Sequences::Compressed { fai: Mutex::new(fai), cached: Mutex::new(None) } });
+        }
+
+        let mut fai_path = path.as_os_str().to_owned();
+        fai_path.push(".fai");
+        let entries = std::fs::read_to_string(&fai_path)?.lines().map(|line| {
+            let fields = line.split('\t').collect::<Vec<_>>();
+            let number = |i: usize| fields.get(i).and_then(|field| field.parse::<u64>().ok());
+            match (number(1), number(2), number(3), number(4)) {
+                (Some(len), Some(offset), Some(line_bases), Some(line_width)) => Ok((fields[0].to_string(), FaiEntry { len, offset, line_bases, line_width })),
+                _ => Err(io::Error::new(io::ErrorKind::InvalidData, format!("Malformed FASTA index line: {}", line))),
+            }
+        }).collect::<io::Result<HashMap<_, _>>>()?;
+        // Safety: the reference isn't expected to change underneath a run
+        let mmap = unsafe { Mmap::map(&file)? };
+        Ok(Self { sequences: Sequences::Mapped { mmap, entries } })
+    }
+
+    /// Length of `contig`, if present in the reference
+    pub fn contig_len(&self, contig: &str) -> Option<u64> {
+        match &self.sequences {
+            Sequences::Mapped { entries, .. } => entries.get(contig).map(|entry| entry.len),
+            Sequences::Compressed { fai, .. } => fai.lock().unwrap().seq_len(contig),
+        }
+    }
+
+    /// Uppercased bases of `contig` in the half-open interval [start, end), clipped to the contig
+    pub fn fetch(&self, contig: &str, start: u64, end: u64) -> Option<Vec<u8>> {
+        let end = end.min(self.contig_len(contig)?);
+        if start >= end {
+            return Some(Vec::new());
+        }
+
+        match &self.sequences {
+            Sequences::Mapped { mmap, entries } => {
+                let entry = entries.get(contig)?;
+                let mut bases = Vec::with_capacity((end - start) as usize);
+                let mut pos = start;
+                while pos < end {
+                    // Copy up to the end of the current line, skipping its newline
+                    let line_end = (pos / entry.line_bases + 1) * entry.line_bases;
+                    let seg_end = line_end.min(end);
+                    let file_offset = entry.offset + (pos / entry.line_bases) * entry.line_width + pos % entry.line_bases;
+                    bases.extend_from_slice(mmap.get(file_offset as usize..(file_offset + seg_end - pos) as usize)?);
+                    pos = seg_end;
+                }
+                bases.make_ascii_uppercase();
+                Some(bases)
+            }
+            Sequences::Compressed { fai, cached } => {
+                let mut cached = cached.lock().unwrap();
+                if cached.as_ref().map(|(name, _)| name.as_str()) != Some(contig) {
+                    let bases = fai.lock().unwrap().fetch_contig(contig)?;
+                    *cached = Some((contig.to_string(), bases));
+                }
+                cached.as_ref().map(|(_, bases)| bases[start as usize..end as usize].to_vec())
+            }
+        }
+    }
+
+    /// Half-open intervals of `contig` where a base other than N repeats more than `min_len` times in a row
+    pub fn homopolymers(&self, contig: &str, min_len: u64) -> Option<Vec<(u64, u64)>> {
+        let bases = self.fetch(contig, 0, self.contig_len(contig)?)?;
+        let mut runs = Vec::new();
+        let mut start = 0;
+        for end in 1..=bases.len() {
+            if end == bases.len() || bases[end] != bases[start] {
+                if (end - start) as u64 > min_len && bases[start] != b'N' {
+                    runs.push((start as u64, end as u64));
+                }
+                start = end;
+            }
+        }
+        Some(runs)
+    }
+
+    /// Tag a mapped chunk with the `flank` reference bases before its start (lf:Z) and after its end (rf:Z)
+    pub fn add_ref_context(&self, rec: &mut Record, contig: &str, flank: u64) {
+        if rec.is_unmapped() || rec.pos() < 0 {
+            return;
+        }
+        let start = rec.pos() as u64;
+        let end = rec.cigar().end_pos().max(rec.pos()) as u64;
+
+        let left = self.fetch(contig, start.saturating_sub(flank), start).unwrap_or_else(|| panic!("Contig {} not found in reference", contig));
+        let right = self.fetch(contig, end, end + flank).unwrap_or_else(|| panic!("Contig {} not found in reference", contig));
+        rec.push_aux(b"lf", Aux::String(&String::from_utf8_lossy(&left))).expect("Unable to add lf tag");
+        rec.push_aux(b"rf", Aux::String(&String::from_utf8_lossy(&right))).expect("Unable to add rf tag");
+    }
+
+    /// Fraction of a mapped chunk's aligned bases that differ from the reference, ignoring Ns.
+    ///
+    /// Returns None for unmapped chunks, those without SEQ, or those without any comparable bases.
+    pub fn mismatch_rate(&self, rec: &Record, contig: &str) -> Option<f64> {
+        if rec.is_unmapped() || rec.seq_len() == 0 {
+            return None;
+        }
+        let ref_start = rec.pos() as u64;
+        let ref_bases = self.fetch(contig, ref_start, rec.cigar().end_pos() as u64).unwrap_or_else(|| panic!("Contig {} not found in reference", contig));
+        let seq = rec.seq().as_bytes();
+
+        let (mut ref_offset, mut query_offset) = (0usize, 0usize);
+        let (mut compared, mut mismatches) = (0u64, 0u64);
+        for c in rec.cigar().iter() {
+            match c {
+                Cigar::Match(len) | Cigar::Equal(len) | Cigar::Diff(len) => {
+                    for i in 0..*len as usize {
+                        let (ref_base, read_base) = (ref_bases.get(ref_offset + i).copied().unwrap_or(b'N'), seq[query_offset + i].to_ascii_uppercase());
+                        if ref_base != b'N' && read_base != b'N' {
+                            compared += 1;
+                            mismatches += (ref_base != read_base) as u64;
+                        }
+                    }
+                    ref_offset += *len as usize;
+                    query_offset += *len as usize;
+                }
+                Cigar::Ins(len) | Cigar::SoftClip(len) => query_offset += *len as usize,
+                Cigar::Del(len) | Cigar::RefSkip(len) => ref_offset += *len as usize,
+                Cigar::HardClip(_) | Cigar::Pad(_) => {}
+            }
+        }
+
+        (compared > 0).then(|| mismatches as f64 / compared as f64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_htslib::bam::record::{Cigar, CigarString};
+
+    #[test]
+    fn ref_context_test() {
+        let dir = std::env::temp_dir().join(format!("chop_reads_reference_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let fasta_path = dir.join("ref.fa");
+        std::fs::write(&fasta_path, b">chr1\nacgtACGTAA\nCCGGTT\n>chr2\nGGGG\n").unwrap();
+
+        let reference = Reference::from_path(&fasta_path).unwrap();
+        assert_eq!(reference.contig_len("chr1"), Some(16));
+        assert_eq!(reference.contig_len("chr3"), None);
+        assert_eq!(reference.fetch("chr1", 2, 12), Some(b"GTACGTAACC".to_vec()));
+        assert_eq!(reference.fetch("chr2", 2, 10), Some(b"GG".to_vec()));
+        assert_eq!(reference.fetch("chr2", 6, 10), Some(Vec::new()));
+        assert_eq!(reference.fetch("chr3", 0, 1), None);
+
+        let mut rec = Record::default();
+        rec.set(b"test", Some(&CigarString(vec![Cigar::Match(2), Cigar::Del(1), Cigar::Match(2)])), b"ACGT", b"IIII");
+        rec.set_pos(1);
+        reference.add_ref_context(&mut rec, "chr1", 3);
+        assert_eq!(rec.aux(b"lf").unwrap(), Aux::String("A"));
+        assert_eq!(rec.aux(b"rf").unwrap(), Aux::String("GTA"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn compressed_reference_test() {
+        let dir = std::env::temp_dir().join(format!("chop_reads_bgzf_reference_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let fasta_path = dir.join("ref.fa.gz");
+        let mut writer = rust_htslib::bgzf::Writer::from_path(&fasta_path).unwrap();
+        std::io::Write::write_all(&mut writer, b">chr1\nacgtACGTAA\nCCGGTT\n>chr2\nGGGG\n").unwrap();
+        drop(writer);
+
+        let reference = Reference::from_path(&fasta_path).unwrap();
+        assert_eq!(reference.contig_len("chr1"), Some(16));
+        assert_eq!(reference.fetch("chr1", 2, 12), Some(b"GTACGTAACC".to_vec()));
+        assert_eq!(reference.fetch("chr2", 2, 10), Some(b"GG".to_vec()));
+        assert_eq!(reference.fetch("chr1", 0, 4), Some(b"ACGT".to_vec()));
+        assert_eq!(reference.fetch("chr3", 0, 1), None);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn shared_reference_test() {
+        let dir = std::env::temp_dir().join(format!("chop_reads_shared_reference_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let fasta_path = dir.join("ref.fa");
+        std::fs::write(&fasta_path, b">chr1\nACGTACGTAA\nCCGGTT\n").unwrap();
+        let reference = Reference::from_path(&fasta_path).unwrap();
+
+        std::thread::scope(|scope| {
+            for start in 0..4 {
+                let reference = &reference;
+                scope.spawn(move || assert_eq!(reference.fetch("chr1", start * 4, start * 4 + 4), Some(b"ACGTACGTAACCGGTT"[start as usize * 4..start as usize * 4 + 4].to_vec())));
+            }
+        });
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn homopolymers_test() {
+        let dir = std::env::temp_dir().join(format!("chop_reads_homopolymers_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let fasta_path = dir.join("ref.fa");
+        std::fs::write(&fasta_path, b">chr1\naaaACGGG\nGTNNNNTT\nT\n").unwrap();
+        let reference = Reference::from_path(&fasta_path).unwrap();
+
+        // Runs are found across lines and regardless of case, but never of N
+        assert_eq!(reference.homopolymers("chr1", 2), Some(vec![(0, 4), (5, 9), (14, 17)]));
+        assert_eq!(reference.homopolymers("chr1", 3), Some(vec![(0, 4), (5, 9)]));
+        assert_eq!(reference.homopolymers("chr2", 3), None);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn mismatch_rate_test() {
+        let dir = std::env::temp_dir().join(format!("chop_reads_mismatch_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let fasta_path = dir.join("ref.fa");
+        std::fs::write(&fasta_path, b">chr1\nACGTACGTAANCGGTT\n").unwrap();
+        let reference = Reference::from_path(&fasta_path).unwrap();
+
+        // Softclip and insertion are skipped, the deletion moves along the reference
+        let mut rec = Record::default();
+        let cigar = CigarString(vec![Cigar::SoftClip(2), Cigar::Match(3), Cigar::Ins(1), Cigar::Match(2), Cigar::Del(2), Cigar::Match(2)]);
+        rec.set(b"test", Some(&cigar), b"TTCGTGACAA", b"IIIIIIIIII");
+        rec.set_pos(1);
+        assert_eq!(reference.mismatch_rate(&rec, "chr1"), Some(0.0));
+
+        // Shifted by one base, most positions disagree and the reference N is not compared
+        rec.set_pos(2);
+        assert_eq!(reference.mismatch_rate(&rec, "chr1"), Some(5.0 / 6.0));
+
+        rec.set_unmapped();
+        assert_eq!(reference.mismatch_rate(&rec, "chr1"), None);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,227 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+use rust_htslib::bam::HeaderView;
+
+/// Reference interval to restrict chopping to, 0-based and half-open
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Region {
+    pub contig: String,
+    pub start: i64,
+    /// None runs to the end of the contig
+    pub end: Option<i64>,
+}
+
+/// Region resolved against an input's header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TargetRegion {
+    pub tid: i32,
+    pub start: i64,
+    pub end: i64,
+}
+
+impl Region {
+    /// Parse a samtools-style region (chr, chr:start or chr:start-end, 1-based inclusive, commas allowed)
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let whole = Region { contig: text.to_string(), start: 0, end: None };
+        // Contig names may themselves contain colons (e.g. HLA alleles), so only split off a numeric range
+        let Some((contig, range)) = text.rsplit_once(':') else {
+            return Ok(whole);
+        };
+        let range = range.replace(',', "");
+        let (start, end) = match range.split_once('-') {
+            Some((start, end)) => (start, Some(end)),
+            None => (range.as_str(), None),
+        };
+        let Ok(start) = start.parse::<i64>() else {
+            return Ok(whole);
+        };
+        let end = end.map(|end| end.parse::<i64>().map_err(|_| format!("Invalid region end in {}", text))).transpose()?;
+        if start < 1 || end.is_some_and(|end| end < start) {
+            return Err(format!("Invalid region {}", text));
+        }
+        Ok(Region { contig: contig.to_string(), start: start - 1, end })
+    }
+}
+
+/// Read the intervals of a BED file, skipping blank, comment, track and browser lines
+pub fn read_bed(path: &Path) -> io::Result<Vec<Region>> {
+    let mut regions = Vec::new();
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        if line.trim().is_empty() || line.starts_with('#') || line.starts_with("track") || line.starts_with("browser") {
+            continue;
+        }
+        let fields = line.split('\t').collect::<Vec<_>>();
+        let coord = |i: usize| fields.get(i).and_then(|field| field.trim().parse::<i64>().ok());
+        match (coord(1), coord(2)) {
+            (Some(start), Some(end)) if start >= 0 && end >= start => regions.push(Region { contig: fields[0].to_string(), start, end: Some(end) }),
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Malformed BED line: {}", line))),
+        }
+    }
+    Ok(regions)
+}
+
+/// Read the exons of a GTF or GFF file as 0-based half-open intervals, skipping other features and comments
+pub fn read_gtf_exons(path: &Path) -> io::Result<Vec<Region>> {
+    let mut exons = Vec::new();
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields = line.split('\t').collect::<Vec<_>>();
+        if fields.len() < 5 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Malformed GTF line: {}", line)));
+        }
+        if fields[2] != "exon" {
+            continue;
+        }
+        match (fields[3].parse::<i64>(), fields[4].parse::<i64>()) {
+            (Ok(start), Ok(end)) if start >= 1 && end >= start => exons.push(Region { contig: fields[0].to_string(), start: start - 1, end: Some(end) }),
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Malformed GTF line: {}", line))),
+        }
+    }
+    Ok(exons)
+}
+
+/// Read a list of positions, one `contig position` (1-based) per line as samtools' -l takes them, as 0-based positions
+pub fn read_positions(path: &Path) -> io::Result<Vec<(String, i64)>> {
+    let mut positions = Vec::new();
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields = line.split_whitespace().collect::<Vec<_>>();
+        match fields.get(1).and_then(|field| field.parse::<i64>().ok()) {
+            Some(pos) if pos >= 1 && fields.len() == 2 => positions.push((fields[0].to_string(), pos - 1)),
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Malformed position line: {}", line))),
+        }
+    }
+    Ok(positions)
+}
+
+/// Resolve `regions` against `header`, sorted by position with overlapping or touching intervals merged.
+///
+/// Merging means each record is fetched once per run of overlapping intervals, and a record
+/// spanning the gap between two merged intervals can be recognised because it starts before the
+/// previous interval's end.
+pub fn resolve_regions(regions: &[Region], header: &HeaderView) -> io::Result<Vec<TargetRegion>> {
+    let mut targets = regions.iter().map(|region| {
+        let tid = header.tid(region.contig.as_bytes()).ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("Region contig {} not found in input", region.contig)))?;
+        let len = header.target_len(tid).unwrap_or(0) as i64;
+        Ok(TargetRegion { tid: tid as i32, start: region.start.min(len), end: region.end.unwrap_or(len).min(len) })
+    }).collect::<io::Result<Vec<_>>>()?;
+    targets.sort_by_key(|target| (target.tid, target.start, target.end));
+
+    let mut merged: Vec<TargetRegion> = Vec::new();
+    for target in targets {
+        match merged.last_mut() {
+            Some(last) if last.tid == target.tid && target.start <= last.end => last.end = last.end.max(target.end),
+            _ => merged.push(target),
+        }
+    }
+    Ok(merged)
+}
+
+/// Starts and ends of `regions` by tid of `header`, as positions to split records at
+pub fn region_breakpoints(regions: &[Region], header: &HeaderView) -> io::Result<HashMap<i32, Vec<i64>>> {
+    let mut breakpoints: HashMap<i32, Vec<i64>> = HashMap::new();
+    for region in regions {
+        let tid = header.tid(region.contig.as_bytes()).ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("Breakpoint contig {} not found in input", region.contig)))?;
+        let positions = breakpoints.entry(tid as i32).or_default();
+        positions.push(region.start);
+        positions.extend(region.end);
+    }
+    Ok(breakpoints)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_test() {
+        assert_eq!(Region::parse("chr1"), Ok(Region { contig: String::from("chr1"), start: 0, end: None }));
+        assert_eq!(Region::parse("chr1:1,001-2,000"), Ok(Region { contig: String::from("chr1"), start: 1000, end: Some(2000) }));
+        assert_eq!(Region::parse("chr1:500"), Ok(Region { contig: String::from("chr1"), start: 499, end: None }));
+        assert_eq!(Region::parse("HLA-A*01:01"), Ok(Region { contig: String::from("HLA-A*01"), start: 0, end: None }));
+        assert_eq!(Region::parse("HLA-A*01:AB"), Ok(Region { contig: String::from("HLA-A*01:AB"), start: 0, end: None }));
+        assert!(Region::parse("chr1:200-100").is_err());
+        assert!(Region::parse("chr1:0-100").is_err());
+        assert!(Region::parse("chr1:1-x").is_err());
+    }
+
+    #[test]
+    fn read_bed_test() {
+        let dir = std::env::temp_dir().join(format!("chop_reads_bed_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let bed_path = dir.join("targets.bed");
+        std::fs::write(&bed_path, b"track name=panel\n# exons\nchr1\t100\t200\tgeneA\n\nchr2\t0\t50\n").unwrap();
+        assert_eq!(read_bed(&bed_path).unwrap(), vec![
+            Region { contig: String::from("chr1"), start: 100, end: Some(200) },
+            Region { contig: String::from("chr2"), start: 0, end: Some(50) },
+        ]);
+
+        std::fs::write(&bed_path, b"chr1\t200\t100\n").unwrap();
+        assert_eq!(read_bed(&bed_path).unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn read_gtf_exons_test() {
+        let dir = std::env::temp_dir().join(format!("chop_reads_gtf_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let gtf_path = dir.join("genes.gtf");
+        std::fs::write(&gtf_path, b"#!genome-build test\nchr1\tsrc\tgene\t101\t500\t.\t+\t.\tgene_id \"g1\";\nchr1\tsrc\texon\t101\t200\t.\t+\t.\tgene_id \"g1\";\nchr1\tsrc\texon\t401\t500\t.\t+\t.\tgene_id \"g1\";\n").unwrap();
+        assert_eq!(read_gtf_exons(&gtf_path).unwrap(), vec![
+            Region { contig: String::from("chr1"), start: 100, end: Some(200) },
+            Region { contig: String::from("chr1"), start: 400, end: Some(500) },
+        ]);
+
+        std::fs::write(&gtf_path, b"chr1\tsrc\texon\t0\t200\n").unwrap();
+        assert_eq!(read_gtf_exons(&gtf_path).unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn read_positions_test() {
+        let dir = std::env::temp_dir().join(format!("chop_reads_positions_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("positions.txt");
+        std::fs::write(&path, b"# probes\nchr1\t101\nchr1 2000\n\nchr2\t1\n").unwrap();
+        assert_eq!(read_positions(&path).unwrap(), vec![(String::from("chr1"), 100), (String::from("chr1"), 1999), (String::from("chr2"), 0)]);
+
+        std::fs::write(&path, b"chr1\t0\n").unwrap();
+        assert_eq!(read_positions(&path).unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn resolve_regions_test() {
+        let header = HeaderView::from_bytes(b"@SQ\tSN:chr1\tLN:1000\n@SQ\tSN:chr2\tLN:500\n");
+        let region = |contig: &str, start: i64, end: Option<i64>| Region { contig: contig.to_string(), start, end };
+        let regions = vec![region("chr2", 10, Some(20)), region("chr1", 300, Some(400)), region("chr1", 100, Some(200)), region("chr1", 200, Some(250)), region("chr2", 400, None)];
+        assert_eq!(resolve_regions(&regions, &header).unwrap(), vec![
+            TargetRegion { tid: 0, start: 100, end: 250 },
+            TargetRegion { tid: 0, start: 300, end: 400 },
+            TargetRegion { tid: 1, start: 10, end: 20 },
+            TargetRegion { tid: 1, start: 400, end: 500 },
+        ]);
+        assert_eq!(resolve_regions(&[region("chrX", 0, None)], &header).unwrap_err().kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn region_breakpoints_test() {
+        let header = HeaderView::from_bytes(b"@SQ\tSN:chr1\tLN:1000\n@SQ\tSN:chr2\tLN:500\n");
+        let region = |contig: &str, start: i64, end: Option<i64>| Region { contig: contig.to_string(), start, end };
+        let breakpoints = region_breakpoints(&[region("chr2", 10, Some(20)), region("chr2", 40, None)], &header).unwrap();
+        assert_eq!(breakpoints, HashMap::from([(1, vec![10, 20, 40])]));
+        assert_eq!(region_breakpoints(&[region("chrX", 0, None)], &header).unwrap_err().kind(), io::ErrorKind::NotFound);
+    }
+}
@@ -0,0 +1,297 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+use rust_htslib::bam::Record;
+use rust_htslib::bam::record::{Aux, Cigar};
+
+/// Lengths bucketed by powers of two, so long reads and short chunks fit on one axis
+#[derive(Debug, Clone, Default)]
+pub struct LengthHistogram {
+    counts: Vec<u64>,
+}
+
+impl LengthHistogram {
+    pub fn add(&mut self, len: usize) {
+        // Bin 0 holds zero lengths, bin i holds [2^(i-1), 2^i)
+        let bin = (usize::BITS - len.leading_zeros()) as usize;
+        if self.counts.len() <= bin {
+            self.counts.resize(bin + 1, 0);
+        }
+        self.counts[bin] += 1;
+    }
+
+    /// Label and count of each bin up to the longest length seen
+    pub fn bins(&self) -> Vec<(String, u64)> {
+        self.counts.iter().enumerate().map(|(bin, &count)| {
+            let label = match bin {
+                0 => String::from("0"),
+                1 => String::from("1"),
+                _ => format!("{}-{}", 1u64 << (bin - 1), (1u64 << bin) - 1),
+            };
+            (label, count)
+        }).collect()
+    }
+}
+
+/// Counts for one contig or read group
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GroupStats {
+    pub records: u64,
+    pub chunks: u64,
+    /// Bases written in chunks
+    pub bases: u64,
+    /// Records that produced no chunks
+    pub dropped: u64,
+}
+
+impl GroupStats {
+    fn add(&mut self, chunks: &[Record]) {
+        self.records += 1;
+        self.chunks += chunks.len() as u64;
+        self.bases += chunks.iter().map(|chunk| chunk.seq_len() as u64).sum::<u64>();
+        self.dropped += chunks.is_empty() as u64;
+    }
+
+    fn to_json(self) -> String {
+        format!("{{\"records\":{},\"chunks\":{},\"bases\":{},\"dropped\":{}}}", self.records, self.chunks, self.bases, self.dropped)
+    }
+}
+
+fn json_string(text: &str) -> String {
+    let mut json = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+/// Summary of a chopping run, rendered as a standalone HTML page or as JSON
+#[derive(Debug, Clone, Default)]
+pub struct RunReport {
+    parameters: Vec<(String, String)>,
+    records: u64,
+    chunks: u64,
+    per_contig: BTreeMap<String, GroupStats>,
+    per_read_group: BTreeMap<String, GroupStats>,
+    clipped_records: u64,
+    soft_clipped_bases: u64,
+    hard_clipped_bases: u64,
+    parent_lengths: LengthHistogram,
+    chunk_lengths: LengthHistogram,
+    warnings: Vec<String>,
+}
+
+impl RunReport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_parameter(&mut self, name: &str, value: &str) {
+        self.parameters.push((name.to_string(), value.to_string()));
+    }
+
+    pub fn add_warning(&mut self, warning: &str) {
+        self.warnings.push(warning.to_string());
+    }
+
+    /// Count a record read from the input on `contig` ("*" if unplaced) and the chunks written for it
+    pub fn add_record(&mut self, rec: &Record, contig: &str, chunks: &[Record]) {
+        self.add_parent(rec);
+        for chunk in chunks {
+            self.add_chunk(chunk);
+        }
+
+        // Broken down by the parent's read group, since --read-group replaces it on chunks
+        let read_group = match rec.aux(b"RG") {
+            Ok(Aux::String(rg)) => rg.to_string(),
+            _ => String::from("*"),
+        };
+        self.per_contig.entry(contig.to_string()).or_default().add(chunks);
+        self.per_read_group.entry(read_group).or_default().add(chunks);
+    }
+
+    fn add_parent(&mut self, rec: &Record) {
+        self.records += 1;
+        self.parent_lengths.add(rec.seq_len());
+
+        let mut clipped = false;
+        for c in rec.cigar().iter() {
+            match c {
+                Cigar::SoftClip(len) => self.soft_clipped_bases += *len as u64,
+                Cigar::HardClip(len) => self.hard_clipped_bases += *len as u64,
+                _ => continue,
+            }
+            clipped = true;
+        }
+        if clipped {
+            self.clipped_records += 1;
+        }
+    }
+
+    fn add_chunk(&mut self, rec: &Record) {
+        self.chunks += 1;
+        self.chunk_lengths.add(rec.seq_len());
+    }
+
+    fn escape(text: &str) -> String {
+        text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+    }
+
+    fn render_group_table(groups: &BTreeMap<String, GroupStats>, name: &str) -> String {
+        let mut html = format!("<table>\n<tr><th>{}</th><th>Records</th><th>Chunks</th><th>Bases</th><th>Dropped</th></tr>\n", name);
+        for (group, stats) in groups {
+            html.push_str(&format!("<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>\n", Self::escape(group), stats.records, stats.chunks, stats.bases, stats.dropped));
+        }
+        html.push_str("</table>\n");
+        html
+    }
+
+    fn render_table(rows: &[(String, String)]) -> String {
+        let mut html = String::from("<table>\n");
+        for (name, value) in rows {
+            html.push_str(&format!("<tr><th>{}</th><td>{}</td></tr>\n", Self::escape(name), Self::escape(value)));
+        }
+        html.push_str("</table>\n");
+        html
+    }
+
+    // Horizontal bar chart, one bar per histogram bin from the shortest length seen
+    fn render_histogram(histogram: &LengthHistogram) -> String {
+        let bins = histogram.bins().into_iter().skip_while(|(_, count)| *count == 0).collect::<Vec<_>>();
+        let max = bins.iter().map(|(_, count)| *count).max().unwrap_or(0).max(1);
+        let mut svg = format!("<svg width=\"600\" height=\"{}\" xmlns=\"http://www.w3.org/2000/svg\">\n", bins.len() * 20 + 5);
+        for (i, (label, count)) in bins.iter().enumerate() {
+            let y = i * 20;
+            let width = 400 * count / max;
+            svg.push_str(&format!("<text x=\"110\" y=\"{}\" text-anchor=\"end\">{}</text>", y + 15, label));
+            svg.push_str(&format!("<rect x=\"120\" y=\"{}\" width=\"{}\" height=\"16\" fill=\"steelblue\"/>", y + 2, width));
+            svg.push_str(&format!("<text x=\"{}\" y=\"{}\">{}</text>\n", 125 + width, y + 15, count));
+        }
+        svg.push_str("</svg>\n");
+        svg
+    }
+
+    pub fn render(&self) -> String {
+        let counts = [
+            ("Input records", self.records),
+            ("Output chunks", self.chunks),
+            ("Records with clipping", self.clipped_records),
+            ("Soft clipped bases", self.soft_clipped_bases),
+            ("Hard clipped bases", self.hard_clipped_bases),
+        ].iter().map(|(name, count)| (name.to_string(), count.to_string())).collect::<Vec<_>>();
+
+        let mut html = String::from("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>chop-reads report</title>\n");
+        html.push_str("<style>body { font-family: sans-serif; } th { text-align: left; padding-right: 2em; } text { font-size: 12px; }</style>\n");
+        html.push_str("</head>\n<body>\n<h1>chop-reads report</h1>\n");
+        html.push_str("<h2>Parameters</h2>\n");
+        html.push_str(&Self::render_table(&self.parameters));
+        html.push_str("<h2>Counts</h2>\n");
+        html.push_str(&Self::render_table(&counts));
+        html.push_str("<h2>Per contig</h2>\n");
+        html.push_str(&Self::render_group_table(&self.per_contig, "Contig"));
+        html.push_str("<h2>Per read group</h2>\n");
+        html.push_str(&Self::render_group_table(&self.per_read_group, "Read group"));
+        html.push_str("<h2>Input record lengths</h2>\n");
+        html.push_str(&Self::render_histogram(&self.parent_lengths));
+        html.push_str("<h2>Output chunk lengths</h2>\n");
+        html.push_str(&Self::render_histogram(&self.chunk_lengths));
+        html.push_str("<h2>Warnings</h2>\n");
+        if self.warnings.is_empty() {
+            html.push_str("<p>None</p>\n");
+        } else {
+            html.push_str("<ul>\n");
+            for warning in &self.warnings {
+                html.push_str(&format!("<li>{}</li>\n", Self::escape(warning)));
+            }
+            html.push_str("</ul>\n");
+        }
+        html.push_str("</body>\n</html>\n");
+        html
+    }
+
+    pub fn to_json(&self) -> String {
+        let groups = |groups: &BTreeMap<String, GroupStats>| groups.iter().map(|(name, stats)| format!("{}:{}", json_string(name), stats.to_json())).collect::<Vec<_>>().join(",");
+        format!(
+            "{{\"records\":{},\"chunks\":{},\"per_contig\":{{{}}},\"per_read_group\":{{{}}},\"warnings\":[{}]}}\n",
+            self.records,
+            self.chunks,
+            groups(&self.per_contig),
+            groups(&self.per_read_group),
+            self.warnings.iter().map(|warning| json_string(warning)).collect::<Vec<_>>().join(","),
+        )
+    }
+
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let mut file = File::create(path)?;
+        file.write_all(self.render().as_bytes())
+    }
+
+    pub fn write_json(&self, path: &Path) -> io::Result<()> {
+        let mut file = File::create(path)?;
+        file.write_all(self.to_json().as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_htslib::bam::record::CigarString;
+
+    #[test]
+    fn length_histogram_test() {
+        let mut histogram = LengthHistogram::default();
+        for len in [0, 1, 3, 4, 7, 8, 1000] {
+            histogram.add(len);
+        }
+        let bins = histogram.bins();
+        assert_eq!(bins.len(), 11);
+        assert_eq!(bins[..5], [(String::from("0"), 1), (String::from("1"), 1), (String::from("2-3"), 1), (String::from("4-7"), 2), (String::from("8-15"), 1)]);
+        assert_eq!(bins[10], (String::from("512-1023"), 1));
+    }
+
+    #[test]
+    fn render_test() {
+        let mut report = RunReport::new();
+        report.add_parameter("Chunk size", "<5>");
+        report.add_warning("Dropped low quality chunks: 2");
+
+        let mut rec = Record::default();
+        rec.set(b"test", Some(&CigarString(vec![Cigar::HardClip(4), Cigar::SoftClip(2), Cigar::Match(6)])), b"AGTCGATG", b"IIIIIIII");
+        report.add_record(&rec, "chr1", &[rec.clone()]);
+
+        let html = report.render();
+        assert!(html.contains("<tr><th>Chunk size</th><td>&lt;5&gt;</td></tr>"));
+        assert!(html.contains("<tr><th>Records with clipping</th><td>1</td></tr>"));
+        assert!(html.contains("<tr><th>Soft clipped bases</th><td>2</td></tr>"));
+        assert!(html.contains("<tr><th>Hard clipped bases</th><td>4</td></tr>"));
+        assert!(html.contains("<li>Dropped low quality chunks: 2</li>"));
+    }
+
+    #[test]
+    fn breakdown_test() {
+        let mut report = RunReport::new();
+        let mut rec = Record::default();
+        rec.set(b"test", Some(&CigarString(vec![Cigar::Match(8)])), b"AGTCGATG", b"IIIIIIII");
+        let mut chunk = Record::default();
+        chunk.set(b"test-0", Some(&CigarString(vec![Cigar::Match(4)])), b"AGTC", b"IIII");
+
+        report.add_record(&rec, "chr1", &[chunk.clone(), chunk.clone()]);
+        rec.push_aux(b"RG", Aux::String("lane\"1")).unwrap();
+        report.add_record(&rec, "chr2", &[chunk.clone()]);
+        report.add_record(&rec, "chr2", &[]);
+        report.add_warning("Skipped\tthings");
+
+        assert_eq!(report.to_json(), "{\"records\":3,\"chunks\":3,\
+            \"per_contig\":{\"chr1\":{\"records\":1,\"chunks\":2,\"bases\":8,\"dropped\":0},\"chr2\":{\"records\":2,\"chunks\":1,\"bases\":4,\"dropped\":1}},\
+            \"per_read_group\":{\"*\":{\"records\":1,\"chunks\":2,\"bases\":8,\"dropped\":0},\"lane\\\"1\":{\"records\":2,\"chunks\":1,\"bases\":4,\"dropped\":1}},\
+            \"warnings\":[\"Skipped\\u0009things\"]}\n");
+        assert!(report.render().contains("<tr><td>chr2</td><td>2</td><td>1</td><td>4</td><td>1</td></tr>"));
+    }
+}
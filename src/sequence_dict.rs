@@ -0,0 +1,193 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use clap::ValueEnum;
+use rust_htslib::bam::{HeaderView, Record};
+
+/// What to do with records aligned to contigs missing from the target dictionary
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum AbsentContigPolicy {
+    /// Drop the record
+    Drop,
+    /// Keep the record as unmapped
+    Unmap,
+}
+
+/// The @SQ lines of a sequence dictionary (e.g. Picard .dict)
+#[derive(Debug, Clone)]
+pub struct SequenceDictionary {
+    sq_lines: Vec<String>,
+    lengths: HashMap<String, u64>,
+    tids: HashMap<String, i32>,
+}
+
+impl SequenceDictionary {
+    pub fn from_path(path: &Path) -> io::Result<Self> {
+        Self::from_text(&fs::read_to_string(path)?)
+    }
+
+    pub fn from_text(text: &str) -> io::Result<Self> {
+        let mut dict = Self {
+            sq_lines: Vec::new(),
+            lengths: HashMap::new(),
+            tids: HashMap::new(),
+        };
+
+        for line in text.lines().filter(|line| line.starts_with("@SQ")) {
+            let field = |tag: &str| line.split('\t').find_map(|f| f.strip_prefix(tag));
+            let name = field("SN:").ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("@SQ line missing SN: {}", line)))?;
+            let length = field("LN:").and_then(|ln| ln.parse().ok())
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("@SQ line missing valid LN: {}", line)))?;
+
+            dict.tids.insert(name.to_string(), dict.sq_lines.len() as i32);
+            dict.lengths.insert(name.to_string(), length);
+            dict.sq_lines.push(line.to_string());
+        }
+
+        Ok(dict)
+    }
+
+    pub fn sq_lines(&self) -> &Vec<String> {
+        &self.sq_lines
+    }
+
+    /// Map each input tid to its tid in this dictionary, if present.
+    ///
+    /// Panics if a contig is present in both with different lengths, since that means the
+    /// dictionary describes a different assembly.
+    pub fn tid_map(&self, header: &HeaderView) -> Vec<Option<i32>> {
+        (0..header.target_count()).map(|tid| {
+            let name = String::from_utf8_lossy(header.tid2name(tid)).to_string();
+            let input_len = header.target_len(tid).unwrap_or(0);
+            match self.lengths.get(&name) {
+                Some(&len) if len != input_len => panic!("Contig {} has length {} in the input but {} in the target dictionary", name, input_len, len),
+                Some(_) => Some(self.tids[&name]),
+                None => None,
+            }
+        }).collect()
+    }
+}
+
+/// Rewrites record contigs from the input header to a target dictionary
+#[derive(Debug, Clone)]
+pub struct TidRemapper {
+    tid_map: Vec<Option<i32>>,
+    policy: AbsentContigPolicy,
+    absent: u64,
+}
+
+impl TidRemapper {
+    pub fn new(tid_map: Vec<Option<i32>>, policy: AbsentContigPolicy) -> Self {
+        Self {
+            tid_map,
+            policy,
+            absent: 0,
+        }
+    }
+
+    fn map(&self, tid: i32) -> Option<i32> {
+        if tid < 0 {
+            return Some(tid);
+        }
+        self.tid_map.get(tid as usize).copied().flatten()
+    }
+
+    /// Remap `rec` in place, returning false if it should be dropped
+    pub fn remap(&mut self, rec: &mut Record) -> bool {
+        match self.map(rec.tid()) {
+            Some(tid) => rec.set_tid(tid),
+            None => {
+                self.absent += 1;
+                match self.policy {
+                    AbsentContigPolicy::Drop => return false,
+                    AbsentContigPolicy::Unmap => {
+                        rec.set_unmapped();
+                        rec.unset_proper_pair();
+                        rec.set_tid(-1);
+                        rec.set_pos(-1);
+                        rec.set_mapq(0);
+                    }
+                }
+            }
+        }
+
+        // A mate on an absent contig is either dropped or unmapped, so report it as unmapped
+        match self.map(rec.mtid()) {
+            Some(mtid) => rec.set_mtid(mtid),
+            None => {
+                rec.set_mate_unmapped();
+                rec.unset_proper_pair();
+                rec.set_mtid(-1);
+                rec.set_mpos(-1);
+                rec.set_insert_size(0);
+                // Mate CIGAR and MAPQ would contradict the unmapped mate
+                for tag in [b"MC", b"MQ"] {
+                    let _ = rec.remove_aux(tag);
+                }
+            }
+        }
+
+        true
+    }
+
+    /// Number of records seen on contigs absent from the target dictionary
+    pub fn absent(&self) -> u64 {
+        self.absent
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_htslib::bam::record::{Aux, Cigar, CigarString};
+
+    fn make_record(tid: i32, mtid: i32) -> Record {
+        let mut rec = Record::default();
+        rec.set(b"test", Some(&CigarString(vec![Cigar::Match(4)])), b"ACGT", b"IIII");
+        rec.set_tid(tid);
+        rec.set_pos(10);
+        rec.set_mtid(mtid);
+        rec.set_mpos(20);
+        rec.set_paired();
+        rec
+    }
+
+    #[test]
+    fn remap_test() {
+        let dict = SequenceDictionary::from_text("@HD\tVN:1.6\n@SQ\tSN:chr2\tLN:500\n@SQ\tSN:chr1\tLN:1000\tM5:abc\n").unwrap();
+        assert_eq!(dict.sq_lines(), &vec![String::from("@SQ\tSN:chr2\tLN:500"), String::from("@SQ\tSN:chr1\tLN:1000\tM5:abc")]);
+
+        let header = HeaderView::from_bytes(b"@SQ\tSN:chr1\tLN:1000\n@SQ\tSN:decoy\tLN:50\n@SQ\tSN:chr2\tLN:500\n");
+        let tid_map = dict.tid_map(&header);
+        assert_eq!(tid_map, vec![Some(1), None, Some(0)]);
+
+        let mut dropper = TidRemapper::new(tid_map.clone(), AbsentContigPolicy::Drop);
+        let mut rec = make_record(0, 2);
+        assert!(dropper.remap(&mut rec));
+        assert_eq!((rec.tid(), rec.mtid()), (1, 0));
+        assert!(!dropper.remap(&mut make_record(1, 0)));
+
+        let mut rec = make_record(2, 1);
+        rec.push_aux(b"MC", Aux::String("4M")).unwrap();
+        rec.push_aux(b"MQ", Aux::U8(60)).unwrap();
+        assert!(dropper.remap(&mut rec));
+        assert_eq!((rec.tid(), rec.mtid(), rec.mpos()), (0, -1, -1));
+        assert!(rec.is_mate_unmapped());
+        assert!(rec.aux(b"MC").is_err() && rec.aux(b"MQ").is_err());
+
+        let mut unmapper = TidRemapper::new(tid_map, AbsentContigPolicy::Unmap);
+        let mut rec = make_record(1, 0);
+        assert!(unmapper.remap(&mut rec));
+        assert!(rec.is_unmapped());
+        assert_eq!((rec.tid(), rec.pos(), rec.mtid()), (-1, -1, 1));
+        assert_eq!(unmapper.absent(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "Contig chr1 has length 999")]
+    fn length_mismatch_test() {
+        let dict = SequenceDictionary::from_text("@SQ\tSN:chr1\tLN:1000\n").unwrap();
+        dict.tid_map(&HeaderView::from_bytes(b"@SQ\tSN:chr1\tLN:999\n"));
+    }
+}
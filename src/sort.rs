@@ -0,0 +1,307 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::io;
+use std::path::{Path, PathBuf};
+use clap::ValueEnum;
+use rust_htslib::bam::{self, CompressionLevel, Format, Header, Read, Record};
+use crate::output::RecordWriter;
+
+/// Order to sort the output in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SortOrder {
+    /// By contig, position and strand, with unmapped records last
+    Coordinate,
+    /// By read name with digit runs compared numerically, so all chunks of a read are adjacent and in order
+    Name,
+}
+
+/// Compare names the way samtools' queryname sort does, treating runs of digits as numbers
+pub fn natural_cmp(a: &[u8], b: &[u8]) -> Ordering {
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        if a[i].is_ascii_digit() && b[j].is_ascii_digit() {
+            // Skip leading zeros, then a longer run of digits is the bigger number
+            let (start_a, start_b) = (i, j);
+            while i < a.len() && a[i] == b'0' { i += 1; }
+            while j < b.len() && b[j] == b'0' { j += 1; }
+            let (digits_a, digits_b) = (i, j);
+            while i < a.len() && a[i].is_ascii_digit() { i += 1; }
+            while j < b.len() && b[j].is_ascii_digit() { j += 1; }
+            let ordering = (i - digits_a).cmp(&(j - digits_b))
+                .then_with(|| a[digits_a..i].cmp(&b[digits_b..j]))
+                .then_with(|| (i - start_a).cmp(&(j - start_b)));
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+        } else {
+            if a[i] != b[j] {
+                return a[i].cmp(&b[j]);
+            }
+            i += 1;
+            j += 1;
+        }
+    }
+    (a.len() - i).cmp(&(b.len() - j))
+}
+
+impl SortOrder {
+    /// Value for the SO tag of the output's @HD line
+    pub fn header_value(&self) -> &'static str {
+        match self {
+            SortOrder::Coordinate => "coordinate",
+            SortOrder::Name => "queryname",
+        }
+    }
+
+    pub fn compare(&self, a: &Record, b: &Record) -> Ordering {
+        match self {
+            // As an unsigned tid, -1 (unmapped) sorts after every contig
+            SortOrder::Coordinate => (a.tid() as u32, a.pos(), a.is_reverse()).cmp(&(b.tid() as u32, b.pos(), b.is_reverse())),
+            // First in pair before second, as samtools does
+            SortOrder::Name => natural_cmp(a.qname(), b.qname()).then_with(|| (a.flags() & 0xc0).cmp(&(b.flags() & 0xc0))),
+        }
+    }
+}
+
+// Rough in-memory size of a buffered record
+fn record_bytes(rec: &Record) -> usize {
+    std::mem::size_of::<Record>() + rec.inner().l_data as usize
+}
+
+// Head record of a run during the merge, ordered so the heap pops the smallest (earliest run on ties)
+struct MergeEntry {
+    order: SortOrder,
+    record: Record,
+    run: usize,
+}
+
+impl PartialEq for MergeEntry {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for MergeEntry {}
+
+impl PartialOrd for MergeEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for MergeEntry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.order.compare(&self.record, &other.record).then(self.run.cmp(&other.run)).reverse()
+    }
+}
+
+/// Sorts records too many to hold in memory.
+///
+/// Records are buffered up to a memory budget, then sorted and spilled to a temporary BAM. On
+/// finishing, the spilled runs and whatever is still buffered are merged into the output. The sort is
+/// stable, so chunks of one read at the same position keep their order.
+pub struct ExternalSorter {
+    order: SortOrder,
+    header: Header,
+    buffer: Vec<Record>,
+    buffer_bytes: usize,
+    max_buffer_bytes: usize,
+    tmp_prefix: PathBuf,
+    runs: Vec<PathBuf>,
+}
+
+impl ExternalSorter {
+    /// Spill runs are written as `<tmp_prefix>.sort.<n>.bam` using `header`
+    pub fn new(order: SortOrder, header: &Header, max_buffer_bytes: usize, tmp_prefix: &Path) -> Self {
+        Self {
+            order,
+            header: header.clone(),
+            buffer: Vec::new(),
+            buffer_bytes: 0,
+            max_buffer_bytes,
+            tmp_prefix: tmp_prefix.to_path_buf(),
+            runs: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, rec: Record) -> io::Result<()> {
+        self.buffer_bytes += record_bytes(&rec);
+        self.buffer.push(rec);
+        if self.buffer_bytes >= self.max_buffer_bytes {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    /// Number of runs spilled to disk so far
+    pub fn spilled_runs(&self) -> usize {
+        self.runs.len()
+    }
+
+    fn sort_buffer(&mut self) {
+        let order = self.order;
+        self.buffer.sort_by(|a, b| order.compare(a, b));
+    }
+
+    fn spill(&mut self) -> io::Result<()> {
+        self.sort_buffer();
+        let mut run_path = self.tmp_prefix.clone().into_os_string();
+        run_path.push(format!(".sort.{}.bam", self.runs.len()));
+        let run_path = PathBuf::from(run_path);
+
+        let mut writer = bam::Writer::from_path(&run_path, &self.header, Format::Bam).map_err(io::Error::other)?;
+        // Runs are read back once, so favour speed over size
+        writer.set_compression_level(CompressionLevel::Fastest).map_err(io::Error::other)?;
+        for rec in self.buffer.drain(..) {
+            writer.write(&rec).map_err(io::Error::other)?;
+        }
+        self.buffer_bytes = 0;
+        self.runs.push(run_path);
+        Ok(())
+    }
+
+    /// Merge everything pushed so far into `writer`, removing the spilled runs
+    pub fn finish(mut self, writer: &mut RecordWriter) -> io::Result<()> {
+        self.sort_buffer();
+        if self.runs.is_empty() {
+            for rec in &self.buffer {
+                writer.write(rec)?;
+            }
+            return Ok(());
+        }
+
+        // The in-memory buffer takes part in the merge as the last run
+        let mut readers = self.runs.iter().map(|path| bam::Reader::from_path(path).map_err(io::Error::other)).collect::<io::Result<Vec<_>>>()?;
+        let buffered_run = readers.len();
+        let mut buffered = self.buffer.drain(..);
+
+        let mut heap = BinaryHeap::new();
+        for (run, reader) in readers.iter_mut().enumerate() {
+            let mut record = Record::new();
+            if let Some(r) = reader.read(&mut record) {
+                r.map_err(io::Error::other)?;
+                heap.push(MergeEntry { order: self.order, record, run });
+            }
+        }
+        if let Some(record) = buffered.next() {
+            heap.push(MergeEntry { order: self.order, record, run: buffered_run });
+        }
+
+        while let Some(MergeEntry { order, mut record, run }) = heap.pop() {
+            writer.write(&record)?;
+            let next = if run == buffered_run {
+                buffered.next()
+            } else {
+                match readers[run].read(&mut record) {
+                    Some(r) => r.map(|_| Some(record)).map_err(io::Error::other)?,
+                    None => None,
+                }
+            };
+            if let Some(record) = next {
+                heap.push(MergeEntry { order, record, run });
+            }
+        }
+
+        for path in &self.runs {
+            std::fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_htslib::bam::header::HeaderRecord;
+    use rust_htslib::bam::record::{Cigar, CigarString};
+
+    #[test]
+    fn natural_cmp_test() {
+        assert_eq!(natural_cmp(b"read-2", b"read-10"), Ordering::Less);
+        assert_eq!(natural_cmp(b"read-10", b"read-10"), Ordering::Equal);
+        assert_eq!(natural_cmp(b"read7-1", b"read10-0"), Ordering::Less);
+        assert_eq!(natural_cmp(b"read-01", b"read-1"), Ordering::Greater);
+        assert_eq!(natural_cmp(b"read", b"read-0"), Ordering::Less);
+        assert_eq!(natural_cmp(b"readA-1", b"readB-0"), Ordering::Less);
+    }
+
+    #[test]
+    fn name_sort_test() {
+        let dir = std::env::temp_dir().join(format!("chop_reads_name_sort_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let header = Header::new();
+
+        let mut sorter = ExternalSorter::new(SortOrder::Name, &header, 1 << 20, &dir.join("out"));
+        for (qname, flags) in [("b-10", 0x40), ("a-2", 0x80), ("b-2", 0x40), ("a-2", 0x40), ("a-10", 0x40)] {
+            let mut rec = Record::default();
+            rec.set(qname.as_bytes(), None, b"ACGT", b"IIII");
+            rec.set_unmapped();
+            rec.set_tid(-1);
+            rec.set_mtid(-1);
+            rec.set_flags(rec.flags() | flags);
+            sorter.push(rec).unwrap();
+        }
+
+        let out_path = dir.join("out.sam");
+        let mut writer = RecordWriter::Hts(bam::Writer::from_path(&out_path, &header, Format::Sam).unwrap());
+        sorter.finish(&mut writer).unwrap();
+        drop(writer);
+
+        let mut reader = bam::Reader::from_path(&out_path).unwrap();
+        let names = reader.records().map(|r| {
+            let rec = r.unwrap();
+            (String::from_utf8(rec.qname().to_vec()).unwrap(), rec.is_first_in_template())
+        }).collect::<Vec<_>>();
+        assert_eq!(names, vec![
+            (String::from("a-2"), true), (String::from("a-2"), false), (String::from("a-10"), true),
+            (String::from("b-2"), true), (String::from("b-10"), true),
+        ]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn external_sort_test() {
+        let dir = std::env::temp_dir().join(format!("chop_reads_sort_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut header = Header::new();
+        header.push_record(HeaderRecord::new(b"SQ").push_tag(b"SN", "chr1").push_tag(b"LN", 1000));
+        header.push_record(HeaderRecord::new(b"SQ").push_tag(b"SN", "chr2").push_tag(b"LN", 1000));
+
+        // A small budget forces several spilled runs
+        let mut sorter = ExternalSorter::new(SortOrder::Coordinate, &header, 1000, &dir.join("out"));
+        let cigar = CigarString(vec![Cigar::Match(4)]);
+        let mut expected = Vec::new();
+        for i in 0..100u32 {
+            let mut rec = Record::default();
+            let (tid, pos) = match i % 10 {
+                0 => (-1, -1),
+                _ => ((i % 2) as i32, ((i * 37) % 50) as i64),
+            };
+            rec.set(format!("r{}", i).as_bytes(), if tid < 0 { None } else { Some(&cigar) }, b"ACGT", b"IIII");
+            rec.set_tid(tid);
+            rec.set_pos(pos);
+            if tid < 0 {
+                rec.set_unmapped();
+            }
+            expected.push((tid as u32, pos, i));
+            sorter.push(rec).unwrap();
+        }
+        assert!(sorter.spilled_runs() > 1);
+
+        let out_path = dir.join("out.sam");
+        let mut writer = RecordWriter::Hts(bam::Writer::from_path(&out_path, &header, Format::Sam).unwrap());
+        sorter.finish(&mut writer).unwrap();
+        drop(writer);
+
+        // Stable, so ties keep the order they were pushed in
+        expected.sort();
+        let mut reader = bam::Reader::from_path(&out_path).unwrap();
+        let qnames = reader.records().map(|r| String::from_utf8(r.unwrap().qname().to_vec()).unwrap()).collect::<Vec<_>>();
+        assert_eq!(qnames, expected.iter().map(|(_, _, i)| format!("r{}", i)).collect::<Vec<_>>());
+        // Only the output is left behind
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,196 @@
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use crate::output::RecordWriter;
+
+/// Name of the split for records whose read group isn't in the header
+pub const UNASSIGNED_READ_GROUP: &str = "unassigned";
+
+// One of several outputs, written at write_path and renamed to path once finished
+struct SplitOutput {
+    name: String,
+    path: PathBuf,
+    write_path: PathBuf,
+    writer: RecordWriter,
+}
+
+/// A set of named outputs that chunks are distributed across, in the order they were added
+#[derive(Default)]
+pub struct SplitOutputs {
+    outputs: Vec<SplitOutput>,
+    by_name: HashMap<String, usize>,
+}
+
+impl SplitOutputs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add an output called `name`, written through `writer` at `write_path` and moved to `path` when finished
+    pub fn add(&mut self, name: &str, path: PathBuf, write_path: PathBuf, writer: RecordWriter) {
+        self.by_name.insert(name.to_string(), self.outputs.len());
+        self.outputs.push(SplitOutput { name: name.to_string(), path, write_path, writer });
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.by_name.contains_key(name)
+    }
+
+    pub fn writer(&mut self, name: &str) -> Option<&mut RecordWriter> {
+        let i = *self.by_name.get(name)?;
+        Some(&mut self.outputs[i].writer)
+    }
+
+    /// Paths the outputs are being written at
+    pub fn write_paths(&self) -> impl Iterator<Item = &Path> {
+        self.outputs.iter().map(|output| output.write_path.as_path())
+    }
+
+    /// Close every output and move it into place, returning each name and final path
+    pub fn finish(self) -> io::Result<Vec<(String, PathBuf)>> {
+        let mut finished = Vec::new();
+        for output in self.outputs {
+            output.writer.finish()?;
+            if output.write_path != output.path {
+                std::fs::rename(&output.write_path, &output.path)?;
+            }
+            finished.push((output.name, output.path));
+        }
+        Ok(finished)
+    }
+}
+
+// File name of `output` split into its stem and extension, keeping .gz with the extension before it (out.fa.gz -> out, fa.gz)
+fn stem_and_extension(output: &Path) -> (OsString, Option<OsString>) {
+    let stem = Path::new(output.file_stem().unwrap_or_default());
+    match (output.extension(), stem.extension()) {
+        (Some(gz), Some(inner)) if gz == "gz" => {
+            let mut extension = OsString::from(inner);
+            extension.push(".gz");
+            (OsString::from(stem.file_stem().unwrap_or_default()), Some(extension))
+        }
+        (extension, _) => (OsString::from(stem), extension.map(OsString::from)),
+    }
+}
+
+// Whether `output` is a template with placeholders like {rg} for the field each split is named by
+fn is_template(output: &Path) -> bool {
+    output.to_string_lossy().contains('{')
+}
+
+// Names of the placeholders in `output`, in order
+fn placeholders(output: &Path) -> Vec<String> {
+    let output = output.to_string_lossy();
+    output.split('{').skip(1).map(|part| part.split_once('}').map_or(part, |(name, _)| name).to_string()).collect()
+}
+
+/// Check that an output path template only has a placeholder for `field`, and has one so the outputs
+/// don't overwrite each other. Paths without placeholders are always fine.
+pub fn check_template(output: &Path, field: &str) -> Result<(), String> {
+    if !is_template(output) {
+        return Ok(());
+    }
+    if let Some(unknown) = placeholders(output).into_iter().find(|name| name != field) {
+        return Err(format!("Unknown placeholder {{{}}} in output path {} (expected {{{}}})", unknown, output.display(), field));
+    }
+    match output.to_string_lossy().contains(&format!("{{{}}}", field)) {
+        true => Ok(()),
+        false => Err(format!("Output path {} needs a {{{}}} placeholder to tell its outputs apart", output.display(), field)),
+    }
+}
+
+/// Path for the part of `output` whose `field` is `value`.
+///
+/// An output path template like `out/{rg}/chunks.bam` has its placeholder replaced by the value, while
+/// other paths have it inserted before the extension (out.bam -> out.lane1.bam). Characters other than
+/// letters, digits, '.', '-' and '_' in the value are replaced so it's safe in a file name.
+pub fn split_output_path(output: &Path, field: &str, value: &str) -> PathBuf {
+    let value = value.chars().map(|c| if c.is_ascii_alphanumeric() || ".-_".contains(c) { c } else { '_' }).collect::<String>();
+    if is_template(output) {
+        return PathBuf::from(output.to_string_lossy().replace(&format!("{{{}}}", field), &value));
+    }
+    let (mut file_name, extension) = stem_and_extension(output);
+    file_name.push(format!(".{}", value));
+    if let Some(extension) = extension {
+        file_name.push(".");
+        file_name.push(extension);
+    }
+    output.with_file_name(file_name)
+}
+
+/// Name of shard `shard` out of `shards`, zero padded so they sort in order
+pub fn shard_name(shard: u32, shards: u32) -> String {
+    let width = (shards.max(1) - 1).to_string().len();
+    format!("{:0width$}", shard, width = width)
+}
+
+/// Path of the TSV listing which file each split went to (out.bam -> out.manifest.tsv), or for an
+/// output path template, manifest.tsv in the directory before its first placeholder
+pub fn manifest_path(output: &Path) -> PathBuf {
+    if is_template(output) {
+        return output.components().take_while(|component| !component.as_os_str().to_string_lossy().contains('{')).collect::<PathBuf>().join("manifest.tsv");
+    }
+    let (mut file_name, _) = stem_and_extension(output);
+    file_name.push(".manifest.tsv");
+    output.with_file_name(file_name)
+}
+
+/// Write a two column manifest of split names (under the `column` heading) and their paths
+pub fn write_manifest(path: &Path, column: &str, outputs: &[(String, PathBuf)]) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    writeln!(writer, "{}\tpath", column)?;
+    for (name, output_path) in outputs {
+        writeln!(writer, "{}\t{}", name, output_path.display())?;
+    }
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_output_path_test() {
+        assert_eq!(split_output_path(Path::new("out/chunks.bam"), "rg", "lane1"), PathBuf::from("out/chunks.lane1.bam"));
+        assert_eq!(split_output_path(Path::new("chunks.cram"), "rg", "HG002/L001:1"), PathBuf::from("chunks.HG002_L001_1.cram"));
+        assert_eq!(split_output_path(Path::new("chunks"), "rg", "rg"), PathBuf::from("chunks.rg"));
+        assert_eq!(split_output_path(Path::new("chunks.fa.gz"), "shard", "0"), PathBuf::from("chunks.0.fa.gz"));
+        assert_eq!(manifest_path(Path::new("out/chunks.bam")), PathBuf::from("out/chunks.manifest.tsv"));
+        assert_eq!(manifest_path(Path::new("chunks.fa.gz")), PathBuf::from("chunks.manifest.tsv"));
+    }
+
+    #[test]
+    fn output_template_test() {
+        let template = Path::new("out/{rg}/chunks.bam");
+        assert_eq!(split_output_path(template, "rg", "HG002/L001"), PathBuf::from("out/HG002_L001/chunks.bam"));
+        assert_eq!(split_output_path(Path::new("out/{shard}.fq.gz"), "shard", "03"), PathBuf::from("out/03.fq.gz"));
+        assert_eq!(manifest_path(template), PathBuf::from("out/manifest.tsv"));
+        assert_eq!(manifest_path(Path::new("{rg}.bam")), PathBuf::from("manifest.tsv"));
+
+        assert_eq!(check_template(template, "rg"), Ok(()));
+        assert_eq!(check_template(Path::new("out.bam"), "rg"), Ok(()));
+        assert!(check_template(Path::new("out/{contig}/{rg}.bam"), "rg").unwrap_err().starts_with("Unknown placeholder {contig}"));
+        assert!(check_template(Path::new("out/{}.bam"), "shard").unwrap_err().starts_with("Unknown placeholder {}"));
+    }
+
+    #[test]
+    fn shard_name_test() {
+        assert_eq!(shard_name(3, 4), "3");
+        assert_eq!(shard_name(3, 10), "3");
+        assert_eq!(shard_name(3, 11), "03");
+        assert_eq!(shard_name(0, 1), "0");
+    }
+
+    #[test]
+    fn manifest_test() {
+        let dir = std::env::temp_dir().join(format!("chop_reads_manifest_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("chunks.manifest.tsv");
+        write_manifest(&path, "read_group", &[(String::from("rg1"), PathBuf::from("chunks.rg1.bam")), (String::from("unassigned"), PathBuf::from("chunks.unassigned.bam"))]).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "read_group\tpath\nrg1\tchunks.rg1.bam\nunassigned\tchunks.unassigned.bam\n");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,100 @@
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+
+/// Progress counters shared between the chopping loop and the status endpoint
+#[derive(Debug)]
+pub struct ProgressCounters {
+    pub records_read: AtomicU64,
+    pub chunks_written: AtomicU64,
+    pub records_skipped: AtomicU64,
+    pub done: AtomicBool,
+    start: Instant,
+}
+
+impl Default for ProgressCounters {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ProgressCounters {
+    pub fn new() -> Self {
+        Self {
+            records_read: AtomicU64::new(0),
+            chunks_written: AtomicU64::new(0),
+            records_skipped: AtomicU64::new(0),
+            done: AtomicBool::new(false),
+            start: Instant::now(),
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        let elapsed = self.start.elapsed().as_secs_f64();
+        let records_read = self.records_read.load(Ordering::Relaxed);
+        let records_per_second = if elapsed > 0.0 { records_read as f64 / elapsed } else { 0.0 };
+        format!(
+            "{{\"records_read\":{},\"chunks_written\":{},\"records_skipped\":{},\"elapsed_seconds\":{:.1},\"records_per_second\":{:.1},\"done\":{}}}",
+            records_read,
+            self.chunks_written.load(Ordering::Relaxed),
+            self.records_skipped.load(Ordering::Relaxed),
+            elapsed,
+            records_per_second,
+            self.done.load(Ordering::Relaxed),
+        )
+    }
+}
+
+fn respond(stream: TcpStream, counters: &ProgressCounters) -> io::Result<()> {
+    // Drain the request headers; every path gets the same status document
+    let mut reader = BufReader::new(&stream);
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 2 {
+        line.clear();
+    }
+
+    let body = counters.to_json();
+    let mut stream = &stream;
+    write!(stream, "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body)
+}
+
+/// Serve the counters as JSON over HTTP on a background thread, returning the bound address
+pub fn start_status_server(addr: &str, counters: Arc<ProgressCounters>) -> io::Result<SocketAddr> {
+    let listener = TcpListener::bind(addr)?;
+    let local_addr = listener.local_addr()?;
+
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            // A misbehaving client shouldn't take down the run
+            let _ = respond(stream, &counters);
+        }
+    });
+
+    Ok(local_addr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn status_server_test() {
+        let counters = Arc::new(ProgressCounters::new());
+        counters.records_read.store(12, Ordering::Relaxed);
+        counters.chunks_written.store(40, Ordering::Relaxed);
+
+        let addr = start_status_server("127.0.0.1:0", counters.clone()).unwrap();
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(b"GET /status HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("\"records_read\":12,\"chunks_written\":40,\"records_skipped\":0,"));
+        assert!(response.ends_with("\"done\":false}"));
+    }
+}
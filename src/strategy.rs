@@ -0,0 +1,394 @@
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::str::FromStr;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rust_htslib::bam::Record;
+use rust_htslib::bam::record::{Aux, Cigar, CigarString};
+
+/// A policy for where records are split, which AlignmentChopper consults while walking their CIGARs.
+///
+/// Every method defaults to leaving the split alone, so a strategy only implements what it decides on,
+/// and strategies combine: a record is split wherever any of them asks. New policies can be added with
+/// `AlignmentChopper::add_strategy` without touching how chunks are cut out of the CIGAR.
+pub trait ChopStrategy: Debug + ChopStrategyClone {
+    /// Prepare to split `rec`, e.g. resetting state kept between its chunks
+    fn start(&mut self, _rec: &Record) {}
+
+    /// Size of the next chunk given the size it would have otherwise, or None to leave it
+    fn next_size(&mut self, _size: u32) -> Option<u32> {
+        None
+    }
+
+    /// Reference positions strictly between `start` and `end` on contig `tid` to split at
+    fn breakpoints(&self, _tid: i32, _start: i64, _end: i64) -> Vec<i64> {
+        Vec::new()
+    }
+
+    /// Positions within the SEQ of `rec` to split at
+    fn query_cuts(&self, _rec: &Record) -> Vec<usize> {
+        Vec::new()
+    }
+
+    /// Whether to cut at the CIGAR operation `c`, leaving it out of the chunks on either side
+    fn cuts_at(&self, _c: &Cigar) -> bool {
+        false
+    }
+}
+
+/// Cloning of boxed strategies, implemented for every strategy that is Clone
+pub trait ChopStrategyClone {
+    fn clone_box(&self) -> Box<dyn ChopStrategy>;
+}
+
+impl<T: ChopStrategy + Clone + 'static> ChopStrategyClone for T {
+    fn clone_box(&self) -> Box<dyn ChopStrategy> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn ChopStrategy> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
+}
+
+fn seeded_rng(seed: Option<u64>) -> StdRng {
+    seed.map_or_else(StdRng::from_entropy, StdRng::seed_from_u64)
+}
+
+/// Splits records at fixed reference positions, by tid
+#[derive(Debug, Clone)]
+pub struct BreakpointList {
+    breakpoints: HashMap<i32, Vec<i64>>,
+}
+
+impl BreakpointList {
+    pub fn new(mut breakpoints: HashMap<i32, Vec<i64>>) -> Self {
+        for positions in breakpoints.values_mut() {
+            positions.sort_unstable();
+            positions.dedup();
+        }
+        Self { breakpoints }
+    }
+}
+
+impl ChopStrategy for BreakpointList {
+    fn breakpoints(&self, tid: i32, start: i64, end: i64) -> Vec<i64> {
+        match self.breakpoints.get(&tid) {
+            Some(breakpoints) => breakpoints[breakpoints.partition_point(|&bp| bp <= start)..breakpoints.partition_point(|&bp| bp < end)].to_vec(),
+            None => Vec::new(),
+        }
+    }
+}
+
+/// Cuts records at every reference skip (N)
+#[derive(Debug, Clone, Copy)]
+pub struct RefSkipSplit;
+
+impl ChopStrategy for RefSkipSplit {
+    fn cuts_at(&self, c: &Cigar) -> bool {
+        matches!(c, Cigar::RefSkip(_))
+    }
+}
+
+/// Cuts records at every insertion or deletion of at least `min_len` bases
+#[derive(Debug, Clone, Copy)]
+pub struct IndelSplit {
+    pub min_len: u32,
+}
+
+impl ChopStrategy for IndelSplit {
+    fn cuts_at(&self, c: &Cigar) -> bool {
+        matches!(c, Cigar::Ins(len) | Cigar::Del(len) if *len >= self.min_len)
+    }
+}
+
+/// Runs of at least `len` bases with mean base quality below `qual`, where records are cut
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LowQualityStretch {
+    pub qual: f64,
+    pub len: usize,
+}
+
+impl FromStr for LowQualityStretch {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(',').map(|(qual, len)| (qual.parse::<f64>(), len.parse::<usize>())) {
+            Some((Ok(qual), Ok(len))) if len > 0 => Ok(LowQualityStretch { qual, len }),
+            _ => Err(format!("Invalid low quality stretch: {} (expected QUAL,LEN)", s)),
+        }
+    }
+}
+
+impl LowQualityStretch {
+    // Query ranges covered by runs of `qual` this stretch describes, merging those that overlap or touch
+    fn find(&self, qual: &[u8]) -> Vec<(usize, usize)> {
+        let mut stretches: Vec<(usize, usize)> = Vec::new();
+        if qual.len() < self.len || qual.first() == Some(&255) {
+            return stretches;
+        }
+        let threshold = self.qual * self.len as f64;
+        let mut sum = qual[..self.len].iter().map(|&q| q as u64).sum::<u64>();
+        for start in 0..=qual.len() - self.len {
+            if start > 0 {
+                sum = sum + qual[start + self.len - 1] as u64 - qual[start - 1] as u64;
+            }
+            if (sum as f64) < threshold {
+                match stretches.last_mut() {
+                    Some(last) if last.1 >= start => last.1 = start + self.len,
+                    _ => stretches.push((start, start + self.len)),
+                }
+            }
+        }
+        stretches
+    }
+}
+
+impl ChopStrategy for LowQualityStretch {
+    fn query_cuts(&self, rec: &Record) -> Vec<usize> {
+        let qual = rec.qual();
+        self.find(&qual[..rec.seq_len().min(qual.len())]).into_iter().flat_map(|(start, end)| [start, end]).collect()
+    }
+}
+
+/// Cuts primary records where the alignments listed in their SA tag start and end, so each chunk
+/// comes from one linear segment of a chimeric read
+#[derive(Debug, Clone, Copy)]
+pub struct SupplementarySplit;
+
+// Clipped bases before, aligned bases within, and total query bases of an alignment with `cigar`
+fn clip_span(cigar: &[Cigar]) -> (usize, usize, usize) {
+    let clipped = |c: &&Cigar| matches!(c, Cigar::SoftClip(_) | Cigar::HardClip(_));
+    let leading = cigar.iter().take_while(clipped).map(|c| c.len() as usize).sum::<usize>();
+    let aligned = cigar.iter().filter(|c| matches!(c, Cigar::Match(_) | Cigar::Ins(_) | Cigar::Equal(_) | Cigar::Diff(_))).map(|c| c.len() as usize).sum::<usize>();
+    let total = aligned + cigar.iter().filter(clipped).map(|c| c.len() as usize).sum::<usize>();
+    (leading, aligned, total)
+}
+
+impl ChopStrategy for SupplementarySplit {
+    fn query_cuts(&self, rec: &Record) -> Vec<usize> {
+        let Ok(Aux::String(sa)) = rec.aux(b"SA") else {
+            return Vec::new();
+        };
+        if rec.is_supplementary() || rec.is_secondary() || rec.is_unmapped() {
+            return Vec::new();
+        }
+        let cigar = rec.cigar();
+        let (_, _, total) = clip_span(&cigar);
+        let hard_clipped = match cigar.first() {
+            Some(Cigar::HardClip(len)) => *len as usize,
+            _ => 0,
+        };
+        let mut cuts = Vec::new();
+        for alignment in sa.split(';').filter(|alignment| !alignment.is_empty()) {
+            let fields = alignment.split(',').collect::<Vec<_>>();
+            let (Some(strand), Some(Ok(sa_cigar))) = (fields.get(2), fields.get(3).map(|cigar| CigarString::try_from(*cigar))) else {
+                continue;
+            };
+            let (leading, aligned, sa_total) = clip_span(&sa_cigar);
+            if sa_total != total {
+                continue;
+            }
+            // Flip the span into the primary's orientation if it aligned to the other strand
+            let (start, end) = if (*strand == "-") == rec.is_reverse() { (leading, leading + aligned) } else { (total - leading - aligned, total - leading) };
+            cuts.extend([start, end].into_iter().filter_map(|cut| cut.checked_sub(hard_clipped)).filter(|&cut| 0 < cut && cut < rec.seq_len()));
+        }
+        cuts
+    }
+}
+
+/// Distribution to draw the size of each chunk from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChunkSizeDist {
+    /// Normal with this mean and standard deviation, rounded and at least 1
+    Normal(f64, f64),
+    /// Uniform between these sizes, inclusive
+    Uniform(u32, u32),
+}
+
+impl FromStr for ChunkSizeDist {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let unknown = || format!("Unknown chunk size distribution: {} (expected normal:MEAN,SD or uniform:MIN,MAX)", s);
+        let (kind, params) = s.split_once(':').ok_or_else(unknown)?;
+        let (a, b) = params.split_once(',').ok_or_else(unknown)?;
+        match kind {
+            "normal" => match (a.parse::<f64>(), b.parse::<f64>()) {
+                (Ok(mean), Ok(sd)) if mean >= 1.0 && sd >= 0.0 => Ok(ChunkSizeDist::Normal(mean, sd)),
+                _ => Err(format!("Invalid normal distribution parameters: {}", params)),
+            },
+            "uniform" => match (a.parse::<u32>(), b.parse::<u32>()) {
+                (Ok(min), Ok(max)) if 1 <= min && min <= max => Ok(ChunkSizeDist::Uniform(min, max)),
+                _ => Err(format!("Invalid uniform distribution parameters: {}", params)),
+            },
+            _ => Err(unknown()),
+        }
+    }
+}
+
+impl ChunkSizeDist {
+    pub fn mean(&self) -> f64 {
+        match self {
+            ChunkSizeDist::Normal(mean, _) => *mean,
+            ChunkSizeDist::Uniform(min, max) => (*min as f64 + *max as f64) / 2.0,
+        }
+    }
+
+    fn sample(&self, rng: &mut StdRng) -> u32 {
+        match self {
+            ChunkSizeDist::Normal(mean, sd) => {
+                // Box-Muller transform, keeping the first uniform away from 0
+                let (u1, u2) = (1.0 - rng.gen::<f64>(), rng.gen::<f64>());
+                let z = (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos();
+                (mean + sd * z).round().max(1.0) as u32
+            }
+            ChunkSizeDist::Uniform(min, max) => rng.gen_range(*min..=*max),
+        }
+    }
+}
+
+/// Draws the size of each chunk from a distribution, the same sizes every run given a seed
+#[derive(Debug, Clone)]
+pub struct RandomSize {
+    dist: ChunkSizeDist,
+    rng: StdRng,
+}
+
+impl RandomSize {
+    pub fn new(dist: ChunkSizeDist, seed: Option<u64>) -> Self {
+        Self { dist, rng: seeded_rng(seed) }
+    }
+}
+
+impl ChopStrategy for RandomSize {
+    fn next_size(&mut self, _size: u32) -> Option<u32> {
+        Some(self.dist.sample(&mut self.rng))
+    }
+}
+
+/// Shifts each boundary between chunks by up to `max_shift` bases either way, the same shifts every run given a seed
+#[derive(Debug, Clone)]
+pub struct Jitter {
+    max_shift: u32,
+    rng: StdRng,
+    // Shift of the boundary the current chunk starts at
+    shift: i64,
+}
+
+impl Jitter {
+    pub fn new(max_shift: u32, seed: Option<u64>) -> Self {
+        Self { max_shift, rng: seeded_rng(seed), shift: 0 }
+    }
+}
+
+impl ChopStrategy for Jitter {
+    fn start(&mut self, _rec: &Record) {
+        self.shift = 0;
+    }
+
+    fn next_size(&mut self, size: u32) -> Option<u32> {
+        // Shift the chunk's end, undoing the shift of its start carried over from the chunk before
+        let shift = self.rng.gen_range(-(self.max_shift as i64)..=self.max_shift as i64);
+        let size = size as i64 + shift - self.shift;
+        self.shift = shift;
+        Some(size.max(1) as u32)
+    }
+}
+
+/// Sizes chunks by the length of their read: each row gives the chunk size for reads of at least its length
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChunkSizeTable {
+    rows: Vec<(usize, u32)>,
+    // Chunk size for the current read
+    size: u32,
+}
+
+impl FromStr for ChunkSizeTable {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let rows = s.split(',').map(|row| match row.split_once(':').map(|(len, size)| (len.parse::<usize>(), size.parse::<u32>())) {
+            Some((Ok(len), Ok(size))) if size > 0 => Ok((len, size)),
+            _ => Err(format!("Invalid chunk size table row: {} (expected LEN:SIZE)", row)),
+        }).collect::<Result<Vec<_>, _>>()?;
+        if rows.first().map(|row| row.0) != Some(0) || rows.windows(2).any(|pair| pair[0].0 >= pair[1].0) {
+            return Err(format!("Chunk size table rows must start at length 0 and increase: {}", s));
+        }
+        Ok(ChunkSizeTable { size: rows[0].1, rows })
+    }
+}
+
+impl ChopStrategy for ChunkSizeTable {
+    fn start(&mut self, rec: &Record) {
+        let row = self.rows.partition_point(|&(len, _)| len <= rec.seq_len()) - 1;
+        self.size = self.rows[row].1;
+    }
+
+    fn next_size(&mut self, _size: u32) -> Option<u32> {
+        Some(self.size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn breakpoint_list_test() {
+        let strategy = BreakpointList::new(HashMap::from([(0, vec![300, 100, 200, 100])]));
+        assert_eq!(strategy.breakpoints(0, 100, 300), vec![200]);
+        assert_eq!(strategy.breakpoints(0, 0, 1000), vec![100, 200, 300]);
+        assert!(strategy.breakpoints(1, 0, 1000).is_empty());
+    }
+
+    #[test]
+    fn low_quality_stretch_test() {
+        let stretch = "50,3".parse::<LowQualityStretch>().unwrap();
+        assert_eq!(stretch, LowQualityStretch { qual: 50.0, len: 3 });
+        assert!("50".parse::<LowQualityStretch>().is_err());
+        assert!("50,0".parse::<LowQualityStretch>().is_err());
+
+        // Windows of 3 bases from 3 to 9 have mean quality below 50
+        assert_eq!(stretch.find(b"IIII!!!!IIII"), vec![(3, 9)]);
+        assert!(stretch.find(&[255; 12]).is_empty());
+    }
+
+    #[test]
+    fn chunk_size_dist_test() {
+        assert_eq!("normal:300,50".parse::<ChunkSizeDist>(), Ok(ChunkSizeDist::Normal(300.0, 50.0)));
+        assert_eq!("uniform:100,200".parse::<ChunkSizeDist>(), Ok(ChunkSizeDist::Uniform(100, 200)));
+        assert!("uniform:200,100".parse::<ChunkSizeDist>().is_err());
+        assert!("normal:300".parse::<ChunkSizeDist>().is_err());
+        assert!("poisson:3,1".parse::<ChunkSizeDist>().is_err());
+    }
+
+    #[test]
+    fn chunk_size_table_test() {
+        let mut table = "0:150,1000:500,10000:2000".parse::<ChunkSizeTable>().unwrap();
+        assert!("100:150".parse::<ChunkSizeTable>().is_err());
+        assert!("0:150,1000:500,1000:600".parse::<ChunkSizeTable>().is_err());
+        assert!("0:0".parse::<ChunkSizeTable>().is_err());
+
+        let mut rec = Record::new();
+        for (len, size) in [(999, 150), (1000, 500), (20000, 2000)] {
+            rec.set(b"read", None, &vec![b'A'; len], &vec![30; len]);
+            table.start(&rec);
+            assert_eq!(table.next_size(100), Some(size));
+        }
+    }
+
+    #[test]
+    fn supplementary_split_test() {
+        let mut rec = Record::new();
+        rec.set(b"read", Some(&CigarString::try_from("30M70S").unwrap()), &[b'A'; 100], &[30; 100]);
+        rec.push_aux(b"SA", Aux::String("chr2,500,+,30S40M30S,60,0;chr3,900,-,30S50M20H,60,1;")).unwrap();
+        // The second alignment covers bases 30 to 80 of the reverse complement, so 20 to 70 of the read
+        assert_eq!(SupplementarySplit.query_cuts(&rec), vec![30, 70, 20, 70]);
+
+        rec.set_supplementary();
+        assert!(SupplementarySplit.query_cuts(&rec).is_empty());
+    }
+}
\ No newline at end of file
@@ -0,0 +1,105 @@
+use rust_htslib::bam::record::Cigar;
+use rust_htslib::bam::{HeaderView, Record};
+
+// Largest POS (0-based) the SAM spec allows
+const MAX_POS: i64 = (1 << 31) - 2;
+const MAX_QNAME_LEN: usize = 254;
+
+/// Check `rec` against the constraints the SAM spec places on a record, returning the first one it breaks.
+///
+/// Covers the QNAME character set and length, the CIGAR (no empty operations, hard clips only at
+/// the ends, soft clips only next to them or to hard clips, and a query length matching SEQ), the
+/// placement of mapped records on a contig of `header` within its bounds, and QUAL's length.
+pub fn check_record(rec: &Record, header: &HeaderView) -> Result<(), String> {
+    let qname = rec.qname();
+    if qname.is_empty() || qname.len() > MAX_QNAME_LEN {
+        return Err(format!("QNAME must be 1 to {} characters, not {}", MAX_QNAME_LEN, qname.len()));
+    }
+    if let Some(c) = qname.iter().find(|&&c| !(b'!'..=b'~').contains(&c) || c == b'@') {
+        return Err(format!("QNAME contains {:?}, outside [!-?A-~]", *c as char));
+    }
+
+    let cigar = rec.cigar();
+    let ops = cigar.iter().collect::<Vec<_>>();
+    for (i, op) in ops.iter().enumerate() {
+        if op.is_empty() {
+            return Err(format!("CIGAR operation {} has length 0", op));
+        }
+        let at_end = i == 0 || i == ops.len() - 1;
+        match op {
+            Cigar::HardClip(_) if !at_end => return Err(format!("H must be the first or last CIGAR operation, found at {}", i)),
+            Cigar::SoftClip(_) => {
+                // Only hard clips may lie between a soft clip and the end
+                let clipped_before = ops[..i].iter().all(|op| matches!(op, Cigar::HardClip(_)));
+                let clipped_after = ops[i + 1..].iter().all(|op| matches!(op, Cigar::HardClip(_)));
+                if !clipped_before && !clipped_after {
+                    return Err(format!("S may only have H between it and the end of the CIGAR, found at {}", i));
+                }
+            }
+            _ => {}
+        }
+    }
+    if !ops.is_empty() {
+        if ops.iter().all(|op| matches!(op, Cigar::HardClip(_) | Cigar::SoftClip(_) | Cigar::Pad(_))) && !rec.is_unmapped() {
+            return Err(String::from("CIGAR of a mapped record aligns no bases"));
+        }
+        let query_len = cigar.iter().filter(|op| matches!(op, Cigar::Match(_) | Cigar::Ins(_) | Cigar::SoftClip(_) | Cigar::Equal(_) | Cigar::Diff(_))).map(|op| op.len() as usize).sum::<usize>();
+        if rec.seq_len() > 0 && query_len != rec.seq_len() {
+            return Err(format!("CIGAR query length {} differs from SEQ length {}", query_len, rec.seq_len()));
+        }
+    }
+
+    if !rec.is_unmapped() {
+        if rec.tid() < 0 || rec.tid() as u32 >= header.target_count() {
+            return Err(format!("Mapped record has RNAME index {} outside the header's {} contigs", rec.tid(), header.target_count()));
+        }
+        if ops.is_empty() {
+            return Err(String::from("Mapped record has no CIGAR"));
+        }
+        let contig_len = header.target_len(rec.tid() as u32).unwrap_or(0) as i64;
+        if rec.pos() < 0 || rec.pos() > MAX_POS || rec.pos() >= contig_len {
+            return Err(format!("POS {} lies outside contig {} of length {}", rec.pos() + 1, String::from_utf8_lossy(header.tid2name(rec.tid() as u32)), contig_len));
+        }
+    }
+    if rec.pos() < -1 || rec.pos() > MAX_POS {
+        return Err(format!("POS {} is out of range", rec.pos() + 1));
+    }
+
+    let qual = rec.qual();
+    if qual.first() != Some(&255) && qual.len() != rec.seq_len() {
+        return Err(format!("QUAL length {} differs from SEQ length {}", qual.len(), rec.seq_len()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_htslib::bam::record::CigarString;
+
+    #[test]
+    fn check_record_test() {
+        let header = HeaderView::from_bytes(b"@SQ\tSN:chr1\tLN:1000\n");
+        let record = |qname: &[u8], cigar: Vec<Cigar>, pos: i64| {
+            let mut rec = Record::new();
+            rec.set(qname, Some(&CigarString(cigar)), b"ACGTACGT", b"IIIIIIII");
+            rec.set_tid(0);
+            rec.set_pos(pos);
+            rec
+        };
+        assert_eq!(check_record(&record(b"r1-0", vec![Cigar::HardClip(3), Cigar::SoftClip(2), Cigar::Match(6)], 10), &header), Ok(()));
+
+        assert!(check_record(&record(b"r1 0", vec![Cigar::Match(8)], 10), &header).unwrap_err().contains("QNAME"));
+        assert!(check_record(&record(b"@r1", vec![Cigar::Match(8)], 10), &header).unwrap_err().contains("QNAME"));
+        assert!(check_record(&record(b"r1", vec![Cigar::Match(4), Cigar::HardClip(2), Cigar::Match(4)], 10), &header).unwrap_err().contains("H must"));
+        assert!(check_record(&record(b"r1", vec![Cigar::Match(4), Cigar::SoftClip(2), Cigar::Match(2)], 10), &header).unwrap_err().contains("S may"));
+        assert!(check_record(&record(b"r1", vec![Cigar::Match(4), Cigar::Del(0), Cigar::Match(4)], 10), &header).unwrap_err().contains("length 0"));
+        assert!(check_record(&record(b"r1", vec![Cigar::SoftClip(8)], 10), &header).unwrap_err().contains("aligns no bases"));
+        assert!(check_record(&record(b"r1", vec![Cigar::Match(7)], 10), &header).unwrap_err().contains("SEQ length"));
+        assert!(check_record(&record(b"r1", vec![Cigar::Match(8)], 1000), &header).unwrap_err().contains("outside contig"));
+
+        let mut unplaced = record(b"r1", vec![Cigar::Match(8)], 10);
+        unplaced.set_tid(1);
+        assert!(check_record(&unplaced, &header).unwrap_err().contains("RNAME"));
+    }
+}
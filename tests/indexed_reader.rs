@@ -0,0 +1,93 @@
+//! Chopping records that come from an IndexedReader, through fetch and pileup loops, as a library user would.
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use rust_htslib::bam::{self, Format, Header, HeaderView, Read, Record};
+use chop_reads::alignment_chopper::{AlignmentChopper, ClipPolicy};
+
+const HEADER: &str = "@HD\tVN:1.6\tSO:coordinate\n@SQ\tSN:chr1\tLN:1000\n@SQ\tSN:chr2\tLN:100000\n";
+
+const RECORDS: [&str; 3] = [
+    "a\t0\tchr1\t101\t60\t10M\t*\t0\t0\tACGTACGTAC\tIIIIIIIIII",
+    "b\t0\tchr2\t16380\t60\t4M2D6M\t*\t0\t0\tACGTACGTAC\tIIIIIIIIII",
+    "c\t16\tchr2\t16385\t30\t2S8M\t*\t0\t0\tACGTACGTAC\tIIIIIIIIII",
+];
+
+// Write RECORDS to an indexed BAM in `dir`
+fn write_indexed_bam(dir: &Path) -> PathBuf {
+    let header_view = HeaderView::from_bytes(HEADER.as_bytes());
+    let path = dir.join("in.bam");
+    let mut writer = bam::Writer::from_path(&path, &Header::from_template(&header_view), Format::Bam).unwrap();
+    for line in RECORDS {
+        writer.write(&Record::from_sam(&header_view, line.as_bytes()).unwrap()).unwrap();
+    }
+    drop(writer);
+    bam::index::build(&path, None, bam::index::Type::Bai, 1).unwrap();
+    path
+}
+
+// Contig, position, CIGAR and bin of each chunk, with the bin htslib gives the same alignment parsed from SAM
+fn describe(chunk: &Record, header_view: &HeaderView) -> (String, i64, String, u16, u16) {
+    let contig = String::from_utf8(header_view.tid2name(chunk.tid() as u32).to_vec()).unwrap();
+    let line = format!("x\t0\t{}\t{}\t60\t{}\t*\t0\t0\t*\t*", contig, chunk.pos() + 1, chunk.cigar());
+    let parsed = Record::from_sam(header_view, line.as_bytes()).unwrap();
+    (contig, chunk.pos(), chunk.cigar().to_string(), chunk.bin(), parsed.bin())
+}
+
+#[test]
+fn fetch_test() {
+    let dir = std::env::temp_dir().join(format!("chop_reads_indexed_fetch_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = write_indexed_bam(&dir);
+
+    let mut reader = bam::IndexedReader::from_path(&path).unwrap();
+    let header_view = reader.header().clone();
+    let mut chopper = AlignmentChopper::new(5, 0, ClipPolicy::Attach, None);
+    chopper.set_header(Some(Rc::new(header_view.clone())));
+
+    reader.fetch("chr2").unwrap();
+    let mut chunks = Vec::new();
+    for r in reader.records() {
+        chunks.extend(chopper.chop_read(&r.unwrap()).iter().map(|chunk| describe(chunk, &header_view)));
+    }
+    // Chunk b-0 straddles a 16kb boundary so it falls in the enclosing 128kb bin
+    assert_eq!(chunks.iter().map(|(contig, pos, cigar, bin, _)| (contig.as_str(), *pos, cigar.as_str(), *bin)).collect::<Vec<_>>(), vec![
+        ("chr2", 16379, "4M2D1M", 585),
+        ("chr2", 16386, "5M", 4682),
+        ("chr2", 16384, "2S5M", 4682),
+        ("chr2", 16389, "3M", 4682),
+    ]);
+    assert!(chunks.iter().all(|(_, _, _, bin, parsed_bin)| bin == parsed_bin));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn pileup_test() {
+    let dir = std::env::temp_dir().join(format!("chop_reads_indexed_pileup_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = write_indexed_bam(&dir);
+
+    let mut reader = bam::IndexedReader::from_path(&path).unwrap();
+    let header_view = reader.header().clone();
+    let mut chopper = AlignmentChopper::new(5, 0, ClipPolicy::Attach, None);
+    chopper.set_header(Some(Rc::new(header_view.clone())));
+
+    // Pileup alignments borrow their records from htslib, so chop each read the first time it turns up
+    reader.fetch(("chr2", 16384, 16390)).unwrap();
+    let mut seen = HashSet::new();
+    let mut chunks = Vec::new();
+    for pileup in reader.pileup() {
+        for alignment in pileup.unwrap().alignments() {
+            let rec = alignment.record();
+            if seen.insert(rec.qname().to_vec()) {
+                chunks.extend(chopper.chop_read(&rec).iter().map(|chunk| (String::from_utf8(chunk.qname().to_vec()).unwrap(), describe(chunk, &header_view))));
+            }
+        }
+    }
+    assert_eq!(chunks.iter().map(|(qname, _)| qname.as_str()).collect::<Vec<_>>(), vec!["b-0", "b-1", "c-1", "c-2"]);
+    assert!(chunks.iter().all(|(_, (contig, _, _, bin, parsed_bin))| contig == "chr2" && bin == parsed_bin));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,131 @@
+//! Regression cases for the chopper written as SAM text.
+//!
+//! Each fixture is a few SAM records against `HEADER` and the SAM body the chopper is expected to
+//! produce from them, so new cases can be added by pasting the offending read and the output you
+//! expect rather than building `Record`s by hand.
+
+use std::fs;
+use rust_htslib::bam::{self, Format, Header, HeaderView, Record};
+use chop_reads::alignment_chopper::{AlignmentChopper, ClipPolicy};
+
+const HEADER: &str = "@HD\tVN:1.6\tSO:unsorted\n@SQ\tSN:chr1\tLN:1000\n";
+
+// Chop each fixture record and render the output records as SAM text, without the header
+fn chop_sam(name: &str, input: &str, mut chopper: AlignmentChopper) -> String {
+    let header_view = HeaderView::from_bytes(HEADER.as_bytes());
+    let path = std::env::temp_dir().join(format!("chop_reads_{}_{}.sam", name, std::process::id()));
+
+    let mut writer = bam::Writer::from_path(&path, &Header::from_template(&header_view), Format::Sam).unwrap();
+    for line in input.lines() {
+        let rec = Record::from_sam(&header_view, line.as_bytes()).unwrap();
+        for chunk in chopper.chop_read(&rec) {
+            writer.write(chunk).unwrap();
+        }
+    }
+    drop(writer);
+
+    let text = fs::read_to_string(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    text.lines().filter(|line| !line.starts_with('@')).map(|line| format!("{}\n", line)).collect()
+}
+
+fn sam(lines: &[&str]) -> String {
+    lines.iter().map(|line| format!("{}\n", line.split_whitespace().collect::<Vec<_>>().join("\t"))).collect()
+}
+
+#[test]
+fn matches_test() {
+    let input = sam(&["r1 0 chr1 101 60 10M * 0 0 ACGTACGTAC IIIIIIIIII"]);
+    assert_eq!(chop_sam("matches", &input, AlignmentChopper::new(4, 1, ClipPolicy::Attach, None)), sam(&[
+        "r1-0 0 chr1 101 60 4M * 0 0 ACGT IIII",
+        "r1-1 0 chr1 105 60 4M * 0 0 ACGT IIII",
+        "r1-2 0 chr1 109 60 2M * 0 0 AC II",
+    ]));
+
+    // A read filling its last chunk exactly leaves no empty chunk behind, even without a min length
+    let input = sam(&["even 0 chr1 101 60 4M4M * 0 0 ACGTACGT IIIIIIII"]);
+    assert_eq!(chop_sam("matches_even", &input, AlignmentChopper::new(4, 0, ClipPolicy::Attach, None)), sam(&[
+        "even-0 0 chr1 101 60 4M * 0 0 ACGT IIII",
+        "even-1 0 chr1 105 60 4M * 0 0 ACGT IIII",
+    ]));
+
+    // Nor a zero-length op when the chunk filled by splitting one op ends right before the next
+    assert_eq!(chop_sam("matches_split", &input, AlignmentChopper::new(2, 0, ClipPolicy::Attach, None)), sam(&[
+        "even-0 0 chr1 101 60 2M * 0 0 AC II",
+        "even-1 0 chr1 103 60 2M * 0 0 GT II",
+        "even-2 0 chr1 105 60 2M * 0 0 AC II",
+        "even-3 0 chr1 107 60 2M * 0 0 GT II",
+    ]));
+}
+
+#[test]
+fn indels_at_chunk_boundary_test() {
+    // Ref-only operations at a cut stay on the right-hand chunk; an insertion is split like any
+    // other query-consuming operation
+    let input = sam(&[
+        "del 0 chr1 101 60 4M2D4M * 0 0 ACGTACGT IIIIIIII",
+        "ins 0 chr1 101 60 3M2I3M * 0 0 ACGTACGT IIIIIIII",
+        "splice 0 chr1 101 60 4M100N4M * 0 0 ACGTACGT IIIIIIII",
+    ]);
+    assert_eq!(chop_sam("indels", &input, AlignmentChopper::new(4, 1, ClipPolicy::Attach, None)), sam(&[
+        "del-0 0 chr1 101 60 4M * 0 0 ACGT IIII",
+        "del-1 0 chr1 105 60 2D4M * 0 0 ACGT IIII",
+        "ins-0 0 chr1 101 60 3M1I * 0 0 ACGT IIII",
+        "ins-1 0 chr1 104 60 1I3M * 0 0 ACGT IIII",
+        "splice-0 0 chr1 101 60 4M * 0 0 ACGT IIII",
+        "splice-1 0 chr1 105 60 100N4M * 0 0 ACGT IIII",
+    ]));
+}
+
+#[test]
+fn clips_test() {
+    let input = sam(&[
+        "soft 0 chr1 101 60 3S6M2S * 0 0 ACGTACGTACG IIIIIIIIIII",
+        "hard 16 chr1 101 60 5H8M * 0 0 ACGTACGT IIIIIIII",
+    ]);
+    // The leading soft clip keeps chunk number 0 even when attached
+    assert_eq!(chop_sam("clips", &input, AlignmentChopper::new(4, 1, ClipPolicy::Attach, None)), sam(&[
+        "soft-1 0 chr1 101 60 3S4M * 0 0 ACGTACG IIIIIII",
+        "soft-2 0 chr1 105 60 2M2S * 0 0 TACG IIII",
+        "hard-0 16 chr1 101 60 5H4M * 0 0 ACGT IIII",
+        "hard-1 16 chr1 105 60 4M * 0 0 ACGT IIII",
+    ]));
+}
+
+#[test]
+fn skip_clipped_bases_with_read_group_test() {
+    let input = sam(&["soft 0 chr1 101 60 3S6M2S * 0 0 ACGTACGTACG IIIIIIIIIII"]);
+    assert_eq!(chop_sam("skip_clipped", &input, AlignmentChopper::new(4, 2, ClipPolicy::Drop, Some(String::from("grp")))), sam(&[
+        "soft-1 0 chr1 101 60 4M * 0 0 TACG IIII RG:Z:grp",
+        "soft-2 0 chr1 105 60 2M * 0 0 TA II RG:Z:grp",
+    ]));
+}
+
+#[test]
+fn hard_clips_test() {
+    // Hard clips stay on the first and last chunks, even when the last chunk is filled exactly
+    let input = sam(&[
+        "uneven 0 chr1 101 60 5H6M3H * 0 0 ACGTAC IIIIII",
+        "even 0 chr1 101 60 5H4M4M3H * 0 0 ACGTACGT IIIIIIII",
+        "clipped 0 chr1 101 60 5H2M2S3H * 0 0 ACGT IIII",
+    ]);
+    assert_eq!(chop_sam("hard_clips", &input, AlignmentChopper::new(4, 1, ClipPolicy::Attach, None)), sam(&[
+        "uneven-0 0 chr1 101 60 5H4M * 0 0 ACGT IIII",
+        "uneven-1 0 chr1 105 60 2M3H * 0 0 AC II",
+        "even-0 0 chr1 101 60 5H4M * 0 0 ACGT IIII",
+        "even-1 0 chr1 105 60 4M3H * 0 0 ACGT IIII",
+        "clipped-0 0 chr1 101 60 5H2M2S3H * 0 0 ACGT IIII",
+    ]));
+}
+
+#[test]
+fn long_cigar_test() {
+    // A CIGAR too long for BAM is kept in CG behind a kSmN placeholder, which SAM readers leave in place
+    let input = sam(&["long 0 chr1 101 60 10S8N * 0 0 ACGTACGTAC IIIIIIIIII CG:B:I,64,33,64 AS:i:9"]);
+    let mut chopper = AlignmentChopper::new(5, 1, ClipPolicy::Attach, None);
+    chopper.set_keep_tags(true);
+    assert_eq!(chop_sam("long_cigar", &input, chopper), sam(&[
+        "long-0 0 chr1 101 60 4M1I * 0 0 ACGTA IIIII AS:i:9",
+        "long-1 0 chr1 105 60 1I4M * 0 0 CGTAC IIIII AS:i:9",
+    ]));
+}
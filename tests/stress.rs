@@ -0,0 +1,131 @@
+//! Large-file stress test, ignored by default since it writes and chops a ~1M record BAM:
+//!
+//!     cargo test --release --test stress -- --ignored
+//!
+//! Set STRESS_RECORDS to change the number of generated records.
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use rust_htslib::bam::{self, Format, Header, HeaderView, Read, Record};
+use rust_htslib::bam::header::HeaderRecord;
+use rust_htslib::bam::record::{Cigar, CigarString};
+
+const CONTIG_LEN: i64 = 250_000_000;
+
+// Small xorshift generator so runs are reproducible without pulling in a RNG crate
+struct Rng(u64);
+
+impl Rng {
+    fn below(&mut self, n: u32) -> u32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % n as u64) as u32
+    }
+}
+
+fn random_cigar(rng: &mut Rng) -> CigarString {
+    let ops = [
+        Cigar::SoftClip(rng.below(30)),
+        Cigar::Match(20 + rng.below(300)),
+        Cigar::Ins(rng.below(6)),
+        Cigar::Match(1 + rng.below(100)),
+        Cigar::Del(rng.below(6)),
+        Cigar::Match(1 + rng.below(100)),
+        Cigar::SoftClip(rng.below(30)),
+    ];
+    CigarString(ops.into_iter().filter(|c| !c.is_empty()).collect())
+}
+
+fn query_len(cigar: &CigarString, skip_clipped_bases: bool) -> u32 {
+    cigar.iter().filter(|c| match c {
+        Cigar::SoftClip(_) => !skip_clipped_bases,
+        Cigar::Match(_) | Cigar::Ins(_) => true,
+        _ => false,
+    }).map(|c| c.len()).sum()
+}
+
+// Write `n` random records to `path`, returning their CIGARs
+fn generate_bam(path: &Path, n: usize) -> Vec<CigarString> {
+    let mut header = Header::new();
+    header.push_record(HeaderRecord::new(b"SQ").push_tag(b"SN", "chr1").push_tag(b"LN", CONTIG_LEN));
+    let mut writer = bam::Writer::from_path(path, &header, Format::Bam).unwrap();
+
+    let mut rng = Rng(0x5eed);
+    let mut cigars = Vec::with_capacity(n);
+    let mut pos = 0;
+    for i in 0..n {
+        let cigar = random_cigar(&mut rng);
+        let len = query_len(&cigar, false) as usize;
+        let seq = (0..len).map(|_| b"ACGT"[rng.below(4) as usize]).collect::<Vec<_>>();
+        let qual = (0..len).map(|_| 2 + rng.below(40) as u8).collect::<Vec<_>>();
+
+        let mut rec = Record::new();
+        rec.set(format!("read{}", i).as_bytes(), Some(&cigar), &seq, &qual);
+        rec.set_tid(0);
+        pos = (pos + rng.below(200) as i64) % (CONTIG_LEN - 10_000);
+        rec.set_pos(pos);
+        rec.set_mapq(60);
+        rec.set_mtid(-1);
+        rec.set_mpos(-1);
+        writer.write(&rec).unwrap();
+        cigars.push(cigar);
+    }
+    cigars
+}
+
+fn expected_chunks(cigars: &[CigarString], chunk_size: u32, min_length: u32, skip_clipped_bases: bool) -> usize {
+    cigars.iter().map(|cigar| {
+        let len = query_len(cigar, skip_clipped_bases);
+        let remainder = len % chunk_size;
+        (len / chunk_size) as usize + (remainder > 0 && remainder >= min_length) as usize
+    }).sum()
+}
+
+// Read the output back, checking every record is well formed, and return the record count
+fn validate_output(path: &Path) -> usize {
+    let mut reader = bam::Reader::from_path(path).unwrap();
+    let header = HeaderView::from_header(&Header::from_template(reader.header()));
+    let mut count = 0;
+    for r in reader.records() {
+        let rec = r.expect("Output record failed to parse");
+        let cigar = rec.cigar().take();
+        assert_eq!(query_len(&cigar, false) as usize, rec.seq_len(), "CIGAR and SEQ disagree for {}", String::from_utf8_lossy(rec.qname()));
+        assert!(cigar.iter().all(|c| !c.is_empty()), "Zero-length CIGAR op in {}", String::from_utf8_lossy(rec.qname()));
+        assert!(rec.pos() >= 0 && rec.cigar().end_pos() <= header.target_len(0).unwrap() as i64);
+        count += 1;
+    }
+
+    // Defer to samtools as well when it's available
+    if let Ok(status) = Command::new("samtools").arg("quickcheck").arg(path).status() {
+        assert!(status.success(), "samtools quickcheck failed on {}", path.display());
+    }
+    count
+}
+
+#[test]
+#[ignore]
+fn large_file_stress_test() {
+    let n = std::env::var("STRESS_RECORDS").ok().and_then(|n| n.parse().ok()).unwrap_or(1_000_000);
+    let dir = std::env::temp_dir().join(format!("chop_reads_stress_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("input.bam");
+    let cigars = generate_bam(&input, n);
+
+    for (chunk_size, min_length, skip_clipped_bases) in [(100, 0, false), (37, 20, true), (1000, 1, false)] {
+        let output: PathBuf = dir.join(format!("chopped_{}_{}_{}.bam", chunk_size, min_length, skip_clipped_bases));
+        let mut command = Command::new(env!("CARGO_BIN_EXE_chop-reads"));
+        command.arg("-i").arg(&input).arg("-o").arg(&output)
+            .arg("-s").arg(chunk_size.to_string())
+            .arg("--min-length").arg(min_length.to_string())
+            .arg("-@").arg("4");
+        if skip_clipped_bases {
+            command.arg("--skip-clipped-bases");
+        }
+        assert!(command.status().unwrap().success());
+
+        assert_eq!(validate_output(&output), expected_chunks(&cigars, chunk_size, min_length, skip_clipped_bases));
+    }
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}